@@ -1,20 +1,48 @@
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
+use std::collections::HashMap;
 
 use crate::value::{Value, Values};
 
-#[derive(FromPrimitive)]
-#[repr(u8)]
-pub enum Opcode {
+macro_rules! opcodes {
+	($($name:ident,)*) => {
+		#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+		#[repr(u8)]
+		pub enum Opcode {
+			$($name,)*
+		}
+
+		impl TryFrom<u8> for Opcode {
+			type Error = ();
+
+			#[inline(always)]
+			fn try_from(value: u8) -> Result<Self, Self::Error> {
+				#[allow(non_upper_case_globals)]
+				mod byte {
+					$(pub const $name: u8 = super::Opcode::$name as u8;)*
+				}
+				match value {
+					$(byte::$name => Ok(Opcode::$name),)*
+					_ => Err(()),
+				}
+			}
+		}
+	};
+}
+
+opcodes! {
 	Constant,
 	Nil,
 	True,
 	False,
+	Uninitialized,
 	Pop,
+	Tuck,
 	GetLocal,
+	GetLocalLong,
+	CheckInitialized,
 	GetGlobal,
 	DefineGlobal,
 	SetLocal,
+	SetLocalLong,
 	SetGlobal,
 	Equal,
 	Greater,
@@ -23,37 +51,300 @@ pub enum Opcode {
 	Subtract,
 	Multiply,
 	Divide,
+	Modulo,
+	BitAnd,
+	BitOr,
+	BitXor,
+	ShiftLeft,
+	ShiftRight,
 	Not,
 	Negate,
+	BitNot,
 	Print,
 	Jump,
+	JumpLong,
 	JumpIfFalse,
+	JumpIfFalseLong,
 	Loop,
+	LoopLong,
 	Return,
 }
 
-impl TryFrom<u8> for Opcode {
-	type Error = ();
+impl Opcode {
+	/// How many operand bytes follow this opcode.
+	pub fn operand_len(self) -> usize {
+		match self {
+			Opcode::Constant
+			| Opcode::GetLocal
+			| Opcode::CheckInitialized
+			| Opcode::GetGlobal
+			| Opcode::DefineGlobal
+			| Opcode::SetLocal
+			| Opcode::SetGlobal => 1,
+			Opcode::GetLocalLong
+			| Opcode::SetLocalLong
+			| Opcode::Jump
+			| Opcode::JumpIfFalse
+			| Opcode::Loop => 2,
+			Opcode::JumpLong | Opcode::JumpIfFalseLong | Opcode::LoopLong => 4,
+			_ => 0,
+		}
+	}
 
-	fn try_from(value: u8) -> Result<Self, Self::Error> {
-		match Opcode::from_u8(value) {
-			Some(opcode) => Ok(opcode),
-			None => Err(()),
+	/// The 32-bit offset version of a jump with a 16-bit offset.
+	fn long_form(self) -> Self {
+		match self {
+			Opcode::Jump => Opcode::JumpLong,
+			Opcode::JumpIfFalse => Opcode::JumpIfFalseLong,
+			Opcode::Loop => Opcode::LoopLong,
+			_ => self,
 		}
 	}
+
+	fn is_long_jump(self) -> bool {
+		matches!(
+			self,
+			Opcode::JumpLong | Opcode::JumpIfFalseLong | Opcode::LoopLong
+		)
+	}
+
+	/// Decodes a byte the compiler emitted as an opcode.
+	#[inline(always)]
+	pub fn decode(byte: u8) -> Option<Self> {
+		Self::try_from(byte).ok()
+	}
+}
+
+/// The stretch of source an instruction was compiled from: `length`
+/// characters starting at `column` of `line`, both counted from 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+	pub line: usize,
+	pub column: usize,
+	pub length: usize,
+}
+
+impl Span {
+	/// The span from the start of `self` to the end of `end`. Spans only
+	/// cover one line, so if `end` is on a later line this is `self`.
+	pub fn to(self, end: Span) -> Span {
+		if end.line != self.line || end.column < self.column {
+			return self;
+		}
+		Span {
+			length: end.column + end.length - self.column,
+			..self
+		}
+	}
+}
+
+/// `count` consecutive bytes of code that all came from `span`.
+struct SpanRun {
+	span: Span,
+	count: usize,
 }
 
 #[derive(Default)]
 pub struct Chunk {
 	code: Vec<u8>,
-	lines: Vec<usize>,
+	spans: Vec<SpanRun>,
 	constants: Values,
 }
 
 impl Chunk {
-	pub fn write(&mut self, byte: u8, line: usize) {
+	/// An empty chunk that takes over `constants`.
+	pub fn with_constants(constants: Values) -> Self {
+		Self {
+			constants,
+			..Default::default()
+		}
+	}
+
+	pub fn write(&mut self, byte: u8, span: Span) {
 		self.code.push(byte);
-		self.lines.push(line);
+		match self.spans.last_mut() {
+			Some(run) if run.span == span => run.count += 1,
+			_ => self.spans.push(SpanRun { span, count: 1 }),
+		}
+	}
+
+	/// The source span of the byte at `offset`.
+	pub fn span_at(&self, offset: usize) -> Span {
+		let mut end = 0;
+		for run in &self.spans {
+			end += run.count;
+			if offset < end {
+				return run.span;
+			}
+		}
+		panic!("offset {offset} is past the end of the chunk")
+	}
+
+	/// The source line of the byte at `offset`.
+	pub fn line_at(&self, offset: usize) -> usize {
+		self.span_at(offset).line
+	}
+
+	/// The source span of each byte of code, in order.
+	pub fn spans(&self) -> impl Iterator<Item = Span> + '_ {
+		self.spans
+			.iter()
+			.flat_map(|run| std::iter::repeat_n(run.span, run.count))
+	}
+
+	/// Drops the code from `len` onwards, along with its source spans.
+	pub fn truncate(&mut self, len: usize) {
+		let mut excess = self.code.len() - len;
+		self.code.truncate(len);
+		while excess > 0 {
+			let run = self.spans.last_mut().expect("span runs cover all code");
+			if run.count > excess {
+				run.count -= excess;
+				excess = 0;
+			} else {
+				excess -= run.count;
+				self.spans.pop();
+			}
+		}
+	}
+
+	/// Re-encodes the chunk so that every jump can reach its target,
+	/// switching to the 32-bit forms where 16 bits are not enough.
+	///
+	/// `far` maps the offset of each forward jump whose distance did not fit
+	/// its operand to the offset it should land on. Widening a jump moves
+	/// the code after it, so any other jump across it is re-encoded too, and
+	/// may itself need widening.
+	pub fn widen_jumps(&mut self, far: &HashMap<usize, usize>) {
+		struct Instruction {
+			offset: usize,
+			opcode: Opcode,
+			/// For jumps, the index of the instruction landed on.
+			target: Option<usize>,
+		}
+
+		let mut instructions = Vec::new();
+		let mut offset = 0;
+		while offset < self.code.len() {
+			let opcode = Opcode::try_from(self.code[offset]).expect("compiler emitted an opcode");
+			instructions.push((offset, opcode));
+			offset += 1 + opcode.operand_len();
+		}
+		let index_of = |target: usize| match instructions
+			.binary_search_by_key(&target, |&(offset, _)| offset)
+		{
+			Ok(index) => index,
+			// Only the end of the chunk lies past every instruction.
+			Err(index) => index,
+		};
+		let mut instructions = instructions
+			.iter()
+			.map(|&(offset, opcode)| {
+				let operand = &self.code[offset + 1..offset + 1 + opcode.operand_len()];
+				let next = offset + 1 + operand.len();
+				let distance = match *operand {
+					[high, low] => u16::from_be_bytes([high, low]) as usize,
+					[a, b, c, d] => u32::from_be_bytes([a, b, c, d]) as usize,
+					_ => 0,
+				};
+				let target = match opcode {
+					Opcode::Jump | Opcode::JumpIfFalse if far.contains_key(&offset) => {
+						Some(far[&offset])
+					}
+					Opcode::Jump
+					| Opcode::JumpIfFalse
+					| Opcode::JumpLong
+					| Opcode::JumpIfFalseLong => Some(next + distance),
+					Opcode::Loop | Opcode::LoopLong => Some(next - distance),
+					_ => None,
+				};
+				let opcode = if far.contains_key(&offset) {
+					opcode.long_form()
+				} else {
+					opcode
+				};
+				Instruction {
+					offset,
+					opcode,
+					target: target.map(index_of),
+				}
+			})
+			.collect::<Vec<_>>();
+
+		// Lay the code out again until every short jump still fits.
+		let mut offsets = Vec::with_capacity(instructions.len() + 1);
+		loop {
+			offsets.clear();
+			let mut offset = 0;
+			for instruction in &instructions {
+				offsets.push(offset);
+				offset += 1 + instruction.opcode.operand_len();
+			}
+			offsets.push(offset);
+
+			let mut widened = false;
+			for (i, instruction) in instructions.iter_mut().enumerate() {
+				let Some(target) = instruction.target else {
+					continue;
+				};
+				let next = offsets[i + 1];
+				if !instruction.opcode.is_long_jump()
+					&& next.abs_diff(offsets[target]) > u16::MAX as usize
+				{
+					instruction.opcode = instruction.opcode.long_form();
+					widened = true;
+				}
+			}
+			if !widened {
+				break;
+			}
+		}
+
+		let spans = self.spans().collect::<Vec<_>>();
+		let mut widened = Chunk::with_constants(self.take_constants());
+		for (i, instruction) in instructions.iter().enumerate() {
+			let old = instruction.offset;
+			let mut bytes = vec![instruction.opcode as u8];
+			match instruction.target {
+				Some(target) => {
+					let distance = offsets[i + 1].abs_diff(offsets[target]);
+					if instruction.opcode.is_long_jump() {
+						bytes.extend_from_slice(&(distance as u32).to_be_bytes());
+					} else {
+						bytes.extend_from_slice(&(distance as u16).to_be_bytes());
+					}
+				}
+				None => bytes.extend_from_slice(
+					&self.code[old + 1..old + 1 + instruction.opcode.operand_len()],
+				),
+			}
+			for byte in bytes {
+				widened.write(byte, spans[old]);
+			}
+		}
+		*self = widened;
+	}
+
+	/// Where the jump at `offset` lands, or `None` if that instruction is
+	/// not a jump.
+	pub fn jump_target(&self, offset: usize) -> Option<usize> {
+		let opcode = Opcode::try_from(self.code[offset]).ok()?;
+		let operand = self
+			.code
+			.get(offset + 1..offset + 1 + opcode.operand_len())?;
+		let next = offset + 1 + operand.len();
+		let distance = match *operand {
+			[high, low] => u16::from_be_bytes([high, low]) as usize,
+			[a, b, c, d] => u32::from_be_bytes([a, b, c, d]) as usize,
+			_ => 0,
+		};
+		match opcode {
+			Opcode::Jump | Opcode::JumpIfFalse | Opcode::JumpLong | Opcode::JumpIfFalseLong => {
+				Some(next + distance)
+			}
+			Opcode::Loop | Opcode::LoopLong => next.checked_sub(distance),
+			_ => None,
+		}
 	}
 
 	pub fn add_constant(&mut self, value: impl Into<Value>) -> usize {
@@ -66,7 +357,7 @@ impl Chunk {
 		self.code.as_ref()
 	}
 
-	#[inline ]
+	#[inline]
 	pub fn code_mut(&mut self) -> &mut [u8] {
 		self.code.as_mut()
 	}
@@ -76,9 +367,9 @@ impl Chunk {
 		&self.constants
 	}
 
-	#[inline]
-	pub fn lines(&self) -> &[usize] {
-		self.lines.as_ref()
+	/// Moves the constants out, leaving the chunk with none.
+	pub fn take_constants(&mut self) -> Values {
+		std::mem::take(&mut self.constants)
 	}
 
 	#[inline]
@@ -1,42 +1,87 @@
+use std::collections::{HashMap, HashSet};
+
+use rlox_common::number::integer_literal;
+
 use crate::{
-	chunk::{Chunk, Opcode},
+	chunk::{Chunk, Opcode, Span},
 	debug,
 	scanner::token::Ty,
+	source::Source,
 	value::Value,
-	vm::VM,
+	vm::{self, VM},
 };
 
 mod parser;
 
 use self::parser::Parser;
 
+/// How deeply expressions and statements may nest before compiling them
+/// would overflow the stack.
+const MAX_NESTING: usize = 1024;
+
 #[derive(Default)]
 struct Compiler<'a> {
 	locals: Vec<Local<'a>>,
-	scope_depth: u8,
+	scope_depth: usize,
 }
 
 struct Local<'a> {
 	name: &'a str,
-	depth: Option<u8>,
+	depth: Option<usize>,
+	/// Declared without an initializer, so reads must check that it has
+	/// been assigned. Locals that start with a value never need the check.
+	uninitialized: bool,
+	constant: Option<Const>,
+}
+
+/// A variable declared with `const`, which can never be assigned.
+#[derive(Clone)]
+struct Const {
+	/// The code that pushed its initializer, if that was a single literal.
+	/// Reads emit it again instead of loading the variable.
+	inline: Option<Vec<u8>>,
 }
 
 pub struct Compilation<'a> {
 	parser: Parser<'a>,
 	current: Compiler<'a>,
 	compiling_chunk: Chunk,
+	/// Forward jumps whose distance overflowed 16 bits, by offset, with the
+	/// offset they should land on. They are widened once the chunk is done.
+	far_jumps: HashMap<usize, usize>,
+	/// The globals declared at the top level of this source so far, with
+	/// the `Const` of those declared with `const`. The constants of earlier
+	/// sources are in the VM.
+	global_constants: HashMap<&'a str, Option<Const>>,
+	/// Where the left operand of the infix operator being compiled starts,
+	/// so the operator's instructions can point at the whole expression.
+	operand_start: Span,
+	/// How many expressions and statements the compiler is inside.
+	nesting: usize,
+	/// The first declaration of each global, in source order, and every
+	/// global used, for `--warn-unused`.
+	global_declarations: Vec<(&'a str, Span)>,
+	used_globals: HashSet<&'a str>,
+	source: &'a Source,
 	vm: &'a mut VM,
 }
 
 impl<'a> Compilation<'a> {
-	pub fn new(vm: &'a mut VM, source: &'a str) -> Self {
-		let parser = Parser::new(source);
+	pub fn new(vm: &'a mut VM, source: &'a Source) -> Self {
+		let parser = Parser::new(source, vm.defined().clone());
 		let compiling_chunk = Chunk::default();
 		let current = Compiler::default();
 		Self {
 			current,
 			parser,
 			compiling_chunk,
+			far_jumps: HashMap::new(),
+			global_constants: HashMap::new(),
+			operand_start: Span::default(),
+			nesting: 0,
+			global_declarations: Vec::new(),
+			used_globals: HashSet::new(),
+			source,
 			vm,
 		}
 	}
@@ -48,11 +93,31 @@ impl<'a> Compilation<'a> {
 		self.end();
 		self.parser.consume(Ty::Eof, "Expect end of expression.");
 
+		if self.vm.warn_unused() && !self.parser.had_error() {
+			self.warn_unused_globals();
+		}
 		!self.parser.had_error()
 	}
 
+	/// Warns about each global declared in the source, or in the files it
+	/// includes, that nothing in them uses. Names starting with `_` are
+	/// unused on purpose.
+	fn warn_unused_globals(&self) {
+		for &(name, span) in &self.global_declarations {
+			if !name.starts_with('_') && !self.used_globals.contains(name) {
+				let location = self.source.location(span.line);
+				eprintln!("[{location}] Warning at '{name}': Global '{name}' is never used.");
+				vm::show_span(self.source, span);
+			}
+		}
+	}
+
 	fn end(&mut self) {
 		self.emit_bytes([Opcode::Return as u8]);
+		if !self.far_jumps.is_empty() {
+			let far_jumps = std::mem::take(&mut self.far_jumps);
+			self.current_chunk_mut().widen_jumps(&far_jumps);
+		}
 		if self.parser.had_error() {
 			debug::disassemble_chunk(self.current_chunk_mut(), "code");
 		}
@@ -61,6 +126,8 @@ impl<'a> Compilation<'a> {
 	fn declaration(&mut self) {
 		if self.parser.matches(Ty::Var) {
 			self.var_declaration();
+		} else if self.parser.matches(Ty::Const) {
+			self.const_declaration();
 		} else {
 			self.statement();
 		}
@@ -71,14 +138,59 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn var_declaration(&mut self) {
-		let global = self.parse_variable("Expect variable name.");
-		if self.parser.matches(Ty::Equal) {
-			self.expression();
-		} else {
-			self.emit_bytes([Opcode::Nil as u8]);
-		};
+		loop {
+			let global = self.parse_variable("Expect variable name.");
+			if self.current.scope_depth == 0 {
+				self.global_constants
+					.insert(self.parser.previous().lexeme(), None);
+			}
+			if self.parser.matches(Ty::Equal) {
+				self.expression();
+			} else {
+				self.emit_bytes([Opcode::Uninitialized as u8]);
+				if self.current.scope_depth > 0 {
+					if let Some(local) = self.current.locals.last_mut() {
+						local.uninitialized = true;
+					}
+				}
+			};
+			// Each variable is in scope for the initializers after it.
+			self.define_variable(global);
+			if !self.parser.matches(Ty::Comma) {
+				break;
+			}
+		}
 		self.parser
 			.consume(Ty::Semicolon, "Expect ';' after variable declaration.");
+	}
+
+	fn const_declaration(&mut self) {
+		let global = self.parse_variable("Expect constant name.");
+		let name = self.parser.previous().lexeme();
+		self.parser
+			.consume(Ty::Equal, "Expect '=' after constant name.");
+		let start = self.current_chunk().len();
+		self.expression();
+		let code = &self.current_chunk().code()[start..];
+		let literal = match *code {
+			[op] => [Opcode::Nil, Opcode::True, Opcode::False]
+				.iter()
+				.any(|&literal| op == literal as u8),
+			[op, _] => op == Opcode::Constant as u8,
+			_ => false,
+		};
+		let constant = Const {
+			inline: literal.then(|| code.to_vec()),
+		};
+		self.parser
+			.consume(Ty::Semicolon, "Expect ';' after constant declaration.");
+		if self.current.scope_depth > 0 {
+			if let Some(local) = self.current.locals.last_mut() {
+				local.constant = Some(constant);
+			}
+		} else {
+			self.global_constants.insert(name, Some(constant));
+		}
 		self.define_variable(global);
 	}
 
@@ -88,7 +200,15 @@ impl<'a> Compilation<'a> {
 		if self.current.scope_depth > 0 {
 			0
 		} else {
-			self.identifier_constant(self.parser.previous().lexeme())
+			let name = self.parser.previous();
+			let declared = self
+				.global_declarations
+				.iter()
+				.any(|&(declared, _)| declared == name.lexeme());
+			if !declared {
+				self.global_declarations.push((name.lexeme(), name.span()));
+			}
+			self.identifier_constant(name.lexeme())
 		}
 	}
 
@@ -110,11 +230,17 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn add_local(&mut self, name: &'a str) {
-		if self.current.locals.len() == u8::MAX as usize {
+		// Slots are addressed by at most a u16 operand.
+		if self.current.locals.len() > u16::MAX as usize {
 			self.parser.error("Too many local variables in function.");
 			return;
 		}
-		self.current.locals.push(Local { name, depth: None });
+		self.current.locals.push(Local {
+			name,
+			depth: None,
+			uninitialized: false,
+			constant: None,
+		});
 	}
 
 	fn identifier_constant(&mut self, name: &str) -> u8 {
@@ -152,6 +278,13 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn statement(&mut self) {
+		if self.nesting == MAX_NESTING {
+			self.parser.error_at_current("Too much nesting.");
+			// Skip the token, so recovering goes on from past it.
+			self.parser.advance();
+			return;
+		}
+		self.nesting += 1;
 		if self.parser.matches(Ty::Print) {
 			self.print_statement();
 		} else if self.parser.matches(Ty::If) {
@@ -167,14 +300,32 @@ impl<'a> Compilation<'a> {
 		} else {
 			self.expression_statement();
 		}
+		self.nesting -= 1;
 	}
 
 	fn if_statement(&mut self) {
 		self.parser.consume(Ty::LeftParen, "Expect '(' after 'if'.");
+		let condition_start = self.current_chunk().len();
 		self.expression();
 		self.parser
 			.consume(Ty::RightParen, "Expect ')' after 'if'.");
 
+		if let Some(condition) = self.take_constant_condition(condition_start) {
+			if condition {
+				self.statement();
+			} else {
+				self.dead_statement();
+			}
+			if self.parser.matches(Ty::Else) {
+				if condition {
+					self.dead_statement();
+				} else {
+					self.statement();
+				}
+			}
+			return;
+		}
+
 		let then_jump = self.emit_jump(Opcode::JumpIfFalse);
 		self.emit_bytes([Opcode::Pop as u8]);
 		self.statement();
@@ -277,6 +428,19 @@ impl<'a> Compilation<'a> {
 		self.parser
 			.consume(Ty::RightParen, "Expect ')' after condition.");
 
+		match self.take_constant_condition(loop_start) {
+			Some(true) => {
+				self.statement();
+				self.emit_loop(loop_start);
+				return;
+			}
+			Some(false) => {
+				self.dead_statement();
+				return;
+			}
+			None => {}
+		}
+
 		let exit_jump = self.emit_jump(Opcode::JumpIfFalse);
 		self.emit_bytes([Opcode::Pop as u8]);
 		self.statement();
@@ -286,6 +450,33 @@ impl<'a> Compilation<'a> {
 		self.emit_bytes([Opcode::Pop as u8]);
 	}
 
+	/// If the code emitted since `start` is just a `true` or `false`
+	/// literal, removes it and returns its value, so the caller can leave
+	/// out the branch that never runs.
+	fn take_constant_condition(&mut self, start: usize) -> Option<bool> {
+		let condition = match self.current_chunk().code()[start..] {
+			[byte] if byte == Opcode::True as u8 => true,
+			[byte] if byte == Opcode::False as u8 => false,
+			_ => return None,
+		};
+		self.truncate(start);
+		Some(condition)
+	}
+
+	/// Compiles a statement that can never run, to report its errors, and
+	/// then throws its code away.
+	fn dead_statement(&mut self) {
+		let start = self.current_chunk().len();
+		self.statement();
+		self.truncate(start);
+	}
+
+	/// Throws away the code from `start` onwards.
+	fn truncate(&mut self, start: usize) {
+		self.current_chunk_mut().truncate(start);
+		self.far_jumps.retain(|&offset, _| offset < start);
+	}
+
 	fn expression_statement(&mut self) {
 		self.expression();
 		self.parser
@@ -294,21 +485,65 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn binary(&mut self, _: bool) {
+		let start = self.operand_start;
 		let operator = self.parser.previous().ty();
 		let rule = get_rule(operator);
 		self.parse_precedence(rule.precedence.successor());
 
+		if is_ordering(operator) && is_ordering(self.parser.current().ty()) {
+			self.comparison_chain(operator, start);
+		} else {
+			self.emit_operator(operator, start);
+		}
+	}
+
+	/// Compiles `a < b < c` like `a < b and b < c`, but evaluating `b` only
+	/// once. Called with `a` and `b` already on the stack.
+	fn comparison_chain(&mut self, mut operator: Ty, start: Span) {
+		let mut false_jumps = Vec::new();
+		while is_ordering(self.parser.current().ty()) {
+			// Keep a copy of the right operand under the result, as the left
+			// operand of the next comparison.
+			self.emit_bytes([Opcode::Tuck as u8]);
+			self.emit_operator(operator, start);
+			false_jumps.push(self.emit_jump(Opcode::JumpIfFalse));
+			self.emit_bytes([Opcode::Pop as u8]);
+			self.parser.advance();
+			operator = self.parser.previous().ty();
+			self.parse_precedence(Precedence::Comparison.successor());
+		}
+		self.emit_operator(operator, start);
+		let end_jump = self.emit_jump(Opcode::Jump);
+
+		// A comparison failed, leaving the copy and `false` on the stack.
+		for jump in false_jumps {
+			self.patch_jump(jump);
+		}
+		self.emit_bytes([Opcode::Pop as u8, Opcode::Pop as u8, Opcode::False as u8]);
+		self.patch_jump(end_jump);
+	}
+
+	/// Emits the instructions for a binary operator, with both operands on
+	/// the stack. They span from `start` to the end of the right operand.
+	fn emit_operator(&mut self, operator: Ty, start: Span) {
+		let span = start.to(self.parser.previous().span());
 		match operator {
-			Ty::BangEqual => self.emit_bytes([Opcode::Equal as u8, Opcode::Not as u8]),
-			Ty::EqualEqual => self.emit_bytes([Opcode::Equal as u8]),
-			Ty::Greater => self.emit_bytes([Opcode::Greater as u8]),
-			Ty::GreaterEqual => self.emit_bytes([Opcode::Less as u8, Opcode::Not as u8]),
-			Ty::Less => self.emit_bytes([Opcode::Less as u8]),
-			Ty::LessEqual => self.emit_bytes([Opcode::Greater as u8, Opcode::Not as u8]),
-			Ty::Plus => self.emit_bytes([Opcode::Add as u8]),
-			Ty::Minus => self.emit_bytes([Opcode::Subtract as u8]),
-			Ty::Star => self.emit_bytes([Opcode::Multiply as u8]),
-			Ty::Slash => self.emit_bytes([Opcode::Divide as u8]),
+			Ty::BangEqual => self.emit_bytes_at(span, [Opcode::Equal as u8, Opcode::Not as u8]),
+			Ty::EqualEqual => self.emit_bytes_at(span, [Opcode::Equal as u8]),
+			Ty::Greater => self.emit_bytes_at(span, [Opcode::Greater as u8]),
+			Ty::GreaterEqual => self.emit_bytes_at(span, [Opcode::Less as u8, Opcode::Not as u8]),
+			Ty::Less => self.emit_bytes_at(span, [Opcode::Less as u8]),
+			Ty::LessEqual => self.emit_bytes_at(span, [Opcode::Greater as u8, Opcode::Not as u8]),
+			Ty::Plus => self.emit_bytes_at(span, [Opcode::Add as u8]),
+			Ty::Minus => self.emit_bytes_at(span, [Opcode::Subtract as u8]),
+			Ty::Star => self.emit_bytes_at(span, [Opcode::Multiply as u8]),
+			Ty::Slash => self.emit_bytes_at(span, [Opcode::Divide as u8]),
+			Ty::Percent => self.emit_bytes_at(span, [Opcode::Modulo as u8]),
+			Ty::Ampersand => self.emit_bytes_at(span, [Opcode::BitAnd as u8]),
+			Ty::Pipe => self.emit_bytes_at(span, [Opcode::BitOr as u8]),
+			Ty::Caret => self.emit_bytes_at(span, [Opcode::BitXor as u8]),
+			Ty::LessLess => self.emit_bytes_at(span, [Opcode::ShiftLeft as u8]),
+			Ty::GreaterGreater => self.emit_bytes_at(span, [Opcode::ShiftRight as u8]),
 			_ => unreachable!(),
 		}
 	}
@@ -321,12 +556,15 @@ impl<'a> Compilation<'a> {
 
 	fn unary(&mut self, _: bool) {
 		let operator = self.parser.previous().ty();
+		let start = self.parser.previous().span();
 
 		self.parse_precedence(Precedence::Unary);
 
+		let span = start.to(self.parser.previous().span());
 		match operator {
-			Ty::Minus => self.emit_bytes([Opcode::Negate as u8]),
-			Ty::Bang => self.emit_bytes([Opcode::Not as u8]),
+			Ty::Minus => self.emit_bytes_at(span, [Opcode::Negate as u8]),
+			Ty::Bang => self.emit_bytes_at(span, [Opcode::Not as u8]),
+			Ty::Tilde => self.emit_bytes_at(span, [Opcode::BitNot as u8]),
 			_ => unreachable!(),
 		}
 	}
@@ -345,7 +583,18 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn parse_precedence(&mut self, prec: Precedence) {
+		if self.nesting == MAX_NESTING {
+			self.parser.error_at_current("Too much nesting.");
+			return;
+		}
+		self.nesting += 1;
+		self.parse_unnested(prec);
+		self.nesting -= 1;
+	}
+
+	fn parse_unnested(&mut self, prec: Precedence) {
 		self.parser.advance();
+		let start = self.parser.previous().span();
 		let prefix_rule = get_rule(self.parser.previous().ty()).prefix;
 		let prefix_rule = if let Some(prefix_rule) = prefix_rule {
 			prefix_rule
@@ -360,21 +609,25 @@ impl<'a> Compilation<'a> {
 		while prec <= get_rule(self.parser.current().ty()).precedence {
 			self.parser.advance();
 			let infix_rule = get_rule(self.parser.previous().ty()).infix.unwrap();
+			self.operand_start = start;
 			infix_rule(self, can_assign);
 		}
 	}
 
 	fn number(&mut self, _: bool) {
-		let value = self.parser.previous().lexeme().parse::<f64>().unwrap();
-		self.emit_constant(value);
+		let lexeme = self.parser.previous().lexeme();
+		match integer_literal(lexeme) {
+			Some(value) => self.emit_constant(value),
+			None => self.emit_constant(lexeme.parse::<f64>().unwrap()),
+		}
 	}
 
 	fn string(&mut self, _: bool) {
 		let token = self.parser.previous();
 		let lexeme = token.lexeme();
-		let copied_str = (&lexeme[1..lexeme.len() - 1]).to_owned();
-		let obj = self.vm.allocate_string(copied_str);
-		self.emit_constant(obj);
+		let copied_str = lexeme[1..lexeme.len() - 1].to_owned();
+		let value = self.vm.make_string(copied_str);
+		self.emit_constant(value);
 	}
 
 	fn variable(&mut self, can_assign: bool) {
@@ -382,31 +635,72 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn named_variable(&mut self, name: &'a str, can_assign: bool) {
-		// let current = &self.current;
-		let (arg, get_op, set_op) = match self.resolve_local(name) {
+		let name_span = self.parser.previous().span();
+		let slot = self.resolve_local(name);
+		let (uninitialized, constant) = match slot {
+			Some(slot) => {
+				let local = &self.current.locals[slot as usize];
+				(local.uninitialized, local.constant.clone())
+			}
+			None => {
+				self.used_globals.insert(name);
+				let constant = match self.global_constants.get(name) {
+					Some(constant) => constant.clone(),
+					None => self
+						.vm
+						.is_global_constant(name)
+						.then_some(Const { inline: None }),
+				};
+				(false, constant)
+			}
+		};
+		let (arg, get_op, set_op) = match slot {
 			None => (
-				self.identifier_constant(name),
+				self.identifier_constant(name) as u16,
 				Opcode::GetGlobal,
 				Opcode::SetGlobal,
 			),
-			Some(i) => (i as u8, Opcode::GetLocal, Opcode::SetLocal),
+			Some(slot) if slot <= u8::MAX as u16 => (slot, Opcode::GetLocal, Opcode::SetLocal),
+			Some(slot) => (slot, Opcode::GetLocalLong, Opcode::SetLocalLong),
 		};
-		if can_assign && self.parser.matches(Ty::Equal) {
+		let op = if can_assign && self.parser.matches(Ty::Equal) {
+			if constant.is_some() {
+				self.parser.error("Can't assign to a constant.");
+			}
 			self.expression();
-			self.emit_bytes([set_op as u8, arg]);
+			set_op
+		} else if let Some(Const { inline: Some(code) }) = constant {
+			for byte in code {
+				self.current_chunk_mut().write(byte, name_span);
+			}
+			return;
 		} else {
-			self.emit_bytes([get_op as u8, arg]);
+			get_op
+		};
+		// An assignment covers the whole expression, a read just the name.
+		let span = name_span.to(self.parser.previous().span());
+		match op {
+			Opcode::GetLocalLong | Opcode::SetLocalLong => {
+				let [high, low] = arg.to_be_bytes();
+				self.emit_bytes_at(span, [op as u8, high, low]);
+			}
+			_ => self.emit_bytes_at(span, [op as u8, arg as u8]),
+		}
+		// Globals are checked as they are looked up.
+		if op == get_op && uninitialized {
+			let name = self.identifier_constant(name);
+			self.emit_bytes_at(span, [Opcode::CheckInitialized as u8, name]);
 		}
 	}
 
-	fn resolve_local(&mut self, name: &'a str) -> Option<u8> {
+	fn resolve_local(&mut self, name: &'a str) -> Option<u16> {
 		for (i, local) in self.current.locals.iter().enumerate().rev() {
 			if name == local.name {
 				if local.depth.is_none() {
 					self.parser
 						.error("Can't read local variable in its own initializer.");
 				}
-				return Some(i as u8);
+				return Some(i as u16);
 			}
 		}
 		None
@@ -432,7 +726,10 @@ impl<'a> Compilation<'a> {
 	fn patch_jump(&mut self, offset: usize) {
 		let jump = self.current_chunk().len() as isize - offset as isize - 2;
 		if jump > u16::MAX as isize {
-			self.parser.error("Too much code to jump over.");
+			// Leave the operand as is; `widen_jumps` rewrites this jump.
+			let target = self.current_chunk().len();
+			self.far_jumps.insert(offset - 1, target);
+			return;
 		}
 		let code = self.current_chunk_mut().code_mut();
 		code[offset] = (jump >> 8) as u8;
@@ -445,23 +742,37 @@ impl<'a> Compilation<'a> {
 	}
 
 	fn emit_bytes<const N: usize>(&mut self, bytes: [u8; N]) {
-		let line = self.parser.previous().line();
+		self.emit_bytes_at(self.parser.previous().span(), bytes);
+	}
+
+	fn emit_bytes_at<const N: usize>(&mut self, span: Span, bytes: [u8; N]) {
 		for byte in bytes {
-			self.current_chunk_mut().write(byte, line);
+			self.current_chunk_mut().write(byte, span);
 		}
 	}
 
 	fn emit_loop(&mut self, loop_start: usize) {
-		self.emit_bytes([Opcode::Loop as u8]);
-
-		let offset = self.current_chunk().len() - loop_start + 2;
-		if offset > u16::MAX as usize {
-			self.parser.error("Loop body too large.");
+		let offset = self.current_chunk().len() - loop_start + 3;
+		if let Ok(offset) = u16::try_from(offset) {
+			self.emit_bytes([Opcode::Loop as u8]);
+			self.emit_bytes(offset.to_be_bytes());
+		} else {
+			self.emit_bytes([Opcode::LoopLong as u8]);
+			self.emit_bytes((offset as u32 + 2).to_be_bytes());
 		}
-		self.emit_bytes((offset as u16).to_be_bytes());
 	}
 
 	#[inline]
+	/// The globals this source declared at the top level, each with
+	/// whether it was declared with `const`, for the VM to keep once the
+	/// source has run.
+	pub fn global_constants(&self) -> Vec<(String, bool)> {
+		self.global_constants
+			.iter()
+			.map(|(name, constant)| (name.to_string(), constant.is_some()))
+			.collect()
+	}
+
 	pub fn into_chunk(self) -> Chunk {
 		self.compiling_chunk
 	}
@@ -486,10 +797,16 @@ enum Precedence {
 	And,        // and
 	Equality,   // == !=
 	Comparison, // < > <= >=
-	Term,       // + -
-	Factor,     // * /
-	Unary,      // ! -
-	Call,       // . ()
+	// The bitwise operators bind tighter than comparisons, unlike in C,
+	// so that `a & mask == 0` means what it looks like.
+	BitOr,  // |
+	BitXor, // ^
+	BitAnd, // &
+	Shift,  // << >>
+	Term,   // + -
+	Factor, // * / %
+	Unary,  // ! - ~
+	Call,   // . ()
 	Primary,
 }
 
@@ -501,7 +818,11 @@ impl Precedence {
 			Precedence::Or => Precedence::And,
 			Precedence::And => Precedence::Equality,
 			Precedence::Equality => Precedence::Comparison,
-			Precedence::Comparison => Precedence::Term,
+			Precedence::Comparison => Precedence::BitOr,
+			Precedence::BitOr => Precedence::BitXor,
+			Precedence::BitXor => Precedence::BitAnd,
+			Precedence::BitAnd => Precedence::Shift,
+			Precedence::Shift => Precedence::Term,
 			Precedence::Term => Precedence::Factor,
 			Precedence::Factor => Precedence::Unary,
 			Precedence::Unary => Precedence::Call,
@@ -533,6 +854,14 @@ impl<'a> ParseRule<'a> {
 	}
 }
 
+/// The operators that can be chained, as in `a < b < c`.
+fn is_ordering(ty: Ty) -> bool {
+	matches!(
+		ty,
+		Ty::Greater | Ty::GreaterEqual | Ty::Less | Ty::LessEqual
+	)
+}
+
 fn get_rule<'a>(operator: Ty) -> ParseRule<'a> {
 	#[rustfmt::skip]
     let (prefix, infix, precedence): (Option<ParseFn>, Option<ParseFn>, Precedence) = match operator
@@ -548,6 +877,11 @@ fn get_rule<'a>(operator: Ty) -> ParseRule<'a> {
         Ty::Semicolon    => (None,                        None,                      Precedence::None),
         Ty::Slash        => (None,                        Some(Compilation::binary), Precedence::Factor),
         Ty::Star         => (None,                        Some(Compilation::binary), Precedence::Factor),
+        Ty::Percent      => (None,                        Some(Compilation::binary), Precedence::Factor),
+        Ty::Ampersand    => (None,                        Some(Compilation::binary), Precedence::BitAnd),
+        Ty::Pipe         => (None,                        Some(Compilation::binary), Precedence::BitOr),
+        Ty::Caret        => (None,                        Some(Compilation::binary), Precedence::BitXor),
+        Ty::Tilde        => (Some(Compilation::unary),    None,                      Precedence::None),
         Ty::Bang         => (Some(Compilation::unary),    None,                      Precedence::None),
         Ty::BangEqual    => (None,                        Some(Compilation::binary), Precedence::Equality),
         Ty::Equal        => (None,                        None,                      Precedence::None),
@@ -556,16 +890,21 @@ fn get_rule<'a>(operator: Ty) -> ParseRule<'a> {
         Ty::GreaterEqual => (None,                        Some(Compilation::binary), Precedence::Comparison),
         Ty::Less         => (None,                        Some(Compilation::binary), Precedence::Comparison),
         Ty::LessEqual    => (None,                        Some(Compilation::binary), Precedence::Comparison),
+        Ty::LessLess     => (None,                        Some(Compilation::binary), Precedence::Shift),
+        Ty::GreaterGreater => (None,                      Some(Compilation::binary), Precedence::Shift),
         Ty::Identifier   => (Some(Compilation::variable), None,                      Precedence::None),
         Ty::String       => (Some(Compilation::string),   None,                      Precedence::None),
         Ty::Number       => (Some(Compilation::number),   None,                      Precedence::None),
         Ty::And          => (None,                        Some(Compilation::and),    Precedence::And),
+        Ty::Break        => (None,                        None,                      Precedence::None),
         Ty::Class        => (None,                        None,                      Precedence::None),
+        Ty::Const        => (None,                        None,                      Precedence::None),
         Ty::Else         => (None,                        None,                      Precedence::None),
         Ty::False        => (Some(Compilation::literal),  None,                      Precedence::None),
         Ty::For          => (None,                        None,                      Precedence::None),
         Ty::Fun          => (None,                        None,                      Precedence::None),
         Ty::If           => (None,                        None,                      Precedence::None),
+        Ty::Is           => (None,                        None,                      Precedence::None),
         Ty::Nil          => (Some(Compilation::literal),  None,                      Precedence::None),
         Ty::Or           => (None,                        Some(Compilation::or),     Precedence::Or),
         Ty::Print        => (None,                        None,                      Precedence::None),
@@ -575,6 +914,7 @@ fn get_rule<'a>(operator: Ty) -> ParseRule<'a> {
         Ty::True         => (Some(Compilation::literal),  None,                      Precedence::None),
         Ty::Var          => (None,                        None,                      Precedence::None),
         Ty::While        => (None,                        None,                      Precedence::None),
+        Ty::Yield        => (None,                        None,                      Precedence::None),
         Ty::Error        => (None,                        None,                      Precedence::None),
         Ty::Eof          => (None,                        None,                      Precedence::None),
     };
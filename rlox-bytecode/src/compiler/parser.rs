@@ -1,30 +1,35 @@
 use crate::scanner::token::Ty;
 use crate::scanner::Scanner;
+use crate::source::Source;
 
 use crate::scanner::token::Token;
 
-use std::mem::MaybeUninit;
+use std::collections::HashSet;
 
 pub struct Parser<'a> {
+	source: &'a Source,
 	scanner: Scanner<'a>,
-	current: MaybeUninit<Token<'a>>,
-	previous: MaybeUninit<Token<'a>>,
+	current: Token<'a>,
+	/// Before the first token is consumed, an empty end of file where the
+	/// script starts.
+	previous: Token<'a>,
 	had_error: bool,
 	panic_mode: bool,
 }
 
 impl<'a> Parser<'a> {
-	pub fn new(source: &'a str) -> Self {
-		let current = MaybeUninit::uninit();
-		let previous = MaybeUninit::uninit();
+	pub fn new(source: &'a Source, defined: HashSet<String>) -> Self {
+		let current = Token::new(Ty::Eof, "", 1, 1);
+		let previous = current;
 		let had_error = false;
 		let panic_mode = false;
-		let scanner = Scanner::new(source);
+		let scanner = Scanner::new(source.text()).with_defined(defined);
 		let mut parser = Self {
 			current,
 			previous,
 			had_error,
 			panic_mode,
+			source,
 			scanner,
 		};
 		// prime the parser
@@ -34,12 +39,12 @@ impl<'a> Parser<'a> {
 
 	#[inline]
 	pub fn previous(&self) -> Token<'a> {
-		unsafe { self.previous.assume_init() }
+		self.previous
 	}
 
 	#[inline]
 	pub fn current(&self) -> Token<'a> {
-		unsafe { self.current.assume_init() }
+		self.current
 	}
 
 	pub fn synchronize(&mut self) {
@@ -50,6 +55,7 @@ impl<'a> Parser<'a> {
 			}
 			match self.current().ty() {
 				Ty::Class
+				| Ty::Const
 				| Ty::Fun
 				| Ty::Var
 				| Ty::For
@@ -65,16 +71,16 @@ impl<'a> Parser<'a> {
 	}
 
 	pub fn advance(&mut self) {
-		let next_token = loop {
+		self.previous = self.current;
+		loop {
 			let token = self.scanner.scan_token();
+			// Errors are reported at the error token itself.
+			self.current = token;
 			if token.ty() != Ty::Error {
-				break token;
+				break;
 			};
 			self.error_at_current(token.lexeme());
-		};
-		// Token implemented Copy so we don't need this
-		// unsafe { self.previous.assume_init_drop() };
-		self.previous = std::mem::replace(&mut self.current, MaybeUninit::new(next_token));
+		}
 	}
 
 	pub fn consume(&mut self, ty: Ty, message: &str) {
@@ -114,7 +120,7 @@ impl<'a> Parser<'a> {
 		}
 		self.panic_mode = true;
 
-		eprint!("[line {}] Error", token.line());
+		eprint!("[{}] Error", self.source.location(token.line()));
 
 		if token.ty() == Ty::Eof {
 			eprint!(" at end");
@@ -1,77 +1,187 @@
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt::Write,
+};
+
 use crate::chunk::{Chunk, Opcode};
 
+/// Jump targets, numbered in the order they appear in the chunk.
+type Labels = BTreeMap<usize, usize>;
+
+/// Prints every instruction in `chunk`, a blank line between basic blocks,
+/// with each jump target labelled and jumps referring to those labels.
 pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-	eprintln!("== {name} ==");
+	let mut out = String::new();
+	let _ = writeln!(out, "== {name} ==");
 
+	let labels = labels(chunk);
+	let mut block_ended = false;
 	let mut offset = 0;
 	while offset < chunk.code().len() {
-		offset = disassemble_instruction(chunk, offset);
+		let label = labels.get(&offset);
+		if offset > 0 && (block_ended || label.is_some()) {
+			out.push('\n');
+		}
+		if let Some(label) = label {
+			let _ = writeln!(out, "L{label}:");
+		}
+		block_ended =
+			chunk.jump_target(offset).is_some() || chunk.code()[offset] == Opcode::Return as u8;
+		offset = instruction(&mut out, chunk, offset, Some(&labels));
 	}
+	eprint!("{out}");
 }
 
+/// Prints the instruction at `offset`, returning the offset of the next.
+/// Jumps show the offset they land on, as there are no labels to refer to.
 pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-	eprint!("{offset:04} ");
+	let mut out = String::new();
+	let next = instruction(&mut out, chunk, offset, None);
+	eprint!("{out}");
+	next
+}
+
+/// Writes to `out` the instructions up to `context` before and after the
+/// one at `offset`, marking that one with `=>`.
+pub fn disassemble_around(out: &mut String, chunk: &Chunk, offset: usize, context: usize) {
+	let mut offsets = Vec::new();
+	let mut next = 0;
+	while next < chunk.code().len() {
+		offsets.push(next);
+		next += match Opcode::try_from(chunk.code()[next]) {
+			Ok(opcode) => 1 + opcode.operand_len(),
+			Err(()) => 1,
+		};
+	}
+	let at = offsets
+		.partition_point(|&start| start <= offset)
+		.saturating_sub(1);
+	let end = (at + context + 1).min(offsets.len());
+	for &start in &offsets[at.saturating_sub(context)..end] {
+		out.push_str(if start == offsets[at] { "=> " } else { "   " });
+		instruction(out, chunk, start, None);
+	}
+}
+
+fn labels(chunk: &Chunk) -> Labels {
+	let mut targets = BTreeSet::new();
+	let mut offset = 0;
+	while offset < chunk.code().len() {
+		targets.extend(chunk.jump_target(offset));
+		offset += match Opcode::try_from(chunk.code()[offset]) {
+			Ok(opcode) => 1 + opcode.operand_len(),
+			Err(()) => 1,
+		};
+	}
+	targets
+		.into_iter()
+		.enumerate()
+		.map(|(label, target)| (target, label))
+		.collect()
+}
 
-	if offset > 0 && chunk.lines()[offset] == chunk.lines()[offset - 1] {
-		eprint!("   | ");
+fn instruction(out: &mut String, chunk: &Chunk, offset: usize, labels: Option<&Labels>) -> usize {
+	let _ = write!(out, "{offset:04} ");
+
+	let span = chunk.span_at(offset);
+	if offset > 0 && span.line == chunk.line_at(offset - 1) {
+		let _ = write!(out, "   |:{:<3} ", span.column);
 	} else {
-		eprint!("{:4} ", chunk.lines()[offset])
+		let _ = write!(out, "{:4}:{:<3} ", span.line, span.column);
 	}
 
 	match Opcode::try_from(chunk.code()[offset]) {
-		Ok(Opcode::Constant) => constant_instruction("OP_CONSTANT", chunk, offset),
-		Ok(Opcode::Nil) => simple_instruction("OP_NIL", offset),
-		Ok(Opcode::True) => simple_instruction("OP_TRUE", offset),
-		Ok(Opcode::False) => simple_instruction("OP_FALSE", offset),
-		Ok(Opcode::Pop) => simple_instruction("OP_POP", offset),
-		Ok(Opcode::GetLocal) => byte_instruction("OP_GET_LOCAL", chunk, offset),
-		Ok(Opcode::GetGlobal) => constant_instruction("OP_GET_GLOBAL", chunk, offset),
-		Ok(Opcode::DefineGlobal) => constant_instruction("OP_DEFINE_GLOBAL", chunk, offset),
-		Ok(Opcode::SetLocal) => byte_instruction("OP_SET_LOCAL", chunk, offset),
-		Ok(Opcode::SetGlobal) => constant_instruction("OP_SET_GLOBAL", chunk, offset),
-		Ok(Opcode::Equal) => simple_instruction("OP_EQUAL", offset),
-		Ok(Opcode::Greater) => simple_instruction("OP_GREATER", offset),
-		Ok(Opcode::Less) => simple_instruction("OP_LESS", offset),
-		Ok(Opcode::Add) => simple_instruction("OP_ADD", offset),
-		Ok(Opcode::Subtract) => simple_instruction("OP_SUBTRACT", offset),
-		Ok(Opcode::Multiply) => simple_instruction("OP_MULTIPLY", offset),
-		Ok(Opcode::Divide) => simple_instruction("OP_DIVIDE", offset),
-		Ok(Opcode::Not) => simple_instruction("OP_NOT", offset),
-		Ok(Opcode::Negate) => simple_instruction("OP_NEGATE", offset),
-		Ok(Opcode::Print) => simple_instruction("OP_PRINT", offset),
-		Ok(Opcode::Jump) => jump_instruction("OP_JUMP", 1, chunk, offset),
-		Ok(Opcode::JumpIfFalse) => jump_instruction("OP_JUMP_IF_FALSE", 1, chunk, offset),
-		Ok(Opcode::Loop) => jump_instruction("OP_LOOP", -1, chunk, offset),
-		Ok(Opcode::Return) => simple_instruction("OP_RETURN", offset),
+		Ok(Opcode::Constant) => constant_instruction(out, "OP_CONSTANT", chunk, offset),
+		Ok(Opcode::Nil) => simple_instruction(out, "OP_NIL", offset),
+		Ok(Opcode::True) => simple_instruction(out, "OP_TRUE", offset),
+		Ok(Opcode::False) => simple_instruction(out, "OP_FALSE", offset),
+		Ok(Opcode::Uninitialized) => simple_instruction(out, "OP_UNINITIALIZED", offset),
+		Ok(Opcode::Pop) => simple_instruction(out, "OP_POP", offset),
+		Ok(Opcode::Tuck) => simple_instruction(out, "OP_TUCK", offset),
+		Ok(Opcode::GetLocal) => byte_instruction(out, "OP_GET_LOCAL", chunk, offset),
+		Ok(Opcode::GetLocalLong) => short_instruction(out, "OP_GET_LOCAL_LONG", chunk, offset),
+		Ok(Opcode::CheckInitialized) => {
+			constant_instruction(out, "OP_CHECK_INITIALIZED", chunk, offset)
+		}
+		Ok(Opcode::GetGlobal) => constant_instruction(out, "OP_GET_GLOBAL", chunk, offset),
+		Ok(Opcode::DefineGlobal) => constant_instruction(out, "OP_DEFINE_GLOBAL", chunk, offset),
+		Ok(Opcode::SetLocal) => byte_instruction(out, "OP_SET_LOCAL", chunk, offset),
+		Ok(Opcode::SetLocalLong) => short_instruction(out, "OP_SET_LOCAL_LONG", chunk, offset),
+		Ok(Opcode::SetGlobal) => constant_instruction(out, "OP_SET_GLOBAL", chunk, offset),
+		Ok(Opcode::Equal) => simple_instruction(out, "OP_EQUAL", offset),
+		Ok(Opcode::Greater) => simple_instruction(out, "OP_GREATER", offset),
+		Ok(Opcode::Less) => simple_instruction(out, "OP_LESS", offset),
+		Ok(Opcode::Add) => simple_instruction(out, "OP_ADD", offset),
+		Ok(Opcode::Subtract) => simple_instruction(out, "OP_SUBTRACT", offset),
+		Ok(Opcode::Multiply) => simple_instruction(out, "OP_MULTIPLY", offset),
+		Ok(Opcode::Divide) => simple_instruction(out, "OP_DIVIDE", offset),
+		Ok(Opcode::Modulo) => simple_instruction(out, "OP_MODULO", offset),
+		Ok(Opcode::BitAnd) => simple_instruction(out, "OP_BIT_AND", offset),
+		Ok(Opcode::BitOr) => simple_instruction(out, "OP_BIT_OR", offset),
+		Ok(Opcode::BitXor) => simple_instruction(out, "OP_BIT_XOR", offset),
+		Ok(Opcode::ShiftLeft) => simple_instruction(out, "OP_SHIFT_LEFT", offset),
+		Ok(Opcode::ShiftRight) => simple_instruction(out, "OP_SHIFT_RIGHT", offset),
+		Ok(Opcode::Not) => simple_instruction(out, "OP_NOT", offset),
+		Ok(Opcode::Negate) => simple_instruction(out, "OP_NEGATE", offset),
+		Ok(Opcode::BitNot) => simple_instruction(out, "OP_BIT_NOT", offset),
+		Ok(Opcode::Print) => simple_instruction(out, "OP_PRINT", offset),
+		Ok(Opcode::Jump) => jump_instruction(out, "OP_JUMP", chunk, offset, labels),
+		Ok(Opcode::JumpIfFalse) => jump_instruction(out, "OP_JUMP_IF_FALSE", chunk, offset, labels),
+		Ok(Opcode::Loop) => jump_instruction(out, "OP_LOOP", chunk, offset, labels),
+		Ok(Opcode::JumpLong) => jump_instruction(out, "OP_JUMP_LONG", chunk, offset, labels),
+		Ok(Opcode::JumpIfFalseLong) => {
+			jump_instruction(out, "OP_JUMP_IF_FALSE_LONG", chunk, offset, labels)
+		}
+		Ok(Opcode::LoopLong) => jump_instruction(out, "OP_LOOP_LONG", chunk, offset, labels),
+		Ok(Opcode::Return) => simple_instruction(out, "OP_RETURN", offset),
 		Err(()) => {
-			eprintln!("Unknown opcode {}", chunk.code()[offset]);
+			let _ = writeln!(out, "Unknown opcode {}", chunk.code()[offset]);
 			offset + 1
 		}
 	}
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn constant_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
 	let constant = chunk.code()[offset + 1] as usize;
-	eprintln!("{name:-16} {constant:4} '{}'", chunk.constants()[constant]);
+	let _ = writeln!(
+		out,
+		"{name:-16} {constant:4} '{}'",
+		chunk.constants()[constant]
+	);
 	offset + 2
 }
 
-fn simple_instruction(name: &str, offset: usize) -> usize {
-	eprintln!("{name}");
+fn simple_instruction(out: &mut String, name: &str, offset: usize) -> usize {
+	let _ = writeln!(out, "{name}");
 	offset + 1
 }
 
-fn byte_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn byte_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
 	let slot = chunk.code()[offset + 1];
-	eprintln!("{name:-16} {slot:4}");
+	let _ = writeln!(out, "{name:-16} {slot:4}");
 	offset + 2
 }
 
-fn jump_instruction(name: &str, sign: isize, chunk: &Chunk, offset: usize) -> usize {
-	let jump = ((chunk.code()[offset + 1] as u16) << 8) | chunk.code()[offset + 2] as u16;
-	eprintln!(
-		"{name:-16} {offset:4} -> {}",
-		offset as isize + 3 + sign * jump as isize
-	);
+fn short_instruction(out: &mut String, name: &str, chunk: &Chunk, offset: usize) -> usize {
+	let slot = u16::from_be_bytes([chunk.code()[offset + 1], chunk.code()[offset + 2]]);
+	let _ = writeln!(out, "{name:-16} {slot:4}");
 	offset + 3
 }
+
+fn jump_instruction(
+	out: &mut String,
+	name: &str,
+	chunk: &Chunk,
+	offset: usize,
+	labels: Option<&Labels>,
+) -> usize {
+	let _ = match (chunk.jump_target(offset), labels) {
+		(Some(target), Some(labels)) => {
+			writeln!(out, "{name:-16} {offset:4} -> L{}", labels[&target])
+		}
+		(Some(target), None) => writeln!(out, "{name:-16} {offset:4} -> {target}"),
+		(None, _) => writeln!(out, "{name:-16} {offset:4} -> ?"),
+	};
+	let opcode = Opcode::try_from(chunk.code()[offset]).unwrap();
+	offset + 1 + opcode.operand_len()
+}
@@ -1,61 +1,232 @@
+#![cfg_attr(
+	feature = "threaded-dispatch",
+	feature(explicit_tail_calls),
+	allow(incomplete_features)
+)]
+
 use std::{
-	io::{self, Write},
+	collections::HashSet,
+	fs::File,
+	io::{self, BufWriter, Write},
+	path::Path,
 	process::exit,
 };
 
+use source::Source;
+use trace::{Filter, Trace};
+
 use vm::{InterpretError, VM};
 
 mod chunk;
 mod compiler;
 mod debug;
+mod optimizer;
 mod scanner;
+mod source;
+mod stats;
 mod table;
+mod trace;
 mod value;
+mod verify;
 mod vm;
 
 fn main() {
-	let mut args = std::env::args();
-	if args.len() == 1 {
-		repl();
-	} else if args.len() == 2 {
-		run_file(&args.nth(1).unwrap());
-	} else {
-		eprintln!("Usage: clox [path]");
-		exit(64);
+	let mut path = None;
+	let mut opt_level = 0;
+	let mut strict_math = false;
+	let mut stats = false;
+	let mut warn_unused = false;
+	let mut trace_filter = None;
+	let mut trace_file = None;
+	let mut dump_on_error: Option<Box<dyn Write>> = None;
+	let mut defined = HashSet::new();
+	#[cfg(feature = "register-vm")]
+	let mut registers = false;
+	#[cfg(feature = "threaded-dispatch")]
+	let mut threaded = false;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--opt-level" => {
+				opt_level = args
+					.next()
+					.and_then(|level| level.parse().ok())
+					.filter(|&level| level <= optimizer::MAX_LEVEL)
+					.unwrap_or_else(|| usage());
+			}
+			"--strict-math" => strict_math = true,
+			"--vm-stats" => stats = true,
+			"--warn-unused" => warn_unused = true,
+			_ if arg.starts_with("--trace=") => {
+				let filter = Filter::parse(&arg["--trace=".len()..]);
+				trace_filter = Some(filter.unwrap_or_else(|| usage()));
+			}
+			"--trace-file" => trace_file = Some(args.next().unwrap_or_else(|| usage())),
+			"--dump-on-error" => dump_on_error = Some(Box::new(io::stderr())),
+			_ if arg.starts_with("--dump-on-error=") => {
+				let path = &arg["--dump-on-error=".len()..];
+				let file = File::create(path).unwrap_or_else(|err| {
+					eprintln!("Could not open \"{path}\" to dump to: {err}");
+					exit(74);
+				});
+				dump_on_error = Some(Box::new(file));
+			}
+			"--define" => {
+				defined.insert(args.next().unwrap_or_else(|| usage()));
+			}
+			#[cfg(feature = "register-vm")]
+			"--registers" => registers = true,
+			#[cfg(feature = "threaded-dispatch")]
+			"--threaded" => threaded = true,
+			_ if arg.starts_with("--") || path.is_some() => usage(),
+			_ => path = Some(arg),
+		}
 	}
+	let trace = match (trace_filter, trace_file) {
+		(Some(filter), Some(path)) => {
+			let file = File::create(&path).unwrap_or_else(|err| {
+				eprintln!("Could not open \"{path}\" to trace to: {err}");
+				exit(74);
+			});
+			Some(Trace::new(filter, Box::new(BufWriter::new(file))))
+		}
+		(Some(filter), None) => Some(Trace::new(filter, Box::new(io::stderr()))),
+		(None, Some(_)) => usage(),
+		(None, None) => None,
+	};
+	let vm = VM::default()
+		.with_opt_level(opt_level)
+		.with_strict_math(strict_math)
+		.with_stats(stats)
+		.with_warn_unused(warn_unused)
+		.with_trace(trace)
+		.with_dump_on_error(dump_on_error)
+		.with_defined(defined);
+	#[cfg(feature = "register-vm")]
+	let vm = vm.with_registers(registers);
+	#[cfg(feature = "threaded-dispatch")]
+	let vm = vm.with_threaded(threaded);
+	match path {
+		Some(path) => run_file(vm, &path),
+		None => repl(vm),
+	}
+}
+
+fn usage() -> ! {
+	eprintln!(
+		"Usage: clox [--opt-level 0|1] [--strict-math] [--vm-stats] [--warn-unused] \
+		 [--trace=calls,globals,jumps [--trace-file file]] [--dump-on-error[=file]] \
+		 [--define name]... [path]"
+	);
+	exit(64);
 }
 
-fn repl() {
+/// Reads and runs one line at a time. Besides code, a line can be one of
+/// these commands:
+///
+/// - `:step code` runs `code` one instruction at a time, showing the stack
+///   and the instruction about to run before each. An empty line runs that
+///   instruction, `:continue` runs the rest without stopping, and `:stack`
+///   and `:globals` work in between.
+/// - `:stack` shows the value stack.
+/// - `:globals` shows every global and its value.
+fn repl(mut vm: VM) {
 	let stdin = io::stdin();
 	let mut stdout = io::stdout();
-	let mut vm = VM::default();
 	loop {
 		let mut line: String = String::new();
-		print!("> ");
-		stdout.flush().unwrap();
+		// The prompt is only a courtesy, not worth failing over.
+		let _ = write!(stdout, "> ").and_then(|()| stdout.flush());
 		match stdin.read_line(&mut line) {
 			Ok(0) | Err(_) => {
-				println!();
+				let _ = writeln!(stdout);
+				print_stats(&vm);
 				break;
 			}
 			Ok(_) => {
-				let _ = vm.intepret(&line);
+				let (command, code) = match line.trim_start().strip_prefix(':') {
+					Some(command) => command.split_once(' ').unwrap_or((command.trim(), "")),
+					None => ("", line.as_str()),
+				};
+				match command {
+					"" => run_line(&mut vm, code, false),
+					"step" => run_line(&mut vm, code, true),
+					"stack" | "globals" => inspect(&vm, command),
+					_ => eprintln!("Unknown command ':{command}'."),
+				}
+			}
+		}
+	}
+}
+
+/// Runs a line of code typed into the REPL, pausing before each
+/// instruction if `step` is set.
+fn run_line(vm: &mut VM, code: &str, step: bool) {
+	let source = match Source::new(code, None) {
+		Ok(source) => source,
+		Err(err) => return eprintln!("{err}"),
+	};
+	if !step {
+		let _ = vm.intepret(&source);
+		return;
+	}
+	let stdin = io::stdin();
+	let mut stdout = io::stdout();
+	let _ = vm.step(&source, &mut |vm, chunk, offset| {
+		let mut instruction = String::new();
+		debug::disassemble_around(&mut instruction, chunk, offset, 0);
+		println!("{}", vm.describe_stack());
+		print!("{instruction}");
+		loop {
+			let mut line = String::new();
+			let _ = write!(stdout, "step> ").and_then(|()| stdout.flush());
+			match stdin.read_line(&mut line) {
+				// Run the rest rather than leave the chunk half done.
+				Ok(0) | Err(_) => return false,
+				Ok(_) => match line.trim() {
+					"" => return true,
+					":continue" => return false,
+					command @ (":stack" | ":globals") => inspect(vm, &command[1..]),
+					command => eprintln!(
+						"Unknown step command '{command}': press enter to step, or try \
+						 ':continue', ':stack' or ':globals'."
+					),
+				},
 			}
 		}
+	});
+}
+
+/// Shows the VM's `"stack"` or `"globals"`.
+fn inspect(vm: &VM, what: &str) {
+	match what {
+		"stack" => println!("{}", vm.describe_stack()),
+		_ => print!("{}", vm.describe_globals()),
 	}
 }
 
-fn run_file(path: &str) {
+fn run_file(mut vm: VM, path: &str) {
 	let source = std::fs::read_to_string(path).unwrap_or_else(|e| {
 		eprintln!("Could not open file \"{path}\".");
 		eprintln!("Error: {e:#?}");
 		exit(74);
 	});
-	let mut vm = VM::default();
+	let source = Source::new(&source, Some(Path::new(path))).unwrap_or_else(|err| {
+		eprintln!("{err}");
+		exit(65);
+	});
 	let result = vm.intepret(&source);
+	print_stats(&vm);
 	match result {
 		Ok(_) => (),
 		Err(InterpretError::Compile) => exit(65),
 		Err(InterpretError::Runtime) => exit(70),
 	}
 }
+
+/// Prints the `--vm-stats` table, if it was asked for.
+fn print_stats(vm: &VM) {
+	if let Some(stats) = vm.stats() {
+		eprintln!("{stats}");
+	}
+}
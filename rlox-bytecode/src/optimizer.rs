@@ -0,0 +1,298 @@
+use crate::chunk::{Chunk, Opcode, Span};
+
+/// The highest level `--opt-level` accepts. Level 0 runs the compiler's
+/// output as it is; level 1 runs every pass in this module.
+pub const MAX_LEVEL: u8 = 1;
+
+/// A straight-line run of instructions, ending where control may go
+/// somewhere other than the next instruction.
+struct Block {
+	instructions: Vec<Instruction>,
+	exit: Exit,
+	/// The span of the instruction that makes the exit, if any.
+	exit_span: Span,
+}
+
+/// Any instruction other than a jump or return.
+struct Instruction {
+	opcode: Opcode,
+	operand: [u8; 2],
+	span: Span,
+}
+
+/// Where control goes after a block. Targets are block indices, so the
+/// direction and width of each jump is only chosen when lowering.
+#[derive(Clone, Copy)]
+enum Exit {
+	/// Carry on into the next block in the layout.
+	FallThrough,
+	Jump(usize),
+	/// Jump when the value on top of the stack is falsey, leaving it there,
+	/// and carry on into the next block otherwise.
+	JumpIfFalse(usize),
+	Return,
+}
+
+/// Lifts `chunk` into basic blocks, runs every pass over them and lays
+/// them out again as a chunk with the same constants.
+pub fn optimize(chunk: Chunk) -> Chunk {
+	let mut blocks = lift(&chunk);
+	remove_redundant_pops(&mut blocks);
+	thread_jumps(&mut blocks);
+	remove_dead_blocks(&mut blocks);
+	drop_jumps_to_next(&mut blocks);
+	lower(&blocks, chunk)
+}
+
+fn lift(chunk: &Chunk) -> Vec<Block> {
+	let code = chunk.code();
+	let spans = chunk.spans().collect::<Vec<_>>();
+
+	let mut instructions = Vec::new();
+	let mut leaders = vec![false; code.len() + 1];
+	leaders[0] = true;
+	let mut offset = 0;
+	while offset < code.len() {
+		let opcode = Opcode::try_from(code[offset]).expect("compiler emitted an opcode");
+		let next = offset + 1 + opcode.operand_len();
+		if let Some(target) = chunk.jump_target(offset) {
+			leaders[target] = true;
+			leaders[next] = true;
+		} else if opcode == Opcode::Return {
+			leaders[next] = true;
+		}
+		instructions.push((offset, opcode));
+		offset = next;
+	}
+
+	let mut block_at = vec![usize::MAX; code.len()];
+	let mut count = 0;
+	for &(offset, _) in &instructions {
+		if leaders[offset] {
+			block_at[offset] = count;
+			count += 1;
+		}
+	}
+
+	let mut blocks = Vec::with_capacity(count);
+	for (offset, opcode) in instructions {
+		if leaders[offset] {
+			blocks.push(Block {
+				instructions: Vec::new(),
+				exit: Exit::FallThrough,
+				exit_span: spans[offset],
+			});
+		}
+		let block = blocks.last_mut().expect("the first instruction leads");
+		let target = chunk.jump_target(offset).map(|target| block_at[target]);
+		block.exit = match (opcode, target) {
+			(Opcode::JumpIfFalse | Opcode::JumpIfFalseLong, Some(target)) => {
+				Exit::JumpIfFalse(target)
+			}
+			(_, Some(target)) => Exit::Jump(target),
+			(Opcode::Return, _) => Exit::Return,
+			_ => {
+				let mut operand = [0; 2];
+				let len = opcode.operand_len();
+				operand[..len].copy_from_slice(&code[offset + 1..offset + 1 + len]);
+				block.instructions.push(Instruction {
+					opcode,
+					operand,
+					span: spans[offset],
+				});
+				continue;
+			}
+		};
+		block.exit_span = spans[offset];
+	}
+	blocks
+}
+
+/// Drops values that are pushed only to be popped straight off again, like
+/// the result of an expression statement with no side effects.
+fn remove_redundant_pops(blocks: &mut [Block]) {
+	for block in blocks {
+		let mut kept: Vec<Instruction> = Vec::with_capacity(block.instructions.len());
+		for instruction in block.instructions.drain(..) {
+			if instruction.opcode == Opcode::Pop {
+				// `!` cannot fail, so negating a value nobody reads is as good
+				// as not negating it.
+				while kept.last().is_some_and(|last| last.opcode == Opcode::Not) {
+					kept.pop();
+				}
+				if kept.last().is_some_and(|last| {
+					matches!(
+						last.opcode,
+						Opcode::Constant
+							| Opcode::Nil | Opcode::True
+							| Opcode::False | Opcode::Uninitialized
+							| Opcode::GetLocal | Opcode::GetLocalLong
+					)
+				}) {
+					kept.pop();
+					continue;
+				}
+			}
+			kept.push(instruction);
+		}
+		block.instructions = kept;
+	}
+}
+
+/// Points jumps that land on a block with nothing in it straight at where
+/// that block goes next.
+fn thread_jumps(blocks: &mut [Block]) {
+	for i in 0..blocks.len() {
+		let (Exit::Jump(mut target) | Exit::JumpIfFalse(mut target)) = blocks[i].exit else {
+			continue;
+		};
+		// Bounded, so that a cycle of empty blocks cannot spin forever.
+		for _ in 0..blocks.len() {
+			let next = match blocks[target] {
+				Block {
+					ref instructions,
+					exit,
+					..
+				} if instructions.is_empty() => match exit {
+					Exit::FallThrough => target + 1,
+					Exit::Jump(next) => next,
+					_ => break,
+				},
+				_ => break,
+			};
+			// The VM only has forward conditional jumps.
+			if matches!(blocks[i].exit, Exit::JumpIfFalse(_)) && next <= i {
+				break;
+			}
+			target = next;
+		}
+		match &mut blocks[i].exit {
+			Exit::Jump(old) | Exit::JumpIfFalse(old) => *old = target,
+			_ => unreachable!(),
+		}
+	}
+}
+
+/// Removes blocks that no path from the start of the chunk reaches.
+fn remove_dead_blocks(blocks: &mut Vec<Block>) {
+	let mut reachable = vec![false; blocks.len()];
+	let mut pending = vec![0];
+	while let Some(i) = pending.pop() {
+		if std::mem::replace(&mut reachable[i], true) {
+			continue;
+		}
+		match blocks[i].exit {
+			Exit::FallThrough => pending.push(i + 1),
+			Exit::Jump(target) => pending.push(target),
+			Exit::JumpIfFalse(target) => pending.extend([target, i + 1]),
+			Exit::Return => {}
+		}
+	}
+
+	let mut renumbered = Vec::with_capacity(blocks.len());
+	let mut count = 0;
+	for &reachable in &reachable {
+		renumbered.push(count);
+		count += reachable as usize;
+	}
+	let mut reachable = reachable.into_iter();
+	blocks.retain(|_| reachable.next().unwrap());
+	for block in blocks {
+		if let Exit::Jump(target) | Exit::JumpIfFalse(target) = &mut block.exit {
+			*target = renumbered[*target];
+		}
+	}
+}
+
+/// Turns jumps to the very next block into plain fall-throughs.
+fn drop_jumps_to_next(blocks: &mut [Block]) {
+	for (i, block) in blocks.iter_mut().enumerate() {
+		if let Exit::Jump(target) | Exit::JumpIfFalse(target) = block.exit {
+			if target == i + 1 {
+				block.exit = Exit::FallThrough;
+			}
+		}
+	}
+}
+
+/// Lays `blocks` out in order, choosing for each jump the direction and the
+/// narrowest width that reaches its target.
+fn lower(blocks: &[Block], mut chunk: Chunk) -> Chunk {
+	let body_len = |block: &Block| {
+		block
+			.instructions
+			.iter()
+			.map(|instruction| 1 + instruction.opcode.operand_len())
+			.sum::<usize>()
+	};
+	let exit_len = |block: &Block, long: bool| match block.exit {
+		Exit::FallThrough => 0,
+		Exit::Return => 1,
+		Exit::Jump(_) | Exit::JumpIfFalse(_) if long => 5,
+		Exit::Jump(_) | Exit::JumpIfFalse(_) => 3,
+	};
+
+	let mut long = vec![false; blocks.len()];
+	let mut starts = Vec::with_capacity(blocks.len());
+	loop {
+		starts.clear();
+		let mut offset = 0;
+		for (block, &long) in blocks.iter().zip(&long) {
+			starts.push(offset);
+			offset += body_len(block) + exit_len(block, long);
+		}
+
+		let mut widened = false;
+		for (i, block) in blocks.iter().enumerate() {
+			if let Exit::Jump(target) | Exit::JumpIfFalse(target) = block.exit {
+				let next = starts[i] + body_len(block) + exit_len(block, long[i]);
+				if !long[i] && next.abs_diff(starts[target]) > u16::MAX as usize {
+					long[i] = true;
+					widened = true;
+				}
+			}
+		}
+		if !widened {
+			break;
+		}
+	}
+
+	let mut lowered = Chunk::with_constants(chunk.take_constants());
+	for (i, block) in blocks.iter().enumerate() {
+		for instruction in &block.instructions {
+			lowered.write(instruction.opcode as u8, instruction.span);
+			for &byte in &instruction.operand[..instruction.opcode.operand_len()] {
+				lowered.write(byte, instruction.span);
+			}
+		}
+		let target = match block.exit {
+			Exit::FallThrough => continue,
+			Exit::Return => {
+				lowered.write(Opcode::Return as u8, block.exit_span);
+				continue;
+			}
+			Exit::Jump(target) | Exit::JumpIfFalse(target) => starts[target],
+		};
+		let next = starts[i] + body_len(block) + exit_len(block, long[i]);
+		let opcode = match (block.exit, target >= next, long[i]) {
+			(Exit::JumpIfFalse(_), _, false) => Opcode::JumpIfFalse,
+			(Exit::JumpIfFalse(_), _, true) => Opcode::JumpIfFalseLong,
+			(_, true, false) => Opcode::Jump,
+			(_, true, true) => Opcode::JumpLong,
+			(_, false, false) => Opcode::Loop,
+			(_, false, true) => Opcode::LoopLong,
+		};
+		lowered.write(opcode as u8, block.exit_span);
+		let distance = next.abs_diff(target);
+		if long[i] {
+			for byte in (distance as u32).to_be_bytes() {
+				lowered.write(byte, block.exit_span);
+			}
+		} else {
+			for byte in (distance as u16).to_be_bytes() {
+				lowered.write(byte, block.exit_span);
+			}
+		}
+	}
+	lowered
+}
@@ -1,6 +1,7 @@
-use std::str::CharIndices;
+use std::{collections::HashSet, str::CharIndices};
 
 use itertools::{Itertools, MultiPeek};
+use rlox_common::{conditional::Conditions, keyword::Keyword};
 
 use self::token::{Token, Ty};
 
@@ -11,6 +12,7 @@ pub struct Scanner<'a> {
 	start: usize,
 	current: MultiPeek<CharIndices<'a>>,
 	line: usize,
+	conditions: Conditions,
 }
 
 impl<'a> Scanner<'a> {
@@ -23,9 +25,25 @@ impl<'a> Scanner<'a> {
 			start,
 			current,
 			line,
+			conditions: Conditions::default(),
 		}
 	}
 
+	/// Sets the symbols `//#if` directives test for.
+	pub fn with_defined(mut self, defined: HashSet<String>) -> Self {
+		self.conditions = Conditions::new(defined);
+		self
+	}
+
+	/// The column, counted in characters from 1, at which the current
+	/// lexeme starts.
+	fn column(&self) -> usize {
+		let line_start = self.source[..self.start]
+			.rfind('\n')
+			.map_or(0, |newline| newline + 1);
+		self.source[line_start..self.start].chars().count() + 1
+	}
+
 	#[inline]
 	fn offset(&mut self) -> usize {
 		let offset = self
@@ -40,14 +58,15 @@ impl<'a> Scanner<'a> {
 	fn make_token(&mut self, ty: Ty) -> Token<'a> {
 		let offset = self.offset();
 		let lexeme = &self.source[self.start..offset];
+		let column = self.column();
 		self.start = offset;
-		Token::new(ty, lexeme, self.line)
+		Token::new(ty, lexeme, self.line, column)
 	}
 
 	fn error_token(&self, message: &'static str) -> Token<'static> {
 		let ty = Ty::Error;
 		let lexeme = message;
-		Token::new(ty, lexeme, self.line)
+		Token::new(ty, lexeme, self.line, self.column())
 	}
 
 	#[inline]
@@ -75,7 +94,9 @@ impl<'a> Scanner<'a> {
 		false
 	}
 
-	fn skip_whitespace(&mut self) {
+	/// Skips whitespace and comments, and any code that directives leave
+	/// out, or fails with what is wrong with a directive.
+	fn skip_whitespace(&mut self) -> Result<(), &'static str> {
 		'outer: loop {
 			match self.peek() {
 				Some(' ') | Some('\r') | Some('\t') => {
@@ -87,13 +108,16 @@ impl<'a> Scanner<'a> {
 				}
 				Some('/') => {
 					if let Some('/') = self.peek() {
+						let start = self.offset();
 						loop {
 							self.advance();
 							if matches!(self.peek(), Some('\n') | None) {
 								self.current.reset_peek();
-								continue 'outer;
+								break;
 							}
 						}
+						self.directive(start)?;
+						continue 'outer;
 					}
 					break 'outer;
 				}
@@ -101,6 +125,34 @@ impl<'a> Scanner<'a> {
 			}
 		}
 		self.current.reset_peek();
+		Ok(())
+	}
+
+	/// Applies the comment that starts at `start` and was just skipped if
+	/// it is a directive, then skips any lines the directives leave out.
+	fn directive(&mut self, start: usize) -> Result<(), &'static str> {
+		let line_start = self.source[..start]
+			.rfind('\n')
+			.map_or(0, |newline| newline + 1);
+		let mut line = &self.source[line_start..self.offset()];
+		loop {
+			self.conditions.apply(line)?;
+			if self.conditions.is_active() {
+				return Ok(());
+			}
+			// Step over the newline, onto the next line.
+			if self.advance().is_none() {
+				return Ok(());
+			}
+			self.line += 1;
+			let start = self.offset();
+			let rest = &self.source[start..];
+			let end = start + rest.find('\n').unwrap_or(rest.len());
+			while self.offset() < end {
+				self.advance();
+			}
+			line = &self.source[start..end];
+		}
 	}
 
 	fn string(&mut self) -> Token<'a> {
@@ -125,6 +177,8 @@ impl<'a> Scanner<'a> {
 		while self.peek_is_digit() {
 			self.advance();
 		}
+		// Peeking at the non-digit moved the peek cursor past it.
+		self.reset_peek();
 
 		if matches!(self.peek(), Some('.')) && self.peek_is_digit() {
 			self.advance();
@@ -136,66 +190,28 @@ impl<'a> Scanner<'a> {
 		self.make_token(Ty::Number)
 	}
 
-	fn identifier_type(&mut self) -> Ty {
-		match self.source.as_bytes()[self.start] {
-			b'a' => return self.check_keyword(1, b"nd", Ty::And),
-			b'c' => return self.check_keyword(1, b"lass", Ty::Class),
-			b'e' => return self.check_keyword(1, b"lse", Ty::Else),
-			b'f' => {
-				if self.offset() - self.start > 1 {
-					match self.source.as_bytes()[self.start + 1] {
-						b'a' => return self.check_keyword(2, b"lse", Ty::False),
-						b'o' => return self.check_keyword(2, b"r", Ty::For),
-						b'u' => return self.check_keyword(2, b"n", Ty::Fun),
-						_ => {}
-					}
-				}
-			}
-			b'i' => return self.check_keyword(1, b"f", Ty::If),
-			b'n' => return self.check_keyword(1, b"il", Ty::Nil),
-			b'o' => return self.check_keyword(1, b"r", Ty::Or),
-			b'p' => return self.check_keyword(1, b"rint", Ty::Print),
-			b'r' => return self.check_keyword(1, b"eturn", Ty::Return),
-			b's' => return self.check_keyword(1, b"uper", Ty::Super),
-			b't' => {
-				if self.offset() - self.start > 1 {
-					match self.source.as_bytes()[self.start + 1] {
-						b'h' => return self.check_keyword(2, b"is", Ty::This),
-						b'r' => return self.check_keyword(2, b"ue", Ty::True),
-						_ => {}
-					}
-				}
-			}
-			b'v' => return self.check_keyword(1, b"ar", Ty::Var),
-			b'w' => return self.check_keyword(1, b"hile", Ty::While),
-			_ => {}
-		}
-		Ty::Identifier
-	}
-
-	fn check_keyword(&mut self, start: usize, rest: &[u8], ty: Ty) -> Ty {
-		if &self.source.as_bytes()[self.start + start..self.offset()] == rest {
-			ty
-		} else {
-			Ty::Identifier
-		}
-	}
-
 	fn identifier(&mut self) -> Token<'a> {
-		while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric()) {
+		while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
 			self.advance();
 		}
 		self.reset_peek();
-		let ty = self.identifier_type();
+		let word = &self.source[self.start..self.offset()];
+		let ty = Keyword::from_word(word).map_or(Ty::Identifier, Ty::from);
 		self.make_token(ty)
 	}
 
 	pub fn scan_token(&mut self) -> Token<'a> {
-		self.skip_whitespace();
+		let skipped = self.skip_whitespace();
 		self.start = self.offset();
+		if let Err(message) = skipped {
+			return self.error_token(message);
+		}
 		match self.advance() {
-			None => self.make_token(Ty::Eof),
-			Some(c) if c.is_ascii_alphabetic() => self.identifier(),
+			None => match self.conditions.finish() {
+				Ok(()) => self.make_token(Ty::Eof),
+				Err(message) => self.error_token(message),
+			},
+			Some(c) if c.is_ascii_alphabetic() || c == '_' => self.identifier(),
 			Some(c) if c.is_ascii_digit() => self.number(),
 			Some('(') => self.make_token(Ty::LeftParen),
 			Some(')') => self.make_token(Ty::RightParen),
@@ -208,6 +224,11 @@ impl<'a> Scanner<'a> {
 			Some('+') => self.make_token(Ty::Plus),
 			Some('/') => self.make_token(Ty::Slash),
 			Some('*') => self.make_token(Ty::Star),
+			Some('%') => self.make_token(Ty::Percent),
+			Some('&') => self.make_token(Ty::Ampersand),
+			Some('|') => self.make_token(Ty::Pipe),
+			Some('^') => self.make_token(Ty::Caret),
+			Some('~') => self.make_token(Ty::Tilde),
 			Some('!') => {
 				let token = if self.matches('=') {
 					Ty::BangEqual
@@ -227,6 +248,8 @@ impl<'a> Scanner<'a> {
 			Some('<') => {
 				let token = if self.matches('=') {
 					Ty::LessEqual
+				} else if self.matches('<') {
+					Ty::LessLess
 				} else {
 					Ty::Less
 				};
@@ -235,6 +258,8 @@ impl<'a> Scanner<'a> {
 			Some('>') => {
 				let token = if self.matches('=') {
 					Ty::GreaterEqual
+				} else if self.matches('>') {
+					Ty::GreaterGreater
 				} else {
 					Ty::Greater
 				};
@@ -245,3 +270,40 @@ impl<'a> Scanner<'a> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The type and lexeme of every token in `source`, up to the end.
+	fn scan(source: &str) -> Vec<(Ty, &str)> {
+		let mut scanner = Scanner::new(source);
+		let mut tokens = Vec::new();
+		loop {
+			let token = scanner.scan_token();
+			if token.ty() == Ty::Eof {
+				return tokens;
+			}
+			tokens.push((token.ty(), token.lexeme()));
+		}
+	}
+
+	#[test]
+	fn identifiers_with_underscores() {
+		for name in ["_x", "my_var", "a_1", "_"] {
+			assert_eq!(scan(name), [(Ty::Identifier, name)]);
+		}
+	}
+
+	#[test]
+	fn keywords_next_to_underscores() {
+		assert_eq!(
+			scan("var var_ _var"),
+			[
+				(Ty::Var, "var"),
+				(Ty::Identifier, "var_"),
+				(Ty::Identifier, "_var"),
+			]
+		);
+	}
+}
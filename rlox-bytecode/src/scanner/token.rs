@@ -1,19 +1,40 @@
+use rlox_common::keyword::Keyword;
+
+use crate::chunk::Span;
+
 #[derive(Clone, Copy)]
 pub struct Token<'a> {
 	ty: Ty,
 	lexeme: &'a str,
 	line: usize,
+	column: usize,
 }
 
 impl<'a> Token<'a> {
-	pub fn new(ty: Ty, lexeme: &'a str, line: usize) -> Self {
-		Self { ty, lexeme, line }
+	pub fn new(ty: Ty, lexeme: &'a str, line: usize, column: usize) -> Self {
+		Self {
+			ty,
+			lexeme,
+			line,
+			column,
+		}
 	}
 
 	pub fn line(&self) -> usize {
 		self.line
 	}
 
+	/// Where the lexeme starts. A string spanning several lines is placed
+	/// on the line it starts on, though [`Token::line`] is where it ends.
+	pub fn span(&self) -> Span {
+		let first_line = self.lexeme.split('\n').next().unwrap_or_default();
+		Span {
+			line: self.line - self.lexeme.matches('\n').count(),
+			column: self.column,
+			length: first_line.chars().count(),
+		}
+	}
+
 	pub fn lexeme(&self) -> &'a str {
 		self.lexeme
 	}
@@ -37,6 +58,11 @@ pub enum Ty {
 	Semicolon,
 	Slash,
 	Star,
+	Percent,
+	Ampersand,
+	Pipe,
+	Caret,
+	Tilde,
 
 	// one or two character
 	Bang,
@@ -47,6 +73,8 @@ pub enum Ty {
 	GreaterEqual,
 	Less,
 	LessEqual,
+	LessLess,
+	GreaterGreater,
 
 	// literals
 	Identifier,
@@ -55,12 +83,15 @@ pub enum Ty {
 
 	// keywords
 	And,
+	Break,
 	Class,
+	Const,
 	Else,
 	False,
 	Fun,
 	For,
 	If,
+	Is,
 	Nil,
 	Or,
 	Print,
@@ -70,7 +101,35 @@ pub enum Ty {
 	True,
 	Var,
 	While,
+	Yield,
 
 	Error,
 	Eof,
 }
+
+impl From<Keyword> for Ty {
+	fn from(keyword: Keyword) -> Self {
+		match keyword {
+			Keyword::And => Ty::And,
+			Keyword::Break => Ty::Break,
+			Keyword::Class => Ty::Class,
+			Keyword::Const => Ty::Const,
+			Keyword::Else => Ty::Else,
+			Keyword::False => Ty::False,
+			Keyword::For => Ty::For,
+			Keyword::Fun => Ty::Fun,
+			Keyword::If => Ty::If,
+			Keyword::Is => Ty::Is,
+			Keyword::Nil => Ty::Nil,
+			Keyword::Or => Ty::Or,
+			Keyword::Print => Ty::Print,
+			Keyword::Return => Ty::Return,
+			Keyword::Super => Ty::Super,
+			Keyword::This => Ty::This,
+			Keyword::True => Ty::True,
+			Keyword::Var => Ty::Var,
+			Keyword::While => Ty::While,
+			Keyword::Yield => Ty::Yield,
+		}
+	}
+}
@@ -0,0 +1,155 @@
+//! Scripts as the compiler sees them: every `#include "file.lox"` line
+//! replaced by the text of that file, before anything is scanned, along
+//! with where each line came from so diagnostics still point into the
+//! file that was written.
+
+use std::{
+	fmt::{self, Display},
+	fs,
+	path::{Path, PathBuf},
+};
+
+pub struct Source {
+	text: String,
+	/// Each file the text was put together from, the script itself first.
+	files: Vec<PathBuf>,
+	/// For each line of `text`, the index into `files` of the file it came
+	/// from and its line number there.
+	origins: Vec<(usize, usize)>,
+}
+
+impl Source {
+	/// Expands the includes in `text`, the script at `path`, or typed at
+	/// the REPL if `None`. Includes are relative to the including file,
+	/// or to the working directory at the REPL.
+	pub fn new(text: &str, path: Option<&Path>) -> Result<Self, IncludeError> {
+		let path = path.unwrap_or(Path::new(""));
+		let mut source = Self {
+			text: String::with_capacity(text.len()),
+			files: vec![path.to_owned()],
+			origins: Vec::new(),
+		};
+		let mut including = path.canonicalize().into_iter().collect();
+		source.include(text, 0, &mut including)?;
+		Ok(source)
+	}
+
+	/// Appends `text`, which is from `files[file]`, expanding its includes.
+	/// `including` holds every file being expanded, to catch cycles.
+	fn include(
+		&mut self,
+		text: &str,
+		file: usize,
+		including: &mut Vec<PathBuf>,
+	) -> Result<(), IncludeError> {
+		for (index, line) in text.split_inclusive('\n').enumerate() {
+			let error = |message| IncludeError {
+				location: Location {
+					file: (file > 0).then(|| self.files[file].as_path()),
+					line: index + 1,
+				}
+				.to_string(),
+				message,
+			};
+			let Some(name) = parse_include(line).map_err(|message| error(message.into()))? else {
+				self.text.push_str(line);
+				// Whatever follows an included file starts on a line of its own.
+				if file > 0 && !line.ends_with('\n') {
+					self.text.push('\n');
+				}
+				self.origins.push((file, index + 1));
+				continue;
+			};
+
+			let dir = self.files[file].parent().unwrap_or(Path::new(""));
+			let path = dir.join(name);
+			let included = path
+				.canonicalize()
+				.and_then(|canonical| Ok((fs::read_to_string(&path)?, canonical)));
+			let (included, canonical) = match included {
+				Ok(included) => included,
+				Err(err) => return Err(error(format!("Could not include \"{name}\": {err}."))),
+			};
+			if including.contains(&canonical) {
+				return Err(error(format!("Cyclic include of \"{name}\".")));
+			}
+
+			self.files.push(path);
+			including.push(canonical);
+			self.include(&included, self.files.len() - 1, including)?;
+			including.pop();
+		}
+		Ok(())
+	}
+
+	/// The text to compile.
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+
+	/// Where `line` of the text came from.
+	pub fn location(&self, line: usize) -> Location<'_> {
+		let (file, line) = match self.origins.get(line.wrapping_sub(1)) {
+			Some(&origin) => origin,
+			// Past the last line, where only the end of the script is.
+			None => match self.origins.last() {
+				Some(&(file, last)) => (file, last + line.saturating_sub(self.origins.len())),
+				None => (0, line),
+			},
+		};
+		Location {
+			file: (file > 0).then(|| self.files[file].as_path()),
+			line,
+		}
+	}
+}
+
+/// The file named by `line` if it is an include, which is an error if it
+/// does not name one properly.
+fn parse_include(line: &str) -> Result<Option<&str>, &'static str> {
+	let Some(rest) = line.trim().strip_prefix("#include") else {
+		return Ok(None);
+	};
+	match rest
+		.trim()
+		.strip_prefix('"')
+		.and_then(|rest| rest.strip_suffix('"'))
+	{
+		Some(name) if !name.is_empty() => Ok(Some(name)),
+		_ => Err("Expect a file name in quotes after '#include'."),
+	}
+}
+
+/// A line of a script, or of a file it included.
+pub struct Location<'a> {
+	/// The included file, or `None` for the script itself.
+	file: Option<&'a Path>,
+	line: usize,
+}
+
+impl Location<'_> {
+	/// The line number within the file.
+	pub fn line(&self) -> usize {
+		self.line
+	}
+}
+
+impl Display for Location<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.file {
+			Some(file) => write!(f, "line {} of {}", self.line, file.display()),
+			None => write!(f, "line {}", self.line),
+		}
+	}
+}
+
+pub struct IncludeError {
+	location: String,
+	message: String,
+}
+
+impl Display for IncludeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "[{}] Error: {}", self.location, self.message)
+	}
+}
@@ -0,0 +1,59 @@
+use std::fmt::Display;
+
+use crate::chunk::Opcode;
+
+/// Counters kept while running with `--vm-stats`, to show where the time
+/// and memory of a program go.
+pub struct Stats {
+	/// How many times each opcode ran, indexed by its byte.
+	instructions: [u64; 256],
+	/// Strings interned on the heap; short strings live inline and cost
+	/// nothing.
+	pub strings: u64,
+	pub ropes: u64,
+	/// How many times the string or global table grew on insert.
+	pub table_resizes: u64,
+}
+
+impl Stats {
+	pub fn new() -> Self {
+		Self {
+			instructions: [0; 256],
+			strings: 0,
+			ropes: 0,
+			table_resizes: 0,
+		}
+	}
+
+	#[inline(always)]
+	pub fn executed(&mut self, opcode: u8) {
+		self.instructions[opcode as usize] += 1;
+	}
+}
+
+impl Display for Stats {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let mut instructions: Vec<_> = (0..=u8::MAX)
+			.filter_map(|byte| Opcode::try_from(byte).ok())
+			.map(|opcode| (opcode, self.instructions[opcode as usize]))
+			.filter(|&(_, count)| count > 0)
+			.collect();
+		instructions.sort_by(|(_, a), (_, b)| b.cmp(a));
+		let total: u64 = instructions.iter().map(|(_, count)| count).sum();
+
+		writeln!(f, "== vm stats ==")?;
+		writeln!(f, "{:<20} {total:>12}", "instructions")?;
+		for (opcode, count) in instructions {
+			let share = count as f64 / total as f64 * 100.0;
+			writeln!(
+				f,
+				"  {:<18} {count:>12} {share:>5.1}%",
+				format!("{opcode:?}")
+			)?;
+		}
+		writeln!(f, "{:<20} {:>12}", "allocations", self.strings + self.ropes)?;
+		writeln!(f, "  {:<18} {:>12}", "string", self.strings)?;
+		writeln!(f, "  {:<18} {:>12}", "rope", self.ropes)?;
+		write!(f, "{:<20} {:>12}", "table resizes", self.table_resizes)
+	}
+}
@@ -1,10 +1,13 @@
-use std::collections::HashMap;
 use std::hash::BuildHasher;
 use std::hash::Hasher;
 
+use indexmap::IndexMap;
+
 use crate::value::ObjString;
 
-pub type Table<V> = HashMap<ObjString, V, FNV1aBuilder>;
+/// Iterates in the order keys were first inserted, like the tree-walker's
+/// globals, rather than in an order that depends on the hashes.
+pub type Table<V> = IndexMap<ObjString, V, FNV1aBuilder>;
 
 #[derive(Default)]
 pub struct FNV1aBuilder;
@@ -0,0 +1,152 @@
+//! Events the VM reports while running with `--trace`, each on a line of
+//! its own after where in the script it happened:
+//!
+//! ```text
+//! call <script>
+//! [line 1] define global i = 0
+//! [line 2] get global i = 0
+//! [line 2] branch to line 4 not taken
+//! [line 3] jump to line 2
+//! return from <script>
+//! ```
+//!
+//! Only the kinds of events asked for are reported, unlike the trace of
+//! every instruction debug builds print.
+
+use std::{
+	fmt::Display,
+	io::{self, Write},
+};
+
+use crate::{chunk::Span, source::Source, value::Value};
+
+/// Which kinds of events are reported.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filter {
+	/// Entering and leaving the script.
+	pub calls: bool,
+	/// Defining, reading and assigning globals.
+	pub globals: bool,
+	/// Jumps, loops and conditional branches.
+	pub jumps: bool,
+}
+
+impl Filter {
+	/// Parses a comma-separated list of event kinds, such as
+	/// `calls,globals`.
+	pub fn parse(list: &str) -> Option<Self> {
+		let mut filter = Self::default();
+		for kind in list.split(',') {
+			match kind {
+				"calls" => filter.calls = true,
+				"globals" => filter.globals = true,
+				"jumps" => filter.jumps = true,
+				_ => return None,
+			}
+		}
+		Some(filter)
+	}
+}
+
+pub struct Trace {
+	filter: Filter,
+	/// `None` once writing to it failed.
+	output: Option<Box<dyn Write>>,
+	/// Where each line of the script being run came from, as errors show
+	/// it.
+	locations: Vec<String>,
+}
+
+impl Trace {
+	pub fn new(filter: Filter, output: Box<dyn Write>) -> Self {
+		Self {
+			filter,
+			output: Some(output),
+			locations: Vec::new(),
+		}
+	}
+
+	/// Starts tracing a run of `source`.
+	pub fn begin(&mut self, source: &Source) {
+		let lines = source.text().lines().count();
+		self.locations = (1..=lines)
+			.map(|line| source.location(line).to_string())
+			.collect();
+		if self.filter.calls {
+			self.write("call <script>");
+		}
+	}
+
+	/// Ends tracing a run, which finished if `returned`, or else stopped
+	/// with an error.
+	pub fn end(&mut self, returned: bool) {
+		if self.filter.calls && returned {
+			self.write("return from <script>");
+		}
+		if let Some(output) = &mut self.output {
+			if let Err(err) = output.flush() {
+				self.fail(err);
+			}
+		}
+	}
+
+	/// Reports the global `name` being defined, read or assigned, as
+	/// `action` says, with the `value` it has.
+	#[cold]
+	pub fn global(&mut self, span: Span, action: &str, name: impl Display, value: Value) {
+		if self.filter.globals {
+			self.event(span, format_args!("{action} global {name} = {value}"));
+		}
+	}
+
+	/// Reports an unconditional jump or loop from `from` to `to`.
+	#[cold]
+	pub fn jump(&mut self, from: Span, to: Span) {
+		if self.filter.jumps {
+			self.event(from, format_args!("jump to line {}", self.line(to)));
+		}
+	}
+
+	/// Reports a conditional jump from `from` to `to`, which was `taken` or
+	/// not.
+	#[cold]
+	pub fn branch(&mut self, from: Span, to: Span, taken: bool) {
+		if self.filter.jumps {
+			let taken = if taken { "taken" } else { "not taken" };
+			self.event(
+				from,
+				format_args!("branch to line {} {taken}", self.line(to)),
+			);
+		}
+	}
+
+	/// The line number `span` is on in its own file.
+	fn line(&self, span: Span) -> String {
+		match self.locations.get(span.line.wrapping_sub(1)) {
+			Some(location) => location.trim_start_matches("line ").to_string(),
+			None => span.line.to_string(),
+		}
+	}
+
+	fn event(&mut self, span: Span, event: std::fmt::Arguments) {
+		let location = match self.locations.get(span.line.wrapping_sub(1)) {
+			Some(location) => location.clone(),
+			None => format!("line {}", span.line),
+		};
+		self.write(&format!("[{location}] {event}"));
+	}
+
+	fn write(&mut self, line: &str) {
+		let Some(output) = &mut self.output else {
+			return;
+		};
+		if let Err(err) = writeln!(output, "{line}") {
+			self.fail(err);
+		}
+	}
+
+	fn fail(&mut self, err: io::Error) {
+		eprintln!("Could not write the trace, stopped tracing: {err}");
+		self.output = None;
+	}
+}
@@ -1,22 +1,62 @@
 use std::{fmt::Display, ops::Deref};
 
+use rlox_common::number::{exact_integer, integer_equals, Number};
+
 mod object;
+mod rope;
 mod string;
 
 pub use self::object::Object;
+pub use self::rope::Rope;
 use self::string::HashedString;
+pub use self::string::ShortString;
 
 pub type ObjString = Object<HashedString>;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy)]
 pub enum Value {
 	Bool(bool),
 	Double(f64),
+	// a whole number, kept exact while arithmetic on it fits; equal to the
+	// double of the same value
+	Int(i64),
 	Nil,
 	// there is two requirements:
 	// - should have the size of a pointer;
 	// - should have some ways to disambiguate between object types
 	String(ObjString),
+	// strings of up to `ShortString::CAPACITY` bytes, which never touch
+	// the heap
+	ShortString(ShortString),
+	// a concatenation whose contents are only copied out when needed
+	Rope(Object<Rope>),
+	// what a variable declared without an initializer holds until it is
+	// assigned; reading a variable never yields it
+	Uninitialized,
+}
+
+const _: () = assert!(std::mem::size_of::<Value>() == 16);
+
+impl PartialEq for Value {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(Self::Bool(a), Self::Bool(b)) => a == b,
+			(Self::Double(a), Self::Double(b)) => a == b,
+			(Self::Int(a), Self::Int(b)) => a == b,
+			(Self::Int(a), Self::Double(b)) | (Self::Double(b), Self::Int(a)) => {
+				integer_equals(*a, *b)
+			}
+			(Self::Nil, Self::Nil) => true,
+			(Self::String(a), Self::String(b)) => a == b,
+			(Self::ShortString(a), Self::ShortString(b)) => a == b,
+			// ropes are not interned, so they are compared by content
+			(Self::Rope(_), _) | (_, Self::Rope(_)) => match (self.as_str(), other.as_str()) {
+				(Some(a), Some(b)) => a == b,
+				_ => false,
+			},
+			_ => false,
+		}
+	}
 }
 
 impl From<ObjString> for Value {
@@ -27,14 +67,71 @@ impl From<ObjString> for Value {
 
 #[allow(unused)]
 impl Value {
+	/// The value of a number, however it is kept.
 	pub fn as_double(self) -> Option<f64> {
-		if let Self::Double(v) = self {
+		match self {
+			Self::Double(v) => Some(v),
+			Self::Int(v) => Some(v as f64),
+			_ => None,
+		}
+	}
+
+	pub fn as_int(self) -> Option<i64> {
+		if let Self::Int(v) = self {
 			Some(v)
 		} else {
 			None
 		}
 	}
 
+	/// Applies `op` to two integers, if both values are. `None` if they
+	/// are not, or the result does not fit in an integer.
+	#[inline]
+	pub fn integer_op(self, other: Self, op: fn(i64, i64) -> Option<i64>) -> Option<Self> {
+		op(self.as_int()?, other.as_int()?).map(Self::Int)
+	}
+
+	/// Negates a number, keeping an integer exact if it can be. Integers
+	/// have no negative zero, so `-0` is a double.
+	pub fn negate(self) -> Option<Self> {
+		match self {
+			Self::Int(v) if v != 0 => {
+				Some(v.checked_neg().map_or(Self::Double(-(v as f64)), Self::Int))
+			}
+			_ => self.as_double().map(|v| Self::Double(-v)),
+		}
+	}
+
+	/// Applies an operator that only works on integers, including doubles
+	/// that are whole numbers, or says what is wrong with the operands.
+	/// `op` gives `None` for a shift by an amount out of range.
+	pub fn bitwise_op(
+		self,
+		other: Self,
+		op: fn(i64, i64) -> Option<i64>,
+	) -> Result<Self, &'static str> {
+		let (Some(a), Some(b)) = (self.as_whole(), other.as_whole()) else {
+			return Err("Operands must be integers.");
+		};
+		op(a, b)
+			.map(Self::Int)
+			.ok_or("Shift amount must be from 0 to 63.")
+	}
+
+	/// Flips every bit of a whole number.
+	pub fn bit_not(self) -> Option<Self> {
+		self.as_whole().map(|v| Self::Int(!v))
+	}
+
+	/// The value of a whole number, however it is kept.
+	fn as_whole(self) -> Option<i64> {
+		match self {
+			Self::Int(v) => Some(v),
+			Self::Double(v) => exact_integer(v),
+			_ => None,
+		}
+	}
+
 	pub fn as_bool(self) -> Option<bool> {
 		if let Self::Bool(v) = self {
 			Some(v)
@@ -58,10 +155,19 @@ impl Value {
 	}
 
 	pub fn as_str(&self) -> Option<&str> {
-		if let Self::String(v) = self {
-			Some(v)
-		} else {
-			None
+		match self {
+			Self::String(v) => Some(v),
+			Self::ShortString(v) => Some(v),
+			Self::Rope(v) => Some(v.as_str()),
+			_ => None,
+		}
+	}
+
+	/// The length of a string value, without flattening it if it is a rope.
+	pub fn str_len(&self) -> Option<usize> {
+		match self {
+			Self::Rope(v) => Some(v.len()),
+			_ => self.as_str().map(str::len),
 		}
 	}
 
@@ -78,9 +184,13 @@ impl Display for Value {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
 			Value::Bool(b) => b.fmt(f),
-			Value::Double(d) => d.fmt(f),
+			Value::Double(d) => Number(*d).fmt(f),
+			Value::Int(i) => i.fmt(f),
 			Value::Nil => "nil".fmt(f),
 			Value::String(s) => s.fmt(f),
+			Value::ShortString(s) => s.fmt(f),
+			Value::Rope(s) => s.fmt(f),
+			Value::Uninitialized => "<uninitialized>".fmt(f),
 		}
 	}
 }
@@ -99,6 +209,13 @@ impl From<f64> for Value {
 	}
 }
 
+impl From<i64> for Value {
+	#[inline]
+	fn from(int: i64) -> Self {
+		Self::Int(int)
+	}
+}
+
 impl From<()> for Value {
 	#[inline]
 	fn from(_: ()) -> Self {
@@ -1,4 +1,5 @@
 use std::any::Any;
+use std::borrow::Borrow;
 use std::fmt::Display;
 use std::hash::Hash;
 use std::ptr::NonNull;
@@ -15,7 +16,7 @@ impl<T: 'static> From<Object<T>> for Object<dyn Any> {
 
 impl<T: ?Sized> Clone for Object<T> {
 	fn clone(&self) -> Self {
-		Self(self.0)
+		*self
 	}
 }
 
@@ -63,9 +64,16 @@ impl<T: ?Sized> Object<T> {
 	}
 }
 
+// lets the interning table be searched with the string itself
+impl<T> Borrow<T> for Object<T> {
+	fn borrow(&self) -> &T {
+		self
+	}
+}
+
 impl<T: ?Sized> PartialEq for Object<T> {
 	fn eq(&self, other: &Self) -> bool {
-		self.0 == other.0
+		std::ptr::addr_eq(self.0.as_ptr(), other.0.as_ptr())
 	}
 }
 
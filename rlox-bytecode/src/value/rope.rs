@@ -0,0 +1,63 @@
+use std::{cell::OnceCell, fmt::Display};
+
+use super::Value;
+
+/// The lazy concatenation of two string values.
+///
+/// Building a rope is constant time, which keeps `s = s + "..."` loops
+/// linear; the characters are only copied out, once, the first time the
+/// contents are needed.
+pub struct Rope {
+	left: Value,
+	right: Value,
+	len: usize,
+	flat: OnceCell<String>,
+}
+
+impl Rope {
+	/// Concatenations shorter than this are cheap enough to copy eagerly.
+	pub const MIN_LEN: usize = 64;
+
+	pub fn new(left: Value, right: Value) -> Self {
+		let len = left.str_len().unwrap() + right.str_len().unwrap();
+		let flat = OnceCell::new();
+		Self {
+			left,
+			right,
+			len,
+			flat,
+		}
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn as_str(&self) -> &str {
+		self.flat.get_or_init(|| self.flatten())
+	}
+
+	fn flatten(&self) -> String {
+		let mut flat = String::with_capacity(self.len);
+		// ropes built in a loop are as deep as the loop is long, so walk
+		// them with an explicit stack rather than recursion
+		let mut pending = vec![self.right, self.left];
+		while let Some(value) = pending.pop() {
+			match value {
+				Value::Rope(rope) if rope.flat.get().is_none() => {
+					pending.push(rope.right);
+					pending.push(rope.left);
+				}
+				value => flat.push_str(value.as_str().unwrap()),
+			}
+		}
+		flat
+	}
+}
+
+impl Display for Rope {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.as_str().fmt(f)
+	}
+}
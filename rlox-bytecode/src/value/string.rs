@@ -7,7 +7,6 @@ use std::{
 
 use crate::table::FNV1aBuilder;
 
-#[derive(PartialEq, Eq)]
 pub struct HashedString<S: BuildHasher = FNV1aBuilder> {
 	hash: u32,
 	inner: String,
@@ -16,18 +15,23 @@ pub struct HashedString<S: BuildHasher = FNV1aBuilder> {
 
 impl<B: BuildHasher + Default> From<String> for HashedString<B> {
 	fn from(inner: String) -> Self {
-		let _builder = B::default();
-		let mut hasher = _builder.build_hasher();
-		inner.hash(&mut hasher);
-		let hash = hasher.finish() as u32;
+		let hash = B::default().hash_one(&inner) as u32;
 		Self {
 			inner,
 			hash,
-			_marker: PhantomData::default(),
+			_marker: PhantomData,
 		}
 	}
 }
 
+impl<B: BuildHasher> PartialEq for HashedString<B> {
+	fn eq(&self, other: &Self) -> bool {
+		self.hash == other.hash && self.inner == other.inner
+	}
+}
+
+impl<B: BuildHasher> Eq for HashedString<B> {}
+
 impl Hash for HashedString {
 	#[inline]
 	fn hash<H: Hasher>(&self, state: &mut H) {
@@ -45,9 +49,7 @@ impl Deref for HashedString {
 
 impl<B: BuildHasher + Default> PartialEq<str> for HashedString<B> {
 	fn eq(&self, other: &str) -> bool {
-		let mut hasher = B::default().build_hasher();
-		other.hash(&mut hasher);
-		self.hash == (hasher.finish() as u32) && self.inner == other
+		self.hash == (B::default().hash_one(other) as u32) && self.inner == other
 	}
 }
 
@@ -56,3 +58,45 @@ impl Display for HashedString {
 		self.inner.fmt(f)
 	}
 }
+
+/// A string short enough to be stored inline in a [`Value`](super::Value)
+/// instead of being allocated on the heap.
+///
+/// Every string that fits is represented this way, so two strings are equal
+/// exactly when their representations (and, for heap strings, their
+/// interned pointers) are.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ShortString {
+	len: u8,
+	bytes: [u8; ShortString::CAPACITY],
+}
+
+impl ShortString {
+	/// The most bytes that still keep `Value` two words wide.
+	pub const CAPACITY: usize = 14;
+
+	pub fn new(s: &str) -> Option<Self> {
+		if s.len() > Self::CAPACITY {
+			return None;
+		}
+		let mut bytes = [0; Self::CAPACITY];
+		bytes[..s.len()].copy_from_slice(s.as_bytes());
+		let len = s.len() as u8;
+		Some(Self { len, bytes })
+	}
+}
+
+impl Deref for ShortString {
+	type Target = str;
+
+	fn deref(&self) -> &Self::Target {
+		// SAFETY: the bytes were copied from a `str` in `ShortString::new`
+		unsafe { std::str::from_utf8_unchecked(&self.bytes[..self.len as usize]) }
+	}
+}
+
+impl Display for ShortString {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		(**self).fmt(f)
+	}
+}
@@ -0,0 +1,235 @@
+use std::fmt::Display;
+
+use crate::chunk::{Chunk, Opcode};
+
+/// Why a chunk was rejected by [`verify`]. Offsets are those of the
+/// offending instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum VerifyError {
+	UnknownOpcode {
+		offset: usize,
+		byte: u8,
+	},
+	TruncatedOperand {
+		offset: usize,
+	},
+	ConstantOutOfRange {
+		offset: usize,
+		index: usize,
+	},
+	NotAName {
+		offset: usize,
+		index: usize,
+	},
+	BadJumpTarget {
+		offset: usize,
+	},
+	FallsOffEnd {
+		offset: usize,
+	},
+	StackUnderflow {
+		offset: usize,
+	},
+	LocalOutOfRange {
+		offset: usize,
+		slot: usize,
+	},
+	StackMismatch {
+		offset: usize,
+		expected: usize,
+		found: usize,
+	},
+}
+
+impl Display for VerifyError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			VerifyError::UnknownOpcode { offset, byte } => {
+				write!(f, "{offset:04}: unknown opcode {byte}")
+			}
+			VerifyError::TruncatedOperand { offset } => {
+				write!(f, "{offset:04}: operand runs past the end of the chunk")
+			}
+			VerifyError::ConstantOutOfRange { offset, index } => {
+				write!(f, "{offset:04}: constant {index} is out of range")
+			}
+			VerifyError::NotAName { offset, index } => {
+				write!(f, "{offset:04}: constant {index} is not a variable name")
+			}
+			VerifyError::BadJumpTarget { offset } => {
+				write!(f, "{offset:04}: jump does not land on an instruction")
+			}
+			VerifyError::FallsOffEnd { offset } => {
+				write!(f, "{offset:04}: execution runs past the end of the chunk")
+			}
+			VerifyError::StackUnderflow { offset } => {
+				write!(f, "{offset:04}: pops more values than are on the stack")
+			}
+			VerifyError::LocalOutOfRange { offset, slot } => {
+				write!(f, "{offset:04}: local slot {slot} is above the stack")
+			}
+			VerifyError::StackMismatch {
+				offset,
+				expected,
+				found,
+			} => write!(
+				f,
+				"{offset:04}: reached with {found} values on the stack, expected {expected}"
+			),
+		}
+	}
+}
+
+/// Checks that `chunk` is safe to hand to the VM: every byte decodes to a
+/// known instruction with all of its operands, constants and local slots
+/// exist, jumps land on the start of an instruction, and no instruction
+/// pops from an empty stack or runs off the end of the code.
+///
+/// Stack depths are tracked along every path through the chunk, so each
+/// instruction must also be reached with the same depth from all of its
+/// predecessors, as the compiler always arranges. Those depths are
+/// returned, indexed by offset, with `None` for bytes that are operands or
+/// are never reached.
+pub fn verify(chunk: &Chunk) -> Result<Vec<Option<usize>>, VerifyError> {
+	let code = chunk.code();
+
+	let mut starts = vec![false; code.len()];
+	let mut offset = 0;
+	while offset < code.len() {
+		let byte = code[offset];
+		let opcode =
+			Opcode::try_from(byte).map_err(|()| VerifyError::UnknownOpcode { offset, byte })?;
+		if offset + opcode.operand_len() >= code.len() {
+			return Err(VerifyError::TruncatedOperand { offset });
+		}
+		starts[offset] = true;
+		offset += 1 + opcode.operand_len();
+	}
+
+	if code.is_empty() {
+		return Err(VerifyError::FallsOffEnd { offset: 0 });
+	}
+	let mut depths = vec![None; code.len()];
+	depths[0] = Some(0);
+	let mut pending = vec![0];
+	while let Some(offset) = pending.pop() {
+		let depth = depths[offset].expect("pending instructions have a depth");
+		let opcode = Opcode::try_from(code[offset]).expect("checked above");
+		let operand = &code[offset + 1..offset + 1 + opcode.operand_len()];
+		let next = offset + 1 + operand.len();
+		let value = match *operand {
+			[byte] => byte as usize,
+			[high, low] => u16::from_be_bytes([high, low]) as usize,
+			[a, b, c, d] => u32::from_be_bytes([a, b, c, d]) as usize,
+			_ => 0,
+		};
+
+		let (pops, pushes) = match opcode {
+			Opcode::Constant
+			| Opcode::Nil
+			| Opcode::True
+			| Opcode::False
+			| Opcode::Uninitialized => (0, 1),
+			Opcode::GetGlobal => (0, 1),
+			Opcode::GetLocal | Opcode::GetLocalLong => (0, 1),
+			Opcode::Pop | Opcode::DefineGlobal | Opcode::Print => (1, 0),
+			Opcode::SetLocal | Opcode::SetLocalLong | Opcode::SetGlobal => (1, 1),
+			Opcode::Tuck => (2, 3),
+			Opcode::Not | Opcode::Negate | Opcode::BitNot | Opcode::CheckInitialized => (1, 1),
+			Opcode::Equal
+			| Opcode::Greater
+			| Opcode::Less
+			| Opcode::Add
+			| Opcode::Subtract
+			| Opcode::Multiply
+			| Opcode::Divide
+			| Opcode::Modulo
+			| Opcode::BitAnd
+			| Opcode::BitOr
+			| Opcode::BitXor
+			| Opcode::ShiftLeft
+			| Opcode::ShiftRight => (2, 1),
+			Opcode::JumpIfFalse | Opcode::JumpIfFalseLong => (1, 1),
+			Opcode::Jump | Opcode::JumpLong | Opcode::Loop | Opcode::LoopLong => (0, 0),
+			Opcode::Return => (0, 0),
+		};
+		if depth < pops {
+			return Err(VerifyError::StackUnderflow { offset });
+		}
+
+		match opcode {
+			Opcode::Constant
+			| Opcode::CheckInitialized
+			| Opcode::GetGlobal
+			| Opcode::DefineGlobal
+			| Opcode::SetGlobal => {
+				let Some(constant) = chunk.constants().get(value) else {
+					return Err(VerifyError::ConstantOutOfRange {
+						offset,
+						index: value,
+					});
+				};
+				if opcode != Opcode::Constant && constant.as_objstring().is_none() {
+					return Err(VerifyError::NotAName {
+						offset,
+						index: value,
+					});
+				}
+			}
+			Opcode::GetLocal | Opcode::GetLocalLong | Opcode::SetLocal | Opcode::SetLocalLong
+				if value >= depth =>
+			{
+				return Err(VerifyError::LocalOutOfRange {
+					offset,
+					slot: value,
+				});
+			}
+			_ => {}
+		}
+
+		let target = match opcode {
+			Opcode::Jump | Opcode::JumpLong | Opcode::JumpIfFalse | Opcode::JumpIfFalseLong => {
+				Some(next.checked_add(value))
+			}
+			Opcode::Loop | Opcode::LoopLong => Some(next.checked_sub(value)),
+			_ => None,
+		};
+		let falls_through = !matches!(
+			opcode,
+			Opcode::Jump | Opcode::JumpLong | Opcode::Loop | Opcode::LoopLong | Opcode::Return
+		);
+
+		let depth = depth - pops + pushes;
+		let mut successors = Vec::with_capacity(2);
+		if let Some(target) = target {
+			match target {
+				Some(target) if starts.get(target) == Some(&true) => successors.push(target),
+				_ => return Err(VerifyError::BadJumpTarget { offset }),
+			}
+		}
+		if falls_through {
+			if next == code.len() {
+				return Err(VerifyError::FallsOffEnd { offset });
+			}
+			successors.push(next);
+		}
+		for successor in successors {
+			match depths[successor] {
+				None => {
+					depths[successor] = Some(depth);
+					pending.push(successor);
+				}
+				Some(expected) if expected != depth => {
+					return Err(VerifyError::StackMismatch {
+						offset: successor,
+						expected,
+						found: depth,
+					});
+				}
+				Some(_) => {}
+			}
+		}
+	}
+
+	Ok(depths)
+}
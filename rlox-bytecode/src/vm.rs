@@ -1,84 +1,337 @@
-use std::{
-	any::Any,
-	io::{Cursor, Read, Seek, SeekFrom},
-};
+use std::{any::Any, collections::HashSet, fmt::Write as _, io::Write};
+
+use rlox_common::number::{shift_left, shift_right};
 
 use crate::{
-	chunk::{Chunk, Opcode},
+	chunk::{Chunk, Opcode, Span},
 	compiler::Compilation,
-	debug::disassemble_instruction,
+	debug::{disassemble_around, disassemble_instruction},
+	optimizer,
+	source::Source,
+	stats::Stats,
 	table::Table,
-	value::{ObjString, Object, Value},
+	trace::Trace,
+	value::{ObjString, Object, Rope, ShortString, Value},
+	verify::verify,
 };
 
-struct ChunkIter<'a> {
+#[cfg(feature = "register-vm")]
+mod register;
+#[cfg(feature = "threaded-dispatch")]
+mod threaded;
+
+/// The execution state of one running chunk: the chunk itself and the
+/// offset of the next byte to execute.
+struct CallFrame<'a> {
 	chunk: &'a Chunk,
-	ip: Cursor<&'a [u8]>,
+	code: &'a [u8],
+	ip: usize,
 }
 
-impl<'a> ChunkIter<'a> {
+impl<'a> CallFrame<'a> {
 	#[inline]
-	fn new(chunk: &'a Chunk, ip: Cursor<&'a [u8]>) -> Self {
-		Self { chunk, ip }
+	fn new(chunk: &'a Chunk) -> Self {
+		let code = chunk.code();
+		Self { chunk, code, ip: 0 }
 	}
 
-	#[inline]
+	#[inline(always)]
 	fn read_u8(&mut self) -> u8 {
-		let mut buf = [0];
-		self.ip.read_exact(&mut buf).unwrap();
-		buf[0]
+		let byte = self.code[self.ip];
+		self.ip += 1;
+		byte
 	}
 
-	#[inline]
+	#[inline(always)]
 	fn read_u16(&mut self) -> u16 {
 		let head = self.read_u8() as u16;
 		let tail = self.read_u8() as u16;
 		(head << 8) | tail
 	}
 
-	#[inline]
+	#[inline(always)]
+	fn read_u32(&mut self) -> u32 {
+		let bytes = [
+			self.read_u8(),
+			self.read_u8(),
+			self.read_u8(),
+			self.read_u8(),
+		];
+		u32::from_be_bytes(bytes)
+	}
+
+	#[inline(always)]
 	fn read_constant(&mut self) -> Value {
 		self.chunk.constants()[self.read_u8() as usize]
 	}
 
-	#[inline]
+	#[inline(always)]
 	fn read_string(&mut self) -> ObjString {
 		self.read_constant().as_objstring().unwrap()
 	}
-
-	#[inline]
-	fn as_inner(&self) -> &Chunk {
-		self.chunk
-	}
-
-	#[inline]
-	fn offset(&self) -> usize {
-		self.ip.position() as usize
-	}
 }
 
+/// What [`VM::step`] calls before each instruction, with the VM, the chunk
+/// and the offset of the instruction. Returns whether to keep pausing.
+pub type Pause<'p> = dyn FnMut(&VM, &Chunk, usize) -> bool + 'p;
+
 #[derive(Default)]
 pub struct VM {
 	stack: Vec<Value>,
 	object: Option<Object<dyn Any>>,
 	strings: Table<()>,
 	globals: Table<Value>,
+	/// How hard to optimize each compiled chunk, up to
+	/// [`optimizer::MAX_LEVEL`].
+	opt_level: u8,
+	/// Make arithmetic that produces NaN or an infinity a runtime error.
+	strict_math: bool,
+	/// Counters for `--vm-stats`, only kept when asked for.
+	stats: Option<Box<Stats>>,
+	/// Where `--trace` reports events, only when asked for.
+	trace: Option<Box<Trace>>,
+	/// Run chunks on the register-based backend instead.
+	#[cfg(feature = "register-vm")]
+	registers: bool,
+	/// Run chunks with threaded dispatch instead of the `match` loop.
+	#[cfg(feature = "threaded-dispatch")]
+	threaded: bool,
+	/// The symbols `//#if` directives find defined.
+	defined: HashSet<String>,
+	/// The top-level `const`s of the sources run so far, which later
+	/// sources can't assign either.
+	global_constants: HashSet<String>,
+	/// Warn about globals that are declared but never used.
+	warn_unused: bool,
+	/// Where the last runtime error happened, until it is shown.
+	error_span: Option<Span>,
+	/// Where `--dump-on-error` writes the state of the VM when a runtime
+	/// error stops it, only when asked for.
+	dump_on_error: Option<Box<dyn Write>>,
+	/// The value stack as the last runtime error left it, kept for the dump.
+	error_stack: Vec<Value>,
+	/// The dump of the last runtime error, until it is written after the
+	/// error itself.
+	error_dump: Option<String>,
 }
 
 impl VM {
-	pub fn intepret(&mut self, source: &str) -> Result<(), InterpretError> {
+	pub fn with_opt_level(mut self, opt_level: u8) -> Self {
+		self.opt_level = opt_level;
+		self
+	}
+
+	pub fn with_strict_math(mut self, strict_math: bool) -> Self {
+		self.strict_math = strict_math;
+		self
+	}
+
+	pub fn with_defined(mut self, defined: HashSet<String>) -> Self {
+		self.defined = defined;
+		self
+	}
+
+	pub fn with_warn_unused(mut self, warn_unused: bool) -> Self {
+		self.warn_unused = warn_unused;
+		self
+	}
+
+	pub fn with_stats(mut self, stats: bool) -> Self {
+		self.stats = stats.then(|| Box::new(Stats::new()));
+		self
+	}
+
+	pub fn with_dump_on_error(mut self, dump_on_error: Option<Box<dyn Write>>) -> Self {
+		self.dump_on_error = dump_on_error;
+		self
+	}
+
+	pub fn with_trace(mut self, trace: Option<Trace>) -> Self {
+		self.trace = trace.map(Box::new);
+		self
+	}
+
+	#[cfg(feature = "register-vm")]
+	pub fn with_registers(mut self, registers: bool) -> Self {
+		self.registers = registers;
+		self
+	}
+
+	#[cfg(feature = "threaded-dispatch")]
+	pub fn with_threaded(mut self, threaded: bool) -> Self {
+		self.threaded = threaded;
+		self
+	}
+
+	pub fn stats(&self) -> Option<&Stats> {
+		self.stats.as_deref()
+	}
+
+	/// Whether `name` was declared with `const` at the top level of a
+	/// source already run.
+	pub fn is_global_constant(&self, name: &str) -> bool {
+		self.global_constants.contains(name)
+	}
+
+	pub fn defined(&self) -> &HashSet<String> {
+		&self.defined
+	}
+
+	pub fn warn_unused(&self) -> bool {
+		self.warn_unused
+	}
+
+	pub fn intepret(&mut self, source: &Source) -> Result<(), InterpretError> {
+		self.interpret_with(source, None)
+	}
+
+	/// Like [`VM::intepret`], but calls `pause` before each instruction with
+	/// the chunk and the offset of the instruction about to run. Once `pause`
+	/// returns `false` the rest of the chunk runs without it.
+	///
+	/// Stepping always uses the `match` loop, whichever backend was asked for.
+	pub fn step(&mut self, source: &Source, pause: &mut Pause) -> Result<(), InterpretError> {
+		self.interpret_with(source, Some(pause))
+	}
+
+	fn interpret_with(
+		&mut self,
+		source: &Source,
+		pause: Option<&mut Pause>,
+	) -> Result<(), InterpretError> {
+		let result = self.compile_and_run(source, pause);
+		if let Some(span) = self.error_span.take() {
+			eprintln!("[{}] in script", source.location(span.line));
+			show_span(source, span);
+		}
+		if let (Some(dump), Some(output)) = (self.error_dump.take(), &mut self.dump_on_error) {
+			if let Err(err) = output
+				.write_all(dump.as_bytes())
+				.and_then(|()| output.flush())
+			{
+				eprintln!("Could not write the state of the VM: {err}");
+			}
+		}
+		result
+	}
+
+	fn compile_and_run(
+		&mut self,
+		source: &Source,
+		pause: Option<&mut Pause>,
+	) -> Result<(), InterpretError> {
 		let mut compilation = Compilation::new(self, source);
 
 		if !compilation.execute() {
 			return Err(InterpretError::Compile);
 		};
 
-		let chunk = compilation.into_chunk();
-		crate::debug::disassemble_chunk(&chunk, "test");
-		let ip = Cursor::new(chunk.code());
+		let global_constants = compilation.global_constants();
+		let mut chunk = compilation.into_chunk();
+		if self.opt_level > 0 {
+			chunk = optimizer::optimize(chunk);
+		}
+		// Leave stderr to the trace when there is one.
+		if self.trace.is_none() {
+			crate::debug::disassemble_chunk(&chunk, "test");
+		}
+		let depths = match verify(&chunk) {
+			Ok(depths) => depths,
+			Err(error) => {
+				eprintln!("Invalid chunk: {error}");
+				return Err(InterpretError::Compile);
+			}
+		};
+
+		if let Some(trace) = &mut self.trace {
+			trace.begin(source);
+		}
+		let result = match pause {
+			Some(pause) => self.run(&chunk, Some(pause)),
+			None => self.run_chunk(&chunk, &depths),
+		};
+		if let Some(trace) = &mut self.trace {
+			trace.end(result.is_ok());
+		}
+		if result.is_ok() {
+			for (name, constant) in global_constants {
+				if constant {
+					self.global_constants.insert(name);
+				} else {
+					self.global_constants.remove(&name);
+				}
+			}
+		}
+		if let (Err(InterpretError::Runtime), Some(span)) = (&result, self.error_span) {
+			if self.dump_on_error.is_some() {
+				self.error_dump = Some(self.dump_state(&chunk, span));
+			}
+		}
+		result
+	}
+
+	/// Describes the state a runtime error at `span` left the VM in: the
+	/// value stack, the code around the instruction that failed, every
+	/// global and the call frames.
+	fn dump_state(&mut self, chunk: &Chunk, span: Span) -> String {
+		let mut dump = String::from("== vm state ==\nstack: ");
+		write_stack(&mut dump, &std::mem::take(&mut self.error_stack));
+
+		dump.push_str("\ncode:\n");
+		// The first instruction compiled from where the error happened.
+		let offset = chunk.spans().position(|at| at == span).unwrap_or(0);
+		disassemble_around(&mut dump, chunk, offset, 3);
+
+		dump.push_str("globals:\n");
+		self.write_globals(&mut dump);
+
+		// Only the script runs until there are functions.
+		dump.push_str("frames:\n");
+		let _ = writeln!(dump, "  <script> at offset {offset:04}");
+		dump
+	}
+
+	/// The value stack, bottom first, as the debug trace shows it.
+	pub fn describe_stack(&self) -> String {
+		let mut out = String::new();
+		write_stack(&mut out, &self.stack);
+		out
+	}
+
+	/// Every global and its value, one per line, sorted by name.
+	pub fn describe_globals(&self) -> String {
+		let mut out = String::new();
+		self.write_globals(&mut out);
+		out
+	}
+
+	fn write_globals(&self, out: &mut String) {
+		let mut globals = self
+			.globals
+			.iter()
+			.map(|(name, value)| (name.to_string(), *value))
+			.collect::<Vec<_>>();
+		globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+		for (name, value) in globals {
+			let _ = writeln!(out, "  {name} = {value}");
+		}
+	}
 
-		let chunk_iter = ChunkIter::new(&chunk, ip);
-		self.run(chunk_iter)
+	/// Runs a verified `chunk` on the backend asked for.
+	fn run_chunk(
+		&mut self,
+		chunk: &Chunk,
+		_depths: &[Option<usize>],
+	) -> Result<(), InterpretError> {
+		#[cfg(feature = "register-vm")]
+		if self.registers {
+			return self.run_registers(&register::translate(chunk, _depths));
+		}
+		#[cfg(feature = "threaded-dispatch")]
+		if self.threaded {
+			return self.run_threaded(CallFrame::new(chunk));
+		}
+		self.run(chunk, None)
 	}
 
 	#[inline]
@@ -96,39 +349,132 @@ impl VM {
 		self.stack[self.stack.len() - 1 - distance]
 	}
 
+	/// Makes a string value, keeping it inline when it is short enough and
+	/// interning it on the heap otherwise.
+	pub fn make_string(&mut self, data: String) -> Value {
+		match ShortString::new(&data) {
+			Some(short) => Value::ShortString(short),
+			None => self.allocate_string(data).into(),
+		}
+	}
+
 	pub fn allocate_string(&mut self, data: String) -> ObjString {
-		match self.strings.keys().find(|&&obj| *obj == *data) {
-			Some(&obj) => obj,
+		let data = data.into();
+		match self.strings.get_key_value(&data) {
+			Some((&obj, _)) => obj,
 			None => {
-				let mut obj: ObjString = Object::new(data.into());
-				obj.set_next(self.object);
-				self.object = Some(obj.into());
+				let obj = self.track(Object::new(data));
+				let capacity = self.strings.capacity();
 				self.strings.insert(obj, ());
+				if let Some(stats) = &mut self.stats {
+					stats.strings += 1;
+					stats.table_resizes += (self.strings.capacity() != capacity) as u64;
+				}
 				obj
 			}
 		}
 	}
 
-	fn run(&mut self, mut iter: ChunkIter) -> Result<(), InterpretError> {
+	/// Concatenates two string values, deferring the copy to a rope when
+	/// the result is long.
+	fn concatenate(&mut self, a: Value, b: Value) -> Value {
+		if a.str_len().unwrap() + b.str_len().unwrap() < Rope::MIN_LEN {
+			let concatenated = [a.as_str().unwrap(), b.as_str().unwrap()].join("");
+			self.make_string(concatenated)
+		} else {
+			if let Some(stats) = &mut self.stats {
+				stats.ropes += 1;
+			}
+			Value::Rope(self.track(Object::new(Rope::new(a, b))))
+		}
+	}
+
+	/// Replaces the two values on top of the stack with `op` of them, or
+	/// says what is wrong with them. Kept out of `run`, whose loop is
+	/// slower the more code is inlined into it.
+	#[inline(never)]
+	fn bitwise_op(&mut self, op: fn(i64, i64) -> Option<i64>) -> Result<(), &'static str> {
+		let result = self.peek(1).bitwise_op(self.peek(0), op)?;
+		self.pop();
+		self.pop();
+		self.push(result);
+		Ok(())
+	}
+
+	/// Links a freshly allocated object into the list the VM frees on drop.
+	fn track<T: 'static>(&mut self, mut obj: Object<T>) -> Object<T> {
+		obj.set_next(self.object);
+		self.object = Some(obj.into());
+		obj
+	}
+
+	fn run(&mut self, chunk: &Chunk, mut pause: Option<&mut Pause>) -> Result<(), InterpretError> {
+		// Built here rather than passed in: a frame argument lives behind a
+		// pointer, so every fetch would load and store `ip` through memory.
+		let mut frame = CallFrame::new(chunk);
+
+		// Integers stay exact as long as `$integer` gives a result.
 		macro_rules! binary_op {
+            ($op:tt, $integer:expr) => {{
+                let a = self.peek(1);
+                let b = self.peek(0);
+                let result = match a.integer_op(b, $integer) {
+                    Some(result) => result,
+                    None => match (a.as_double(), b.as_double()) {
+                        (Some(a), Some(b)) => {
+                            let result = a $op b;
+                            if self.strict_math && !result.is_finite() {
+                                return Err(self.non_finite(frame.chunk.span_at(frame.ip - 1), a, stringify!($op), b, result));
+                            }
+                            result.into()
+                        }
+                        _ => {
+                            self.runtime_error(&frame, "Operands must be numbers.");
+                            return Err(InterpretError::Runtime);
+                        }
+                    },
+                };
+                self.pop();
+                self.pop();
+                self.push(result);
+            }};
+        }
+
+		macro_rules! bitwise_op {
+			($integer:expr) => {{
+				if let Err(message) = self.bitwise_op($integer) {
+					self.runtime_error(&frame, message);
+					return Err(InterpretError::Runtime);
+				}
+			}};
+		}
+
+		// Like `binary_op!`, but strings are also ordered, lexicographically.
+		macro_rules! comparison_op {
             ($op:tt) => {{
                 let a = self.peek(1);
                 let b = self.peek(0);
-                match (a.as_double(), b.as_double()) {
-                    (Some(a), Some(b)) => {
-                        self.pop();
-                        self.pop();
-                        self.push(a $op b);
-                    }
-                    _ => {
-                        self.runtime_error(&iter, "Operands must be numbers.");
-                    }
-                }
+                let result = match (a, b) {
+                    (Value::Int(a), Value::Int(b)) => a $op b,
+                    _ => match (a.as_double(), b.as_double()) {
+                        (Some(a), Some(b)) => a $op b,
+                        _ => match (a.as_str(), b.as_str()) {
+                            (Some(a), Some(b)) => a $op b,
+                            _ => {
+                                self.runtime_error(&frame, "Operands must be two numbers or two strings.");
+                                return Err(InterpretError::Runtime);
+                            }
+                        },
+                    },
+                };
+                self.pop();
+                self.pop();
+                self.push(result);
             }};
         }
 
 		loop {
-			if cfg!(debug_assertions) {
+			if cfg!(debug_assertions) && self.trace.is_none() {
 				eprint!("          ");
 				if self.stack.is_empty() {
 					eprint!("<empty stack>");
@@ -137,127 +483,306 @@ impl VM {
 					eprint!("[ {value} ]")
 				}
 				eprintln!();
-				disassemble_instruction(iter.as_inner(), iter.offset());
+				disassemble_instruction(frame.chunk, frame.ip);
+			}
+			if let Some(resume) = &mut pause {
+				if !resume(self, frame.chunk, frame.ip) {
+					pause = None;
+				}
 			}
 
-			match Opcode::try_from(iter.read_u8()) {
-				Ok(Opcode::Constant) => {
-					let constant = iter.read_constant();
+			let byte = frame.read_u8();
+			if let Some(stats) = &mut self.stats {
+				stats.executed(byte);
+			}
+			match Opcode::decode(byte) {
+				Some(Opcode::Constant) => {
+					let constant = frame.read_constant();
 					self.push(constant);
 				}
-				Ok(Opcode::Not) => {
+				Some(Opcode::Not) => {
 					let result = !self.pop().is_truthy();
 					self.push(result);
 				}
-				Ok(Opcode::Nil) => self.push(()),
-				Ok(Opcode::True) => self.push(true),
-				Ok(Opcode::False) => self.push(false),
-				Ok(Opcode::Pop) => {
+				Some(Opcode::Nil) => self.push(()),
+				Some(Opcode::True) => self.push(true),
+				Some(Opcode::False) => self.push(false),
+				Some(Opcode::Uninitialized) => self.push(Value::Uninitialized),
+				Some(Opcode::Pop) => {
 					self.pop();
 				}
-				Ok(Opcode::GetLocal) => {
-					let slot = iter.read_u8();
+				Some(Opcode::Tuck) => {
+					let b = self.pop();
+					let a = self.pop();
+					self.push(b);
+					self.push(a);
+					self.push(b);
+				}
+				Some(Opcode::GetLocal) => {
+					let slot = frame.read_u8();
 					self.push(self.stack[slot as usize]);
 				}
-				Ok(Opcode::GetGlobal) => {
-					let name = iter.read_string();
-					let value = if let Some(value) = self.globals.get(&name) {
-						*value
-					} else {
-						self.runtime_error(&iter, &format!("Undefined variable '{}'", name));
+				Some(Opcode::GetLocalLong) => {
+					let slot = frame.read_u16();
+					self.push(self.stack[slot as usize]);
+				}
+				Some(Opcode::CheckInitialized) => {
+					let name = frame.read_string();
+					if let Value::Uninitialized = self.peek(0) {
+						self.runtime_error(&frame, &format!("Uninitialized variable '{}'.", name));
 						return Err(InterpretError::Runtime);
+					}
+				}
+				Some(Opcode::GetGlobal) => {
+					let name = frame.read_string();
+					let value = match self.globals.get(&name) {
+						Some(Value::Uninitialized) => {
+							self.runtime_error(
+								&frame,
+								&format!("Uninitialized variable '{}'.", name),
+							);
+							return Err(InterpretError::Runtime);
+						}
+						Some(value) => *value,
+						None => {
+							self.runtime_error(&frame, &format!("Undefined variable '{}'", name));
+							return Err(InterpretError::Runtime);
+						}
 					};
+					if let Some(trace) = &mut self.trace {
+						trace.global(frame.chunk.span_at(frame.ip - 2), "get", name, value);
+					}
 					self.push(value);
 				}
-				Ok(Opcode::DefineGlobal) => {
-					let name = iter.read_string();
-					self.globals.insert(name, self.peek(0));
+				Some(Opcode::DefineGlobal) => {
+					let name = frame.read_string();
+					let value = self.peek(0);
+					if let Some(trace) = &mut self.trace {
+						trace.global(frame.chunk.span_at(frame.ip - 2), "define", name, value);
+					}
+					let capacity = self.globals.capacity();
+					self.globals.insert(name, value);
+					if let Some(stats) = &mut self.stats {
+						stats.table_resizes += (self.globals.capacity() != capacity) as u64;
+					}
 					self.pop();
 				}
-				Ok(Opcode::SetLocal) => {
-					let slot = iter.read_u8();
+				Some(Opcode::SetLocal) => {
+					let slot = frame.read_u8();
 					self.stack[slot as usize] = self.peek(0);
 				}
-				Ok(Opcode::SetGlobal) => {
-					let name = iter.read_string();
+				Some(Opcode::SetLocalLong) => {
+					let slot = frame.read_u16();
+					self.stack[slot as usize] = self.peek(0);
+				}
+				Some(Opcode::SetGlobal) => {
+					let name = frame.read_string();
 					let value = self.peek(0);
 					if let Some(assignee) = self.globals.get_mut(&name) {
 						*assignee = value;
 					} else {
-						self.runtime_error(&iter, &format!("Undefined variable '{}'", name));
+						self.runtime_error(&frame, &format!("Undefined variable '{}'", name));
 						return Err(InterpretError::Runtime);
 					};
+					if let Some(trace) = &mut self.trace {
+						trace.global(frame.chunk.span_at(frame.ip - 2), "set", name, value);
+					}
 				}
-				Ok(Opcode::Equal) => {
+				Some(Opcode::Equal) => {
 					let a = self.pop();
 					let b = self.pop();
 					self.push(a == b);
 				}
-				Ok(Opcode::Greater) => binary_op!(>),
-				Ok(Opcode::Less) => binary_op!(<),
-				Ok(Opcode::Add) => {
+				Some(Opcode::Greater) => comparison_op!(>),
+				Some(Opcode::Less) => comparison_op!(<),
+				Some(Opcode::Add) => {
 					let a = self.peek(1);
 					let b = self.peek(0);
-					if let (Some(a), Some(b)) = (a.as_str(), b.as_str()) {
-						let concatenated = [a, b].join("");
-						let obj = self.allocate_string(concatenated);
+					if a.str_len().is_some() && b.str_len().is_some() {
+						let value = self.concatenate(a, b);
 						self.pop();
 						self.pop();
-						self.push(obj);
+						self.push(value);
+					} else if let Some(result) = a.integer_op(b, i64::checked_add) {
+						self.pop();
+						self.pop();
+						self.push(result);
 					} else if let (Some(a), Some(b)) = (a.as_double(), b.as_double()) {
+						let result = a + b;
+						if self.strict_math && !result.is_finite() {
+							return Err(self.non_finite(
+								frame.chunk.span_at(frame.ip - 1),
+								a,
+								"+",
+								b,
+								result,
+							));
+						}
 						self.pop();
 						self.pop();
-						self.push(a + b);
+						self.push(result);
 					} else {
-						self.runtime_error(&iter, "Operands must be numbers.");
+						self.runtime_error(&frame, "Operands must be numbers.");
+						return Err(InterpretError::Runtime);
 					}
 				}
-				Ok(Opcode::Subtract) => binary_op!(-),
-				Ok(Opcode::Multiply) => binary_op!(*),
-				Ok(Opcode::Divide) => binary_op!(/),
-				Ok(Opcode::Negate) => {
-					if let Some(number) = self.peek(0).as_double() {
+				Some(Opcode::Subtract) => binary_op!(-, i64::checked_sub),
+				Some(Opcode::Multiply) => binary_op!(*, i64::checked_mul),
+				// Division always gives a double, even of two integers.
+				Some(Opcode::Divide) => binary_op!(/, |_, _| None),
+				// The remainder has the sign of the dividend, as in C.
+				Some(Opcode::Modulo) => binary_op!(%, i64::checked_rem),
+				Some(Opcode::BitAnd) => bitwise_op!(|a, b| Some(a & b)),
+				Some(Opcode::BitOr) => bitwise_op!(|a, b| Some(a | b)),
+				Some(Opcode::BitXor) => bitwise_op!(|a, b| Some(a ^ b)),
+				Some(Opcode::ShiftLeft) => bitwise_op!(shift_left),
+				Some(Opcode::ShiftRight) => bitwise_op!(shift_right),
+				Some(Opcode::Negate) => {
+					if let Some(value) = self.peek(0).negate() {
 						self.pop();
-						let value = -number;
 						self.push(value);
 					} else {
-						self.runtime_error(&iter, "Operand must be a number.");
+						self.runtime_error(&frame, "Operand must be a number.");
 						return Err(InterpretError::Runtime);
 					}
 				}
-				Ok(Opcode::Print) => {
-					println!("{}", self.pop());
+				Some(Opcode::BitNot) => {
+					if let Some(value) = self.peek(0).bit_not() {
+						self.pop();
+						self.push(value);
+					} else {
+						self.runtime_error(&frame, "Operand must be an integer.");
+						return Err(InterpretError::Runtime);
+					}
 				}
-				Ok(Opcode::Jump) => {
-					let offset = iter.read_u16();
-					iter.ip.seek(SeekFrom::Current(offset as i64)).unwrap();
+				Some(Opcode::Print) => {
+					let value = self.pop();
+					if let Err(err) = writeln!(std::io::stdout(), "{value}") {
+						self.runtime_error(&frame, &format!("Could not write output: {err}."));
+						return Err(InterpretError::Runtime);
+					}
+				}
+				Some(Opcode::Jump) => {
+					let offset = frame.read_u16();
+					self.trace_jump(&frame, 3, frame.ip + offset as usize, None);
+					frame.ip += offset as usize;
 				}
-				Ok(Opcode::JumpIfFalse) => {
-					let offset = iter.read_u16();
-					if !self.peek(0).is_truthy() {
-						iter.ip.seek(SeekFrom::Current(offset as i64)).unwrap();
+				Some(Opcode::JumpIfFalse) => {
+					let offset = frame.read_u16();
+					let taken = !self.peek(0).is_truthy();
+					self.trace_jump(&frame, 3, frame.ip + offset as usize, Some(taken));
+					if taken {
+						frame.ip += offset as usize;
 					}
 				}
-				Ok(Opcode::Loop) => {
-					let offset = iter.read_u16();
-					iter.ip.seek(SeekFrom::Current(-(offset as i64))).unwrap();
+				Some(Opcode::Loop) => {
+					let offset = frame.read_u16();
+					self.trace_jump(&frame, 3, frame.ip - offset as usize, None);
+					frame.ip -= offset as usize;
 				}
-				Ok(Opcode::Return) => {
+				Some(Opcode::JumpLong) => {
+					let offset = frame.read_u32();
+					self.trace_jump(&frame, 5, frame.ip + offset as usize, None);
+					frame.ip += offset as usize;
+				}
+				Some(Opcode::JumpIfFalseLong) => {
+					let offset = frame.read_u32();
+					let taken = !self.peek(0).is_truthy();
+					self.trace_jump(&frame, 5, frame.ip + offset as usize, Some(taken));
+					if taken {
+						frame.ip += offset as usize;
+					}
+				}
+				Some(Opcode::LoopLong) => {
+					let offset = frame.read_u32();
+					self.trace_jump(&frame, 5, frame.ip - offset as usize, None);
+					frame.ip -= offset as usize;
+				}
+				Some(Opcode::Return) => {
 					return Ok(());
 				}
-				Err(()) => return Err(InterpretError::Runtime),
+				None => return Err(InterpretError::Runtime),
 			}
 		}
 	}
 
-	fn runtime_error(&mut self, iter: &ChunkIter, message: &str) {
+	/// Reports to the trace, if there is one, the jump `frame` just read,
+	/// which is `length` bytes long, to `target`. Conditional jumps say
+	/// whether they are `taken`.
+	#[inline(always)]
+	fn trace_jump(&mut self, frame: &CallFrame, length: usize, target: usize, taken: Option<bool>) {
+		if let Some(trace) = &mut self.trace {
+			let from = frame.chunk.span_at(frame.ip - length);
+			let to = frame.chunk.span_at(target);
+			match taken {
+				Some(taken) => trace.branch(from, to, taken),
+				None => trace.jump(from, to),
+			}
+		}
+	}
+
+	/// Reports `a op b = result` when strict math forbids the result.
+	#[cold]
+	fn non_finite(&mut self, span: Span, a: f64, op: &str, b: f64, result: f64) -> InterpretError {
+		let what = if result.is_nan() {
+			"not a number"
+		} else {
+			"infinite"
+		};
+		let (a, b) = (Value::from(a), Value::from(b));
+		self.error_at(span, &format!("{a} {op} {b} is {what}."));
+		InterpretError::Runtime
+	}
+
+	fn runtime_error(&mut self, frame: &CallFrame, message: &str) {
+		self.error_at(frame.chunk.span_at(frame.ip - 1), message);
+	}
+
+	/// Reports a runtime error at `span`. Where that is in the source is
+	/// shown once `intepret` returns.
+	fn error_at(&mut self, span: Span, message: &str) {
 		eprintln!("{message}");
-		let line = iter.offset();
-		eprintln!("[line {line}] in script");
-		self.stack.clear();
+		self.error_span = Some(span);
+		if self.dump_on_error.is_some() {
+			self.error_stack = std::mem::take(&mut self.stack);
+		} else {
+			self.stack.clear();
+		}
 	}
 }
 
+/// Writes each value of `stack` in brackets, bottom first.
+fn write_stack(out: &mut String, stack: &[Value]) {
+	if stack.is_empty() {
+		out.push_str("<empty stack>");
+	}
+	for value in stack {
+		let _ = write!(out, "[ {value} ]");
+	}
+}
+
+/// Prints the source line `span` is on, underlining the span.
+/// Prints the line `span` is on, underlining the span.
+pub fn show_span(source: &Source, span: Span) {
+	let Some(text) = source.text().lines().nth(span.line.wrapping_sub(1)) else {
+		return;
+	};
+	// Keep tabs so the underline lines up however wide they are shown.
+	let indent = text
+		.chars()
+		.take(span.column - 1)
+		.map(|c| if c == '\t' { c } else { ' ' })
+		.collect::<String>();
+	let length = span.length.clamp(
+		1,
+		text.chars().count().saturating_sub(span.column - 1).max(1),
+	);
+	let line = source.location(span.line).line();
+	let gutter = line.to_string().len();
+	eprintln!("{line} | {text}");
+	eprintln!("{:gutter$} | {indent}{}", "", "^".repeat(length));
+}
+
 impl Drop for VM {
 	fn drop(&mut self) {
 		let mut maybe_obj = self.object;
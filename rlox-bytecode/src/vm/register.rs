@@ -0,0 +1,605 @@
+//! An experimental register-based backend, in the style of Lua's VM.
+//!
+//! Rather than compiling the source a second time, a verified stack chunk
+//! is translated: the value at stack depth `d` lives in register `d`, so
+//! locals keep their slots as registers. Instructions then name their
+//! operands and destination directly, which lets the translation drop
+//! most of the pushes and pops the stack machine spends its time on.
+
+use std::io::Write;
+
+use rlox_common::number::{shift_left, shift_right};
+
+use super::{InterpretError, VM};
+use crate::{
+	chunk::{Chunk, Opcode, Span},
+	value::{ObjString, Value},
+};
+
+type Register = u16;
+
+/// One register instruction. Constants and names are indices into the
+/// chunk's constants, and jump targets are indices into the program.
+#[derive(Debug, Clone, Copy)]
+enum Instruction {
+	LoadConstant {
+		dst: Register,
+		constant: u8,
+	},
+	LoadNil {
+		dst: Register,
+	},
+	LoadBool {
+		dst: Register,
+		value: bool,
+	},
+	LoadUninitialized {
+		dst: Register,
+	},
+	Move {
+		dst: Register,
+		src: Register,
+	},
+	CheckInitialized {
+		src: Register,
+		name: u8,
+	},
+	GetGlobal {
+		dst: Register,
+		name: u8,
+	},
+	DefineGlobal {
+		src: Register,
+		name: u8,
+	},
+	SetGlobal {
+		src: Register,
+		name: u8,
+	},
+	Equal {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Greater {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Less {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Add {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Subtract {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Multiply {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Divide {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Modulo {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	BitAnd {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	BitOr {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	BitXor {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	ShiftLeft {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	ShiftRight {
+		dst: Register,
+		a: Register,
+		b: Register,
+	},
+	Not {
+		dst: Register,
+		src: Register,
+	},
+	Negate {
+		dst: Register,
+		src: Register,
+	},
+	BitNot {
+		dst: Register,
+		src: Register,
+	},
+	Print {
+		src: Register,
+	},
+	Jump {
+		target: u32,
+	},
+	JumpIfFalse {
+		condition: Register,
+		target: u32,
+	},
+	Return,
+}
+
+impl Instruction {
+	/// The register a computed result is written to, which translation may
+	/// point somewhere else.
+	fn dst_mut(&mut self) -> Option<&mut Register> {
+		match self {
+			Instruction::Equal { dst, .. }
+			| Instruction::Greater { dst, .. }
+			| Instruction::Less { dst, .. }
+			| Instruction::Add { dst, .. }
+			| Instruction::Subtract { dst, .. }
+			| Instruction::Multiply { dst, .. }
+			| Instruction::Divide { dst, .. }
+			| Instruction::Modulo { dst, .. }
+			| Instruction::BitAnd { dst, .. }
+			| Instruction::BitOr { dst, .. }
+			| Instruction::BitXor { dst, .. }
+			| Instruction::ShiftLeft { dst, .. }
+			| Instruction::ShiftRight { dst, .. }
+			| Instruction::Not { dst, .. }
+			| Instruction::Negate { dst, .. }
+			| Instruction::BitNot { dst, .. } => Some(dst),
+			_ => None,
+		}
+	}
+}
+
+/// A translated chunk, which still owns the constants.
+pub struct Program<'a> {
+	chunk: &'a Chunk,
+	code: Vec<Instruction>,
+	spans: Vec<Span>,
+	registers: usize,
+}
+
+/// Translates `chunk`, given the stack depth before each of its
+/// instructions as found by [`crate::verify::verify`].
+pub fn translate<'a>(chunk: &'a Chunk, depths: &[Option<usize>]) -> Program<'a> {
+	let mut translation = Translation {
+		code: Vec::new(),
+		spans: Vec::new(),
+		span: Span::default(),
+		sources: Vec::new(),
+		computed: None,
+	};
+	let code = chunk.code();
+	let mut is_target = vec![false; code.len()];
+	let mut offset = 0;
+	while offset < code.len() {
+		if let Some(target) = chunk.jump_target(offset) {
+			is_target[target] = true;
+		}
+		offset += 1 + Opcode::try_from(code[offset]).unwrap().operand_len();
+	}
+
+	// Jumps are emitted with byte offsets as targets and patched once every
+	// instruction has its index.
+	let mut index_of = vec![0; code.len()];
+	let mut jumps = Vec::new();
+	let mut offset = 0;
+	while offset < code.len() {
+		let opcode = Opcode::try_from(code[offset]).unwrap();
+		let operand = &code[offset + 1..offset + 1 + opcode.operand_len()];
+		let value = match *operand {
+			[byte] => byte as usize,
+			[high, low] => u16::from_be_bytes([high, low]) as usize,
+			_ => 0,
+		};
+		let Some(depth) = depths[offset] else {
+			offset += 1 + operand.len();
+			continue;
+		};
+		translation.span = chunk.span_at(offset);
+		if is_target[offset] {
+			translation.materialize_all();
+			translation.computed = None;
+			translation.sources = (0..depth as Register).collect();
+		}
+		index_of[offset] = translation.code.len();
+		debug_assert_eq!(translation.sources.len(), depth);
+
+		let t = &mut translation;
+		let top = depth.wrapping_sub(1);
+		let dst = depth as Register;
+		match opcode {
+			Opcode::Constant => t.push(Instruction::LoadConstant {
+				dst,
+				constant: value as u8,
+			}),
+			Opcode::Nil => t.push(Instruction::LoadNil { dst }),
+			Opcode::True => t.push(Instruction::LoadBool { dst, value: true }),
+			Opcode::False => t.push(Instruction::LoadBool { dst, value: false }),
+			Opcode::Uninitialized => t.push(Instruction::LoadUninitialized { dst }),
+			Opcode::Pop => {
+				t.sources.pop();
+			}
+			Opcode::Tuck => {
+				// a b -> b a b, through the free register above.
+				t.materialize(top - 1);
+				t.materialize(top);
+				let (a, b) = (top as Register - 1, top as Register);
+				t.emit(Instruction::Move { dst, src: b });
+				t.emit(Instruction::Move { dst: b, src: a });
+				t.emit(Instruction::Move { dst: a, src: dst });
+				t.sources.push(dst);
+			}
+			Opcode::GetLocal | Opcode::GetLocalLong => {
+				let src = t.sources[value];
+				t.sources.push(src);
+			}
+			Opcode::CheckInitialized => t.emit(Instruction::CheckInitialized {
+				src: t.sources[top],
+				name: value as u8,
+			}),
+			Opcode::GetGlobal => t.push(Instruction::GetGlobal {
+				dst,
+				name: value as u8,
+			}),
+			Opcode::DefineGlobal => {
+				let src = t.sources.pop().unwrap();
+				t.emit(Instruction::DefineGlobal {
+					src,
+					name: value as u8,
+				});
+			}
+			Opcode::SetLocal | Opcode::SetLocalLong => t.set_local(value as Register, top),
+			Opcode::SetGlobal => t.emit(Instruction::SetGlobal {
+				src: t.sources[top],
+				name: value as u8,
+			}),
+			Opcode::Equal => t.binary(|dst, a, b| Instruction::Equal { dst, a, b }),
+			Opcode::Greater => t.binary(|dst, a, b| Instruction::Greater { dst, a, b }),
+			Opcode::Less => t.binary(|dst, a, b| Instruction::Less { dst, a, b }),
+			Opcode::Add => t.binary(|dst, a, b| Instruction::Add { dst, a, b }),
+			Opcode::Subtract => t.binary(|dst, a, b| Instruction::Subtract { dst, a, b }),
+			Opcode::Multiply => t.binary(|dst, a, b| Instruction::Multiply { dst, a, b }),
+			Opcode::Divide => t.binary(|dst, a, b| Instruction::Divide { dst, a, b }),
+			Opcode::Modulo => t.binary(|dst, a, b| Instruction::Modulo { dst, a, b }),
+			Opcode::BitAnd => t.binary(|dst, a, b| Instruction::BitAnd { dst, a, b }),
+			Opcode::BitOr => t.binary(|dst, a, b| Instruction::BitOr { dst, a, b }),
+			Opcode::BitXor => t.binary(|dst, a, b| Instruction::BitXor { dst, a, b }),
+			Opcode::ShiftLeft => t.binary(|dst, a, b| Instruction::ShiftLeft { dst, a, b }),
+			Opcode::ShiftRight => t.binary(|dst, a, b| Instruction::ShiftRight { dst, a, b }),
+			Opcode::Not => t.unary(|dst, src| Instruction::Not { dst, src }),
+			Opcode::Negate => t.unary(|dst, src| Instruction::Negate { dst, src }),
+			Opcode::BitNot => t.unary(|dst, src| Instruction::BitNot { dst, src }),
+			Opcode::Print => {
+				let src = t.sources.pop().unwrap();
+				t.emit(Instruction::Print { src });
+			}
+			Opcode::Jump
+			| Opcode::JumpLong
+			| Opcode::JumpIfFalse
+			| Opcode::JumpIfFalseLong
+			| Opcode::Loop
+			| Opcode::LoopLong => {
+				t.materialize_all();
+				let target = chunk.jump_target(offset).unwrap() as u32;
+				jumps.push(t.code.len());
+				t.emit(match opcode {
+					Opcode::JumpIfFalse | Opcode::JumpIfFalseLong => Instruction::JumpIfFalse {
+						condition: top as Register,
+						target,
+					},
+					_ => Instruction::Jump { target },
+				});
+			}
+			Opcode::Return => t.emit(Instruction::Return),
+		}
+		offset += 1 + operand.len();
+	}
+
+	let mut code = translation.code;
+	for jump in jumps {
+		match &mut code[jump] {
+			Instruction::Jump { target } | Instruction::JumpIfFalse { target, .. } => {
+				*target = index_of[*target as usize] as u32;
+			}
+			_ => unreachable!("only jumps are patched"),
+		}
+	}
+	Program {
+		chunk,
+		code,
+		spans: translation.spans,
+		registers: depths.iter().flatten().max().map_or(0, |depth| depth + 1),
+	}
+}
+
+struct Translation {
+	code: Vec<Instruction>,
+	spans: Vec<Span>,
+	span: Span,
+	/// For each value on the stack, the register that holds it. A local
+	/// read is not copied until it has to be: until then its stack slot
+	/// refers to the local's register. Such references always point below
+	/// themselves, and never to a slot that is itself a reference.
+	sources: Vec<Register>,
+	/// The index of the last instruction, if it computed the value on top
+	/// of the stack and nothing has been emitted since.
+	computed: Option<usize>,
+}
+
+impl Translation {
+	fn emit(&mut self, instruction: Instruction) {
+		self.code.push(instruction);
+		self.spans.push(self.span);
+		self.computed = None;
+	}
+
+	/// Emits an instruction that writes a new value on top of the stack.
+	fn push(&mut self, instruction: Instruction) {
+		self.sources.push(self.sources.len() as Register);
+		self.emit(instruction);
+	}
+
+	fn binary(&mut self, make: impl FnOnce(Register, Register, Register) -> Instruction) {
+		let b = self.sources.pop().unwrap();
+		let a = self.sources.pop().unwrap();
+		self.push(make(self.sources.len() as Register, a, b));
+		self.computed = Some(self.code.len() - 1);
+	}
+
+	fn unary(&mut self, make: impl FnOnce(Register, Register) -> Instruction) {
+		let src = self.sources.pop().unwrap();
+		self.push(make(self.sources.len() as Register, src));
+		self.computed = Some(self.code.len() - 1);
+	}
+
+	/// Assigns the value on top of the stack to the local in `slot`,
+	/// writing a just-computed value straight into it where that is safe.
+	fn set_local(&mut self, slot: Register, top: usize) {
+		let src = self.sources[top];
+		let referenced = |sources: &[Register]| {
+			(0..sources.len()).any(|i| i != top && i != slot as usize && sources[i] == slot)
+		};
+		match self.computed {
+			Some(index) if src == top as Register && !referenced(&self.sources) => {
+				*self.code[index].dst_mut().unwrap() = slot;
+				self.computed = None;
+			}
+			_ => {
+				for i in 0..self.sources.len() {
+					if i != top && i != slot as usize && self.sources[i] == slot {
+						self.materialize(i);
+					}
+				}
+				if src != slot {
+					self.emit(Instruction::Move { dst: slot, src });
+				}
+			}
+		}
+		self.sources[slot as usize] = slot;
+		if top != slot as usize {
+			self.sources[top] = slot;
+		}
+	}
+
+	/// Copies the value of stack slot `i` into its own register, if it is
+	/// still a reference to a local.
+	fn materialize(&mut self, i: usize) {
+		let src = self.sources[i];
+		if src as usize != i {
+			self.emit(Instruction::Move {
+				dst: i as Register,
+				src,
+			});
+			self.sources[i] = i as Register;
+		}
+	}
+
+	/// Gives every stack slot its own register, as control flow can only
+	/// merge states that agree on where each value is.
+	fn materialize_all(&mut self) {
+		for i in 0..self.sources.len() {
+			self.materialize(i);
+		}
+	}
+}
+
+impl VM {
+	pub(super) fn run_registers(&mut self, program: &Program) -> Result<(), InterpretError> {
+		if cfg!(debug_assertions) && self.trace.is_none() {
+			eprintln!("== registers ==");
+			for (index, instruction) in program.code.iter().enumerate() {
+				eprintln!("{index:04} {:4} {instruction:?}", program.spans[index].line);
+			}
+		}
+
+		let constants = program.chunk.constants();
+		let name = |index: u8| -> ObjString { constants[index as usize].as_objstring().unwrap() };
+		let mut r = vec![Value::Nil; program.registers];
+		let mut pc = 0;
+
+		macro_rules! error {
+			($message:expr) => {{
+				self.error_at(program.spans[pc - 1], $message);
+				return Err(InterpretError::Runtime);
+			}};
+		}
+
+		// Integers stay exact as long as `$integer` gives a result.
+		macro_rules! arithmetic {
+			($dst:expr, $a:expr, $b:expr, $op:tt, $integer:expr) => {{
+				let (a, b) = (r[$a as usize], r[$b as usize]);
+				match a.integer_op(b, $integer) {
+					Some(result) => r[$dst as usize] = result,
+					None => match (a.as_double(), b.as_double()) {
+						(Some(a), Some(b)) => {
+							let result = a $op b;
+							if self.strict_math && !result.is_finite() {
+								return Err(self.non_finite(program.spans[pc - 1], a, stringify!($op), b, result));
+							}
+							r[$dst as usize] = result.into();
+						}
+						_ => error!("Operands must be numbers."),
+					},
+				}
+			}};
+		}
+
+		macro_rules! bitwise {
+			($dst:expr, $a:expr, $b:expr, $integer:expr) => {{
+				match r[$a as usize].bitwise_op(r[$b as usize], $integer) {
+					Ok(result) => r[$dst as usize] = result,
+					Err(message) => error!(message),
+				}
+			}};
+		}
+
+		macro_rules! comparison {
+			($dst:expr, $a:expr, $b:expr, $op:tt) => {{
+				let (a, b) = (r[$a as usize], r[$b as usize]);
+				let result = match (a, b) {
+					(Value::Int(a), Value::Int(b)) => a $op b,
+					_ => match (a.as_double(), b.as_double()) {
+						(Some(a), Some(b)) => a $op b,
+						_ => match (a.as_str(), b.as_str()) {
+							(Some(a), Some(b)) => a $op b,
+							_ => error!("Operands must be two numbers or two strings."),
+						},
+					},
+				};
+				r[$dst as usize] = result.into();
+			}};
+		}
+
+		loop {
+			let instruction = program.code[pc];
+			pc += 1;
+			match instruction {
+				Instruction::LoadConstant { dst, constant } => {
+					r[dst as usize] = constants[constant as usize];
+				}
+				Instruction::LoadNil { dst } => r[dst as usize] = Value::Nil,
+				Instruction::LoadBool { dst, value } => r[dst as usize] = value.into(),
+				Instruction::LoadUninitialized { dst } => r[dst as usize] = Value::Uninitialized,
+				Instruction::Move { dst, src } => r[dst as usize] = r[src as usize],
+				Instruction::CheckInitialized { src, name: index } => {
+					if let Value::Uninitialized = r[src as usize] {
+						error!(&format!("Uninitialized variable '{}'.", name(index)));
+					}
+				}
+				Instruction::GetGlobal { dst, name: index } => {
+					let name = name(index);
+					r[dst as usize] = match self.globals.get(&name) {
+						Some(Value::Uninitialized) => {
+							error!(&format!("Uninitialized variable '{}'.", name))
+						}
+						Some(value) => *value,
+						None => error!(&format!("Undefined variable '{}'", name)),
+					};
+					if let Some(trace) = &mut self.trace {
+						trace.global(program.spans[pc - 1], "get", name, r[dst as usize]);
+					}
+				}
+				Instruction::DefineGlobal { src, name: index } => {
+					if let Some(trace) = &mut self.trace {
+						trace.global(
+							program.spans[pc - 1],
+							"define",
+							name(index),
+							r[src as usize],
+						);
+					}
+					self.globals.insert(name(index), r[src as usize]);
+				}
+				Instruction::SetGlobal { src, name: index } => {
+					let name = name(index);
+					match self.globals.get_mut(&name) {
+						Some(assignee) => *assignee = r[src as usize],
+						None => error!(&format!("Undefined variable '{}'", name)),
+					}
+					if let Some(trace) = &mut self.trace {
+						trace.global(program.spans[pc - 1], "set", name, r[src as usize]);
+					}
+				}
+				Instruction::Equal { dst, a, b } => {
+					r[dst as usize] = (r[a as usize] == r[b as usize]).into();
+				}
+				Instruction::Greater { dst, a, b } => comparison!(dst, a, b, >),
+				Instruction::Less { dst, a, b } => comparison!(dst, a, b, <),
+				Instruction::Add { dst, a, b } => {
+					let (x, y) = (r[a as usize], r[b as usize]);
+					if x.str_len().is_some() && y.str_len().is_some() {
+						r[dst as usize] = self.concatenate(x, y);
+					} else {
+						arithmetic!(dst, a, b, +, i64::checked_add);
+					}
+				}
+				Instruction::Subtract { dst, a, b } => arithmetic!(dst, a, b, -, i64::checked_sub),
+				Instruction::Multiply { dst, a, b } => arithmetic!(dst, a, b, *, i64::checked_mul),
+				// Division always gives a double, even of two integers.
+				Instruction::Divide { dst, a, b } => arithmetic!(dst, a, b, /, |_, _| None),
+				// The remainder has the sign of the dividend, as in C.
+				Instruction::Modulo { dst, a, b } => arithmetic!(dst, a, b, %, i64::checked_rem),
+				Instruction::BitAnd { dst, a, b } => bitwise!(dst, a, b, |a, b| Some(a & b)),
+				Instruction::BitOr { dst, a, b } => bitwise!(dst, a, b, |a, b| Some(a | b)),
+				Instruction::BitXor { dst, a, b } => bitwise!(dst, a, b, |a, b| Some(a ^ b)),
+				Instruction::ShiftLeft { dst, a, b } => bitwise!(dst, a, b, shift_left),
+				Instruction::ShiftRight { dst, a, b } => bitwise!(dst, a, b, shift_right),
+				Instruction::Not { dst, src } => {
+					r[dst as usize] = (!r[src as usize].is_truthy()).into()
+				}
+				Instruction::Negate { dst, src } => match r[src as usize].negate() {
+					Some(value) => r[dst as usize] = value,
+					None => error!("Operand must be a number."),
+				},
+				Instruction::BitNot { dst, src } => match r[src as usize].bit_not() {
+					Some(value) => r[dst as usize] = value,
+					None => error!("Operand must be an integer."),
+				},
+				Instruction::Print { src } => {
+					if let Err(err) = writeln!(std::io::stdout(), "{}", r[src as usize]) {
+						error!(&format!("Could not write output: {err}."));
+					}
+				}
+				Instruction::Jump { target } => {
+					if let Some(trace) = &mut self.trace {
+						trace.jump(program.spans[pc - 1], program.spans[target as usize]);
+					}
+					pc = target as usize;
+				}
+				Instruction::JumpIfFalse { condition, target } => {
+					let taken = !r[condition as usize].is_truthy();
+					if let Some(trace) = &mut self.trace {
+						let (from, to) = (program.spans[pc - 1], program.spans[target as usize]);
+						trace.branch(from, to, taken);
+					}
+					if taken {
+						pc = target as usize;
+					}
+				}
+				Instruction::Return => return Ok(()),
+			}
+		}
+	}
+}
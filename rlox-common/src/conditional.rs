@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+
+/// Tracks the conditional compilation directives of a script, which are
+/// lines of their own starting with `//#if`, `//#else` or `//#endif`:
+///
+/// ```text
+/// //#if DEBUG
+/// print "only with --define DEBUG";
+/// //#else
+/// print "only without it";
+/// //#endif
+/// ```
+///
+/// `//#if !NAME` takes its branch when `NAME` is not defined. Directives
+/// nest, and being comments they are ignored by anything that does not
+/// know them. Any other comment starting with `//#`, like `//### Title`,
+/// is only a comment.
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+	defined: HashSet<String>,
+	/// Each `//#if` not closed yet, innermost last.
+	open: Vec<Branch>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Branch {
+	/// Whether the code under the branch is kept, if its enclosing
+	/// branches are.
+	taken: bool,
+	/// Whether `//#else` has been seen.
+	otherwise: bool,
+}
+
+impl Conditions {
+	pub fn new(defined: HashSet<String>) -> Self {
+		Self {
+			defined,
+			open: Vec::new(),
+		}
+	}
+
+	/// Whether the code at this point of the script is kept.
+	pub fn is_active(&self) -> bool {
+		self.open.iter().all(|branch| branch.taken)
+	}
+
+	/// Applies `line` if it is a directive. Returns whether it was one, or
+	/// what is wrong with it.
+	pub fn apply(&mut self, line: &str) -> Result<bool, &'static str> {
+		let Some(directive) = line.trim_start().strip_prefix("//#") else {
+			return Ok(false);
+		};
+		let keyword = match directive.split(char::is_whitespace).next() {
+			Some(keyword @ ("if" | "else" | "endif")) => keyword,
+			_ => return Ok(false),
+		};
+		let mut words = directive[keyword.len()..].split_whitespace();
+		match (keyword, words.next(), words.next()) {
+			("if", Some(condition), None) => {
+				let taken = match condition.strip_prefix('!') {
+					Some(name) => !self.defined.contains(name),
+					None => self.defined.contains(condition),
+				};
+				self.open.push(Branch {
+					taken,
+					otherwise: false,
+				});
+			}
+			("if", ..) => return Err("Expect one symbol after '//#if'."),
+			("else", None, _) => match self.open.last_mut() {
+				Some(branch) if !branch.otherwise => {
+					branch.taken = !branch.taken;
+					branch.otherwise = true;
+				}
+				Some(_) => return Err("Already seen '//#else' for this '//#if'."),
+				None => return Err("'//#else' without '//#if'."),
+			},
+			("endif", None, _) => {
+				if self.open.pop().is_none() {
+					return Err("'//#endif' without '//#if'.");
+				}
+			}
+			("else", ..) => return Err("Expect nothing after '//#else'."),
+			_ => return Err("Expect nothing after '//#endif'."),
+		}
+		Ok(true)
+	}
+
+	/// Closes every open `//#if` at the end of the script, which is an
+	/// error if there were any.
+	pub fn finish(&mut self) -> Result<(), &'static str> {
+		if std::mem::take(&mut self.open).is_empty() {
+			Ok(())
+		} else {
+			Err("Expect '//#endif' before end of script.")
+		}
+	}
+}
@@ -0,0 +1,55 @@
+/// The reserved words of Lox. Both scanners look words up here, so they
+/// always agree on which words are names and which are not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+	And,
+	Break,
+	Class,
+	Const,
+	Else,
+	False,
+	For,
+	Fun,
+	If,
+	Is,
+	Nil,
+	Or,
+	Print,
+	Return,
+	Super,
+	This,
+	True,
+	Var,
+	While,
+	Yield,
+}
+
+static KEYWORDS: phf::Map<&'static str, Keyword> = phf::phf_map! {
+	"and" =>    Keyword::And,
+	"break" =>  Keyword::Break,
+	"class" =>  Keyword::Class,
+	"const" =>  Keyword::Const,
+	"else" =>   Keyword::Else,
+	"false" =>  Keyword::False,
+	"for" =>    Keyword::For,
+	"fun" =>    Keyword::Fun,
+	"if" =>     Keyword::If,
+	"is" =>     Keyword::Is,
+	"nil" =>    Keyword::Nil,
+	"or" =>     Keyword::Or,
+	"print" =>  Keyword::Print,
+	"return" => Keyword::Return,
+	"super" =>  Keyword::Super,
+	"this" =>   Keyword::This,
+	"true" =>   Keyword::True,
+	"var" =>    Keyword::Var,
+	"while" =>  Keyword::While,
+	"yield" =>  Keyword::Yield,
+};
+
+impl Keyword {
+	/// The keyword spelled `word`, or `None` if `word` is a name.
+	pub fn from_word(word: &str) -> Option<Self> {
+		KEYWORDS.get(word).copied()
+	}
+}
@@ -0,0 +1,5 @@
+//! Behavior both interpreters must agree on.
+
+pub mod conditional;
+pub mod keyword;
+pub mod number;
@@ -0,0 +1,56 @@
+use std::fmt::Display;
+
+/// Formats a Lox number with the fewest digits that parse back to exactly
+/// the same double, never in exponent notation and whatever the locale.
+/// What is printed is thus also a number literal of the same value, apart
+/// from `nan` and the infinities, which have none.
+pub struct Number(pub f64);
+
+impl Display for Number {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let n = self.0;
+		if n.is_nan() {
+			return f.write_str("nan");
+		}
+		if n.is_infinite() {
+			return f.write_str(if n < 0.0 { "-inf" } else { "inf" });
+		}
+		// Rust's own formatting is already the shortest that round-trips.
+		n.fmt(f)
+	}
+}
+
+/// The value of a number literal written without a decimal point, which
+/// is kept as an integer if it fits in one. Any other number is a double.
+pub fn integer_literal(lexeme: &str) -> Option<i64> {
+	if lexeme.bytes().all(|byte| byte.is_ascii_digit()) {
+		lexeme.parse().ok()
+	} else {
+		None
+	}
+}
+
+/// Whether an integer and a double are exactly the same number, which
+/// converting either to the other's type could get wrong.
+pub fn integer_equals(integer: i64, double: f64) -> bool {
+	exact_integer(double) == Some(integer)
+}
+
+/// The integer a double is exactly, if there is one.
+pub fn exact_integer(double: f64) -> Option<i64> {
+	// 2^63, the first double past the integers.
+	const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+	(double.fract() == 0.0 && (-LIMIT..LIMIT).contains(&double)).then_some(double as i64)
+}
+
+/// Shifts `value` left by `amount` bits, which must be from 0 to 63.
+/// Bits shifted past the top are lost.
+pub fn shift_left(value: i64, amount: i64) -> Option<i64> {
+	value.checked_shl(u32::try_from(amount).ok()?)
+}
+
+/// Shifts `value` right by `amount` bits, which must be from 0 to 63,
+/// keeping its sign.
+pub fn shift_right(value: i64, amount: i64) -> Option<i64> {
+	value.checked_shr(u32::try_from(amount).ok()?)
+}
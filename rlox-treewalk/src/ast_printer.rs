@@ -3,22 +3,23 @@ use std::borrow::Cow;
 use crate::expr::Expr;
 
 #[allow(unused)]
-pub fn ast_to_string(expr: &Expr) -> Cow<'_, str> {
+pub fn ast_to_string<'a>(expr: &'a Expr<'_>) -> Cow<'a, str> {
 	match expr {
 		Expr::Binary {
 			left,
 			operator,
 			right,
-		} => parenthesize(&operator.lexeme, &[left, right]).into(),
+		} => parenthesize(operator.lexeme, &[left, right]).into(),
 		Expr::Grouping(expr) => parenthesize("group", &[expr]).into(),
 		Expr::Literal(lit) => format!("{lit}").into(),
-		Expr::Unary { operator, right } => parenthesize(&operator.lexeme, &[right]).into(),
-		Expr::Variable(name) => (&name.lexeme).into(),
+		Expr::String(value) => (*value).into(),
+		Expr::Unary { operator, right } => parenthesize(operator.lexeme, &[right]).into(),
+		Expr::Variable { name, .. } => name.lexeme.into(),
 		_ => unimplemented!(),
 	}
 }
 
-fn parenthesize(name: &str, exprs: &[&Expr]) -> String {
+fn parenthesize(name: &str, exprs: &[&Expr<'_>]) -> String {
 	let mut str = String::new();
 	str.push('(');
 	str.push_str(name);
@@ -0,0 +1,154 @@
+//! Integers of any size, for `bigAdd` and `bigMul`. Scripts keep them as
+//! strings of decimal digits, so they print and compare like any other
+//! string, and only become `BigInt`s for the length of a native call.
+
+use std::{cmp::Ordering, fmt::Display};
+
+/// Each limb holds nine decimal digits, so printing needs no division.
+const BASE: u64 = 1_000_000_000;
+const DIGITS: usize = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+	negative: bool,
+	/// Least significant first, with no zero limbs at the end; zero has
+	/// none at all.
+	limbs: Vec<u32>,
+}
+
+impl BigInt {
+	/// Parses decimal digits with an optional leading `-`.
+	pub fn parse(text: &str) -> Option<Self> {
+		let (negative, digits) = match text.strip_prefix('-') {
+			Some(digits) => (true, digits),
+			None => (false, text),
+		};
+		if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		let limbs = digits
+			.as_bytes()
+			.rchunks(DIGITS)
+			.map(|chunk| {
+				chunk
+					.iter()
+					.fold(0, |limb, digit| limb * 10 + u32::from(digit - b'0'))
+			})
+			.collect();
+		Some(Self { negative, limbs }.normalized())
+	}
+
+	/// Drops leading zeros, and the sign of zero.
+	fn normalized(mut self) -> Self {
+		while self.limbs.last() == Some(&0) {
+			self.limbs.pop();
+		}
+		self.negative &= !self.limbs.is_empty();
+		self
+	}
+
+	pub fn add(&self, other: &Self) -> Self {
+		if self.negative == other.negative {
+			return Self {
+				negative: self.negative,
+				limbs: add_magnitudes(&self.limbs, &other.limbs),
+			};
+		}
+		// The signs differ, so the larger magnitude decides the sign.
+		let (larger, smaller) = match compare_magnitudes(&self.limbs, &other.limbs) {
+			Ordering::Less => (other, self),
+			_ => (self, other),
+		};
+		Self {
+			negative: larger.negative,
+			limbs: subtract_magnitudes(&larger.limbs, &smaller.limbs),
+		}
+		.normalized()
+	}
+
+	pub fn mul(&self, other: &Self) -> Self {
+		let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+		for (i, &a) in self.limbs.iter().enumerate() {
+			let mut carry = 0;
+			for (j, &b) in other.limbs.iter().enumerate() {
+				let product = limbs[i + j] + u64::from(a) * u64::from(b) + carry;
+				limbs[i + j] = product % BASE;
+				carry = product / BASE;
+			}
+			limbs[i + other.limbs.len()] += carry;
+		}
+		Self {
+			negative: self.negative != other.negative,
+			limbs: limbs.into_iter().map(|limb| limb as u32).collect(),
+		}
+		.normalized()
+	}
+}
+
+impl From<i64> for BigInt {
+	fn from(n: i64) -> Self {
+		let mut magnitude = n.unsigned_abs();
+		let mut limbs = Vec::new();
+		while magnitude > 0 {
+			limbs.push((magnitude % BASE) as u32);
+			magnitude /= BASE;
+		}
+		Self {
+			negative: n < 0,
+			limbs,
+		}
+	}
+}
+
+impl Display for BigInt {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let Some((most, rest)) = self.limbs.split_last() else {
+			return "0".fmt(f);
+		};
+		if self.negative {
+			"-".fmt(f)?;
+		}
+		most.fmt(f)?;
+		for limb in rest.iter().rev() {
+			write!(f, "{limb:0DIGITS$}")?;
+		}
+		Ok(())
+	}
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+	a.len()
+		.cmp(&b.len())
+		.then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+	let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+	let mut carry = 0;
+	for i in 0..a.len().max(b.len()) {
+		let limb = u64::from(a.get(i).copied().unwrap_or(0))
+			+ u64::from(b.get(i).copied().unwrap_or(0))
+			+ carry;
+		sum.push((limb % BASE) as u32);
+		carry = limb / BASE;
+	}
+	if carry > 0 {
+		sum.push(carry as u32);
+	}
+	sum
+}
+
+/// `a - b`, where `a` is at least as large as `b`.
+fn subtract_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+	let mut borrow = 0;
+	a.iter()
+		.enumerate()
+		.map(|(i, &limb)| {
+			let subtrahend = i64::from(b.get(i).copied().unwrap_or(0)) + borrow;
+			let mut difference = i64::from(limb) - subtrahend;
+			borrow = (difference < 0) as i64;
+			difference += borrow * BASE as i64;
+			difference as u32
+		})
+		.collect()
+}
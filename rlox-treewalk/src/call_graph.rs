@@ -0,0 +1,40 @@
+//! Which user-defined functions call which, as the resolver finds from
+//! the calls written in a script, for `--dump-callgraph`.
+
+use std::{collections::HashSet, fmt::Write, rc::Rc};
+
+use crate::token::Span;
+
+pub struct CallGraph {
+	/// Every function declared, by name and where.
+	pub functions: Vec<(Rc<str>, Span)>,
+	/// Every call, from the function declared at the first span, or from
+	/// the top level if `None`, to the one declared at the second.
+	pub calls: Vec<(Option<Span>, Span)>,
+}
+
+impl CallGraph {
+	/// The graph in Graphviz's DOT language. Functions are told apart by
+	/// where they were declared, as several may share a name, and each
+	/// pair of functions has one edge however many calls there are.
+	pub fn to_dot(&self) -> String {
+		let node = |function: Option<Span>| match function {
+			Some(span) => format!("f{}", span.offset),
+			None => "script".to_string(),
+		};
+
+		let mut dot = String::from("digraph calls {\n");
+		dot.push_str("\tscript [label=\"<script>\", shape=box];\n");
+		for (name, span) in &self.functions {
+			let _ = writeln!(dot, "\t{} [label=\"{name}\"];", node(Some(*span)));
+		}
+		let mut seen = HashSet::new();
+		for &(caller, callee) in &self.calls {
+			if seen.insert((caller, callee)) {
+				let _ = writeln!(dot, "\t{} -> {};", node(caller), node(Some(callee)));
+			}
+		}
+		dot.push_str("}\n");
+		dot
+	}
+}
@@ -0,0 +1,95 @@
+//! The names that can be completed at a point of a script, for the REPL's
+//! `:complete` and whatever else offers completions.
+
+use std::{collections::HashSet, fmt, rc::Rc};
+
+use crate::{interpreter::Interpreter, object::Object, resolver::Resolver, stmt::Stmt};
+
+pub struct Completion {
+	pub name: Rc<str>,
+	pub kind: CompletionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+	/// A local variable or parameter.
+	Local,
+	/// A `const`, local or global.
+	Constant,
+	/// A global variable.
+	Global,
+	/// A global function declared in Lox.
+	Function,
+	/// A global built into the interpreter.
+	Native,
+}
+
+impl fmt::Display for CompletionKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			CompletionKind::Local => "local",
+			CompletionKind::Constant => "constant",
+			CompletionKind::Global => "global",
+			CompletionKind::Function => "function",
+			CompletionKind::Native => "native",
+		};
+		f.write_str(name)
+	}
+}
+
+/// Every name in scope at byte `cursor` of the script parsed to
+/// `statements`, sorted by name: the locals the resolver finds there, the
+/// globals the script declares, and those `interpreter` already has. A
+/// local hides any global of the same name.
+pub fn completions<'ast>(
+	interpreter: &mut Interpreter<'ast>,
+	statements: &[Stmt<'ast>],
+	cursor: usize,
+) -> Vec<Completion> {
+	let locals = Resolver::new(interpreter).completions(statements, cursor);
+
+	let declared = statements.iter().flat_map(|statement| {
+		let names = match statement {
+			Stmt::Var { name, constant, .. } => {
+				let kind = if *constant {
+					CompletionKind::Constant
+				} else {
+					CompletionKind::Global
+				};
+				vec![(name, kind)]
+			}
+			Stmt::VarTuple { names, .. } => names
+				.iter()
+				.map(|name| (name, CompletionKind::Global))
+				.collect(),
+			Stmt::Function(function) => vec![(&function.name, CompletionKind::Function)],
+			_ => Vec::new(),
+		};
+		names.into_iter().map(|(name, kind)| Completion {
+			name: name.lexeme.into(),
+			kind,
+		})
+	});
+	let existing = interpreter.globals.iter().map(|(name, value)| {
+		let kind = match value {
+			Object::Callable(callable) if callable.is_native() => CompletionKind::Native,
+			Object::Callable(_) => CompletionKind::Function,
+			_ if interpreter.global_constants.contains(name) => CompletionKind::Constant,
+			_ => CompletionKind::Global,
+		};
+		Completion {
+			name: name.clone(),
+			kind,
+		}
+	});
+
+	let mut seen = HashSet::new();
+	let mut completions = locals
+		.into_iter()
+		.chain(declared)
+		.chain(existing)
+		.filter(|completion| seen.insert(completion.name.clone()))
+		.collect::<Vec<_>>();
+	completions.sort_by(|a, b| a.name.cmp(&b.name));
+	completions
+}
@@ -0,0 +1,83 @@
+//! Delimiter-separated tables, as RFC 4180 describes for CSV: records end
+//! at a line break, fields are quoted with `"` when they hold the
+//! delimiter, a quote or a line break, and a quote inside quotes is
+//! doubled.
+
+/// Splits `text` into records of fields. A line break at the very end
+/// does not start another record.
+pub fn parse(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, String> {
+	let mut records = Vec::new();
+	let mut record = Vec::new();
+	let mut field = String::new();
+	let mut chars = text.chars().peekable();
+	let mut line = 1;
+	// Whether anything of the current record has been read.
+	let mut in_record = false;
+	while let Some(c) = chars.next() {
+		in_record = true;
+		match c {
+			'"' if field.is_empty() => {
+				let start = line;
+				loop {
+					match chars.next() {
+						Some('"') if chars.peek() == Some(&'"') => {
+							chars.next();
+							field.push('"');
+						}
+						Some('"') => break,
+						Some(c) => {
+							line += (c == '\n') as usize;
+							field.push(c);
+						}
+						None => return Err(format!("Unterminated quoted field on line {start}.")),
+					}
+				}
+				match chars.peek() {
+					Some(&c) if c == delimiter || c == '\r' || c == '\n' => {}
+					None => {}
+					Some(_) => {
+						return Err(format!(
+							"Expect a delimiter or line break after closing quote on line {line}."
+						))
+					}
+				}
+			}
+			_ if c == delimiter => record.push(std::mem::take(&mut field)),
+			'\r' if chars.peek() == Some(&'\n') => {}
+			'\n' => {
+				record.push(std::mem::take(&mut field));
+				records.push(std::mem::take(&mut record));
+				line += 1;
+				in_record = false;
+			}
+			_ => field.push(c),
+		}
+	}
+	if in_record {
+		record.push(field);
+		records.push(record);
+	}
+	Ok(records)
+}
+
+/// Writes `records` with each field quoted only if it has to be, and a
+/// line break after every record.
+pub fn stringify(records: &[Vec<String>], delimiter: char) -> String {
+	let mut text = String::new();
+	for record in records {
+		for (i, field) in record.iter().enumerate() {
+			if i > 0 {
+				text.push(delimiter);
+			}
+			if field.contains([delimiter, '"', '\r', '\n']) {
+				text.push('"');
+				text.push_str(&field.replace('"', "\"\""));
+				text.push('"');
+			} else {
+				text.push_str(field);
+			}
+		}
+		text.push('\n');
+	}
+	text
+}
@@ -0,0 +1,68 @@
+//! Byte encodings for the `base64Encode`, `hexEncode` and similar
+//! natives, which work on the UTF-8 bytes of strings.
+
+const BASE64_ALPHABET: &[u8; 64] =
+	b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64, padded with `=`.
+pub fn base64_encode(bytes: &[u8]) -> String {
+	let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for chunk in bytes.chunks(3) {
+		let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+			group | u32::from(byte) << (16 - 8 * i)
+		});
+		for i in 0..4 {
+			if i <= chunk.len() {
+				let sextet = (group >> (18 - 6 * i)) & 0x3f;
+				encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+			} else {
+				encoded.push('=');
+			}
+		}
+	}
+	encoded
+}
+
+/// The bytes of padded standard base64, or `None` if `text` is not that.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+	let text = text.as_bytes();
+	if !text.len().is_multiple_of(4) {
+		return None;
+	}
+	let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+	for (index, chunk) in text.chunks(4).enumerate() {
+		let last = index == text.len() / 4 - 1;
+		let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+		if padding > 2 || (padding > 0 && !last) {
+			return None;
+		}
+		let mut group = 0u32;
+		for &c in &chunk[..4 - padding] {
+			let sextet = BASE64_ALPHABET.iter().position(|&a| a == c)?;
+			group = group << 6 | sextet as u32;
+		}
+		group <<= 6 * padding;
+		bytes.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+	}
+	Some(bytes)
+}
+
+/// Two lowercase hex digits per byte.
+pub fn hex_encode(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The bytes of pairs of hex digits in either case, or `None` if `text`
+/// is not that.
+pub fn hex_decode(text: &str) -> Option<Vec<u8>> {
+	if !text.len().is_multiple_of(2) {
+		return None;
+	}
+	text.as_bytes()
+		.chunks(2)
+		.map(|pair| {
+			let digit = |byte: u8| char::from(byte).to_digit(16);
+			Some((digit(pair[0])? * 16 + digit(pair[1])?) as u8)
+		})
+		.collect()
+}
@@ -1,123 +1,226 @@
 use std::{
-	cell::RefCell,
+	cell::{Cell, RefCell},
 	collections::{hash_map::Entry, HashMap},
 	rc::Rc,
 };
 
 use crate::{interpreter::RuntimeError, object::Object, token::Token};
 
-#[derive(Debug, Default, Clone)]
-pub struct EnvironmentPointer(Rc<RefCell<Environment>>);
+/// A local variable's location, as computed by the resolver: how many frames
+/// to walk up from the current one, and the variable's index in that frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+	pub depth: usize,
+	pub index: usize,
+}
+
+/// How many environments are alive, and how many have been created in
+/// total, as reported by `memoryUsage()`. Each interpreter counts its own.
+#[derive(Debug, Default)]
+pub struct EnvironmentCounts {
+	live: Cell<usize>,
+	created: Cell<usize>,
+}
 
-impl EnvironmentPointer {
-	pub fn new(enclosing: EnvironmentPointer) -> Self {
-		Self(Rc::new(RefCell::new(Environment::new(enclosing))))
+impl EnvironmentCounts {
+	/// The number of environments alive, and the number ever created.
+	pub fn get(&self) -> (usize, usize) {
+		(self.live.get(), self.created.get())
 	}
+}
 
-	#[inline]
-	pub fn define(&mut self, name: String, value: Object) {
-		self.0.borrow_mut().define(name, value);
+#[derive(Debug, Clone)]
+pub struct EnvironmentPointer<'ast>(Rc<RefCell<Environment<'ast>>>);
+
+impl<'ast> EnvironmentPointer<'ast> {
+	/// Creates an environment inside `enclosing`, counted in `counts`.
+	pub fn new(
+		enclosing: Option<EnvironmentPointer<'ast>>,
+		counts: &Rc<EnvironmentCounts>,
+	) -> Self {
+		Self(Rc::new(RefCell::new(Environment::new(enclosing, counts))))
 	}
 
 	#[inline]
-	pub fn get(&self, name: &Token) -> Result<Object, RuntimeError> {
-		self.0.borrow().get(name)
+	pub fn define(&mut self, value: Object<'ast>) {
+		self.0.borrow_mut().values.push(Some(value));
 	}
 
+	/// Adds a variable that has no value until it is assigned one.
 	#[inline]
-	pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), RuntimeError> {
-		self.0.borrow_mut().assign(name, value)
+	pub fn declare(&mut self) {
+		self.0.borrow_mut().values.push(None);
 	}
 
-	#[inline]
-	pub fn get_at(&self, distance: usize, name: &Token) -> Result<Object, RuntimeError> {
-		self.0.borrow().get_at(distance, name)
+	/// The value in `slot`, which is `None` if it has not been assigned
+	/// yet. Returns `None` if there is no such slot, which only happens if
+	/// the resolver got it wrong.
+	pub fn get_at(&self, slot: Slot) -> Option<Option<Object<'ast>>> {
+		let environment = self.0.borrow();
+		if slot.depth == 0 {
+			environment.values.get(slot.index).cloned()
+		} else {
+			environment.enclosing.as_ref()?.get_at(Slot {
+				depth: slot.depth - 1,
+				..slot
+			})
+		}
 	}
 
-	#[inline]
-	pub fn assign_at(
-		&mut self,
-		distance: usize,
-		name: &Token,
-		value: Object,
-	) -> Result<(), RuntimeError> {
-		self.0.borrow_mut().assign_at(distance, name, value)
+	/// Puts `value` in `slot`. Returns `None` if there is no such slot,
+	/// like `get_at`.
+	pub fn assign_at(&self, slot: Slot, value: Object<'ast>) -> Option<()> {
+		if slot.depth == 0 {
+			*self.0.borrow_mut().values.get_mut(slot.index)? = Some(value);
+			Some(())
+		} else {
+			self.0.borrow().enclosing.as_ref()?.assign_at(
+				Slot {
+					depth: slot.depth - 1,
+					..slot
+				},
+				value,
+			)
+		}
 	}
 }
 
-#[derive(Debug, Default)]
-struct Environment {
-	enclosing: Option<EnvironmentPointer>,
-	values: HashMap<String, Object>,
+/// A frame of local variables. Slots are handed out by the resolver in
+/// declaration order, so defining a variable is just a push.
+#[derive(Debug)]
+struct Environment<'ast> {
+	enclosing: Option<EnvironmentPointer<'ast>>,
+	/// `None` for a variable declared without an initializer and not
+	/// assigned since.
+	values: Vec<Option<Object<'ast>>>,
+	/// Where this environment is counted, until it is dropped.
+	counts: Rc<EnvironmentCounts>,
 }
 
-impl Environment {
-	pub fn new(enclosing: EnvironmentPointer) -> Self {
+impl<'ast> Environment<'ast> {
+	pub fn new(
+		enclosing: Option<EnvironmentPointer<'ast>>,
+		counts: &Rc<EnvironmentCounts>,
+	) -> Self {
+		counts.live.set(counts.live.get() + 1);
+		counts.created.set(counts.created.get() + 1);
 		Self {
-			enclosing: Some(enclosing),
-			..Default::default()
+			enclosing,
+			values: Vec::new(),
+			counts: counts.clone(),
 		}
 	}
+}
 
-	pub fn define(&mut self, name: String, value: Object) {
-		self.values.entry(name).or_insert(value);
+impl Drop for Environment<'_> {
+	fn drop(&mut self) {
+		self.counts.live.set(self.counts.live.get() - 1);
 	}
+}
 
-	pub fn get(&self, name: &Token) -> Result<Object, RuntimeError> {
-		if let Some(obj) = self.values.get(&name.lexeme) {
-			Ok(obj.clone())
-		} else if let Some(enclosing) = self.enclosing.as_ref() {
-			Ok(enclosing.get(name)?)
-		} else {
-			Err(RuntimeError::Custom(
-				name.clone(),
-				format!("Undefined variable '{}'.", name.lexeme).into(),
-			))
-		}
+/// Top-level bindings, which the resolver leaves unresolved and are looked
+/// up by name. They are kept in the order they were first defined, so
+/// listing them gives the same output every run.
+#[derive(Debug, Default, Clone)]
+pub struct Globals<'ast> {
+	/// Where each name is in `values`.
+	indices: HashMap<Rc<str>, usize>,
+	values: Vec<(Rc<str>, Option<Object<'ast>>)>,
+	/// Make `redefinition` reject names that are already defined, instead
+	/// of letting the new definition replace the old one.
+	pub strict: bool,
+}
+
+impl<'ast> Globals<'ast> {
+	pub fn define(&mut self, name: Rc<str>, value: Object<'ast>) {
+		self.insert(name, Some(value));
 	}
 
-	pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), RuntimeError> {
-		match self.values.entry(name.lexeme.to_owned()) {
-			Entry::Occupied(mut entry) => {
-				entry.insert(value);
-				Ok(())
-			}
-			Entry::Vacant(_) => {
-				if let Some(enclosing) = self.enclosing.as_mut() {
-					enclosing.assign(name, value)
-				} else {
-					Err(RuntimeError::Custom(
-						name.clone(),
-						format!("Undefined variable '{}'.", name.lexeme).into(),
-					))
-				}
+	/// Adds a global that has no value until it is assigned one.
+	pub fn declare(&mut self, name: Rc<str>) {
+		self.insert(name, None);
+	}
+
+	/// Sets the value of `name`, which keeps its place if it was already
+	/// defined and goes last otherwise.
+	fn insert(&mut self, name: Rc<str>, value: Option<Object<'ast>>) {
+		match self.indices.entry(name) {
+			Entry::Occupied(entry) => self.values[*entry.get()].1 = value,
+			Entry::Vacant(entry) => {
+				self.values.push((entry.key().clone(), value));
+				entry.insert(self.values.len() - 1);
 			}
 		}
 	}
 
-	pub fn get_at(&self, distance: usize, name: &Token) -> Result<Object, RuntimeError> {
-		if distance == 0 {
-			Ok(self.values.get(&name.lexeme).unwrap().clone())
-		} else if let Some(enclosing) = &self.enclosing {
-			enclosing.get_at(distance - 1, name)
-		} else {
-			panic!("ancestor too far")
+	/// Checks that `name` may be defined, which is always allowed unless
+	/// `strict` is set.
+	pub fn redefinition(&self, name: &Token) -> Result<(), RuntimeError<'ast>> {
+		if self.strict && self.indices.contains_key(name.lexeme) {
+			return Err(RuntimeError::at(
+				name,
+				format!("Already a global variable named '{}'.", name.lexeme).into(),
+			));
 		}
+		Ok(())
 	}
 
-	pub fn assign_at(
-		&mut self,
-		distance: usize,
-		name: &Token,
-		value: Object,
-	) -> Result<(), RuntimeError> {
-		if distance == 0 {
-			self.values.insert(name.lexeme.to_owned(), value);
-			Ok(())
-		} else if let Some(enclosing) = &mut self.enclosing {
-			enclosing.assign_at(distance - 1, name, value)
-		} else {
-			panic!("ancestor too far")
+	/// Every global that has a value, in the order they were first
+	/// defined.
+	pub fn iter(&self) -> impl Iterator<Item = (&Rc<str>, &Object<'ast>)> {
+		self.values
+			.iter()
+			.filter_map(|(name, value)| Some((name, value.as_ref()?)))
+	}
+
+	pub fn get(&self, name: &Token) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let value = self
+			.indices
+			.get(name.lexeme)
+			.map(|&index| &self.values[index].1);
+		match value {
+			Some(Some(obj)) => Ok(obj.clone()),
+			Some(None) => Err(uninitialized(name)),
+			None => Err(RuntimeError::at(
+				name,
+				format!("Undefined variable '{}'.", name.lexeme).into(),
+			)),
 		}
 	}
+
+	pub fn assign(&mut self, name: &Token, value: Object<'ast>) -> Result<(), RuntimeError<'ast>> {
+		match self.indices.get(name.lexeme) {
+			Some(&index) => {
+				self.values[index].1 = Some(value);
+				Ok(())
+			}
+			None => Err(RuntimeError::at(
+				name,
+				format!("Undefined variable '{}'.", name.lexeme).into(),
+			)),
+		}
+	}
+}
+
+/// The error for a local variable the resolver found a slot for that is
+/// not there when the code runs. That is a bug in the interpreter, but one
+/// better reported than crashed on.
+pub fn missing_slot<'ast>(name: &Token) -> RuntimeError<'ast> {
+	RuntimeError::at(
+		name,
+		format!(
+			"Internal error: no slot for local variable '{}'.",
+			name.lexeme
+		)
+		.into(),
+	)
+}
+
+/// The error for reading a variable that was declared without an
+/// initializer and has not been assigned since.
+pub fn uninitialized<'ast>(name: &Token) -> RuntimeError<'ast> {
+	RuntimeError::at(
+		name,
+		format!("Uninitialized variable '{}'.", name.lexeme).into(),
+	)
 }
@@ -1,30 +1,93 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::{literal::Literal, token::Token};
 
-pub enum Expr {
+/// Names a variable or assignment expression in the interpreter's table of
+/// resolved locals. Ids are never reused, not even by a later parse, so
+/// every line of a REPL session can share the one table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+	pub fn next() -> Self {
+		static NEXT: AtomicUsize = AtomicUsize::new(0);
+		Self(NEXT.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+/// An expression node. Nodes live in the parser's arena and refer to their
+/// children by shared reference, so the tree is freed all at once with the
+/// arena rather than node by node.
+pub enum Expr<'ast> {
 	Binary {
-		left: Box<Expr>,
-		operator: Token,
-		right: Box<Expr>,
+		left: &'ast Expr<'ast>,
+		operator: Token<'ast>,
+		right: &'ast Expr<'ast>,
 	},
 	Call {
-		callee: Box<Expr>,
-		paren: Token,
-		arguments: Vec<Expr>,
+		callee: &'ast Expr<'ast>,
+		paren: Token<'ast>,
+		arguments: &'ast [Expr<'ast>],
+	},
+	/// `object.name`, which looks `name` up in the methods of `object`'s
+	/// type.
+	Get {
+		object: &'ast Expr<'ast>,
+		name: Token<'ast>,
 	},
-	Grouping(Box<Expr>),
+	Grouping(&'ast Expr<'ast>),
+	/// `object[index]`, an element of a tuple or a character of a string.
+	Index {
+		object: &'ast Expr<'ast>,
+		bracket: Token<'ast>,
+		index: &'ast Expr<'ast>,
+	},
+	/// `...value` in a call's arguments, passing each element of the tuple
+	/// `value` as an argument of its own. Only ever built as an argument.
+	Spread {
+		ellipsis: Token<'ast>,
+		value: &'ast Expr<'ast>,
+	},
+	/// A number, boolean or `nil`.
 	Literal(Literal),
+	/// A string, its escapes already replaced. The text is in the arena
+	/// like the node, rather than in a `Literal` the arena would never
+	/// drop.
+	String(&'ast str),
+	/// `a < b < c`, which is true when every comparison is, evaluating each
+	/// operand at most once. There is one more operand than operators.
+	Comparison {
+		operands: &'ast [Expr<'ast>],
+		operators: &'ast [Token<'ast>],
+	},
 	Logical {
-		left: Box<Expr>,
-		operator: Token,
-		right: Box<Expr>,
+		left: &'ast Expr<'ast>,
+		operator: Token<'ast>,
+		right: &'ast Expr<'ast>,
 	},
 	Unary {
-		operator: Token,
-		right: Box<Expr>,
+		operator: Token<'ast>,
+		right: &'ast Expr<'ast>,
+	},
+	Variable {
+		id: ExprId,
+		name: Token<'ast>,
 	},
-	Variable(Token),
 	Assign {
-		name: Token,
-		value: Box<Expr>,
+		id: ExprId,
+		name: Token<'ast>,
+		value: &'ast Expr<'ast>,
+	},
+	/// `(a, b)`, or a bare `a, b` after `return`.
+	Tuple(&'ast [Expr<'ast>]),
+	/// `(a, b) = value`. Every target is an `Expr::Variable`.
+	AssignTuple {
+		targets: &'ast [Expr<'ast>],
+		equals: Token<'ast>,
+		value: &'ast Expr<'ast>,
 	},
+	/// Where an expression failed to parse, at `token`. Parsing goes on
+	/// past it so the rest of the script can still be resolved, but a
+	/// script with one is never run.
+	Error(Token<'ast>),
 }
@@ -0,0 +1,260 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+	environment::EnvironmentPointer,
+	expr::Expr,
+	interpreter::{Interpreter, Iteration, RuntimeError},
+	lox_callable::LoxCallable,
+	object::Object,
+	stmt::{Stmt, StmtFunction},
+	token::Token,
+};
+
+/// What calling a `fun*` returns. Each call to the generator runs its body
+/// up to the next `yield` and returns the yielded value, or nil once the
+/// body has finished, so a generator can drive a for-in loop directly.
+#[derive(Clone)]
+pub struct Generator<'ast> {
+	declaration: &'ast StmtFunction<'ast>,
+	state: Rc<RefCell<State<'ast>>>,
+}
+
+/// A paused generator body. Statements that may contain a `yield` are run
+/// step by step from an explicit stack of frames rather than by recursing
+/// into `Interpreter::execute`, so execution can stop at a `yield` and
+/// pick up from the same place on the next call.
+struct State<'ast> {
+	frames: Vec<Frame<'ast>>,
+	/// The innermost local frame of the body while it is paused.
+	environment: Option<EnvironmentPointer<'ast>>,
+}
+
+enum Frame<'ast> {
+	Statements {
+		statements: &'ast [Stmt<'ast>],
+		next: usize,
+		/// The environment to go back to once these statements are done,
+		/// if they run in a scope of their own.
+		enclosing: Option<Option<EnvironmentPointer<'ast>>>,
+	},
+	While {
+		keyword: &'ast Token<'ast>,
+		condition: &'ast Expr<'ast>,
+		body: &'ast Stmt<'ast>,
+	},
+	ForIn {
+		iteration: Iteration<'ast>,
+		body: &'ast Stmt<'ast>,
+	},
+}
+
+impl<'ast> Generator<'ast> {
+	pub fn new(
+		declaration: &'ast StmtFunction<'ast>,
+		environment: EnvironmentPointer<'ast>,
+	) -> Self {
+		Self {
+			declaration,
+			state: Rc::new(RefCell::new(State {
+				frames: vec![Frame::Statements {
+					statements: declaration.body,
+					next: 0,
+					enclosing: None,
+				}],
+				environment: Some(environment),
+			})),
+		}
+	}
+}
+
+impl std::fmt::Debug for Generator<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<generator {}>", self.declaration.name.lexeme)
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Generator<'ast> {
+	fn arity(&self) -> usize {
+		0
+	}
+
+	fn identity(&self) -> usize {
+		Rc::as_ptr(&self.state) as usize
+	}
+
+	fn is_native(&self) -> bool {
+		false
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let Ok(mut state) = self.state.try_borrow_mut() else {
+			return Err(RuntimeError::at(
+				&self.declaration.name,
+				"Generator is already running.".into(),
+			));
+		};
+		let caller = std::mem::replace(&mut intpr.environment, state.environment.take());
+		let result = state.resume(intpr);
+		state.environment = std::mem::replace(&mut intpr.environment, caller);
+		if !matches!(result, Ok(Some(_))) {
+			state.frames.clear();
+		}
+		result.map(|value| value.unwrap_or_else(|| ().into()))
+	}
+}
+
+impl<'ast> State<'ast> {
+	/// Runs until the next `yield`, returning its value, or until the body
+	/// finishes, returning `None`.
+	fn resume(
+		&mut self,
+		intpr: &mut Interpreter<'ast>,
+	) -> Result<Option<Object<'ast>>, RuntimeError<'ast>> {
+		while let Some(frame) = self.frames.last_mut() {
+			match frame {
+				Frame::Statements {
+					statements,
+					next,
+					enclosing,
+				} => {
+					let Some(statement) = (*statements).get(*next) else {
+						if let Some(enclosing) = enclosing.take() {
+							intpr.environment = enclosing;
+						}
+						self.frames.pop();
+						continue;
+					};
+					*next += 1;
+					if let Some(value) = self.step(intpr, statement)? {
+						return Ok(Some(value));
+					}
+				}
+				Frame::While {
+					keyword,
+					condition,
+					body,
+				} => {
+					let (keyword, condition, body) = (*keyword, *condition, *body);
+					let condition = intpr.evaluate(condition)?;
+					if intpr.is_truthy(&condition, keyword)? {
+						self.push(body, None);
+					} else {
+						self.frames.pop();
+					}
+				}
+				Frame::ForIn { iteration, body } => {
+					let body = *body;
+					match iteration.next(intpr)? {
+						Some(value) => {
+							let mut environment = EnvironmentPointer::new(
+								intpr.environment.clone(),
+								&intpr.environment_counts,
+							);
+							environment.define(value);
+							let enclosing = intpr.environment.replace(environment);
+							self.push(body, Some(enclosing));
+						}
+						None => {
+							self.frames.pop();
+						}
+					}
+				}
+			}
+		}
+		Ok(None)
+	}
+
+	/// Starts running `statement`. Returns the value of a `yield`, if that
+	/// is what it was.
+	fn step(
+		&mut self,
+		intpr: &mut Interpreter<'ast>,
+		statement: &'ast Stmt<'ast>,
+	) -> Result<Option<Object<'ast>>, RuntimeError<'ast>> {
+		match statement {
+			Stmt::Yield { value, .. } => return intpr.evaluate(value).map(Some),
+			Stmt::Return { value, .. } => {
+				intpr.evaluate(value)?;
+				// The caller's environment is put back by `Generator::call`.
+				self.frames.clear();
+			}
+			Stmt::Break { .. } => {
+				// Leave every frame up to and including the innermost loop,
+				// and the scopes they opened.
+				while let Some(frame) = self.frames.pop() {
+					match frame {
+						Frame::Statements {
+							enclosing: Some(enclosing),
+							..
+						} => intpr.environment = enclosing,
+						Frame::Statements { .. } => {}
+						Frame::While { .. } | Frame::ForIn { .. } => break,
+					}
+				}
+			}
+			Stmt::Block(statements) => {
+				let environment =
+					EnvironmentPointer::new(intpr.environment.clone(), &intpr.environment_counts);
+				let enclosing = intpr.environment.replace(environment);
+				self.frames.push(Frame::Statements {
+					statements,
+					next: 0,
+					enclosing: Some(enclosing),
+				});
+			}
+			Stmt::If {
+				keyword,
+				condition,
+				then_branch,
+				else_branch,
+			} => {
+				let condition = intpr.evaluate(condition)?;
+				if intpr.is_truthy(&condition, keyword)? {
+					self.push(then_branch, None);
+				} else if let Some(else_branch) = else_branch {
+					self.push(else_branch, None);
+				}
+			}
+			Stmt::While {
+				keyword,
+				condition,
+				body,
+			} => {
+				self.frames.push(Frame::While {
+					keyword,
+					condition,
+					body,
+				});
+			}
+			Stmt::ForIn {
+				keyword,
+				iterable,
+				body,
+				..
+			} => {
+				let iterable = intpr.evaluate(iterable)?;
+				let iteration = Interpreter::iterate(keyword, iterable)?;
+				self.frames.push(Frame::ForIn { iteration, body });
+			}
+			// These cannot contain a `yield` of this generator.
+			_ => intpr.execute(statement)?,
+		}
+		Ok(None)
+	}
+
+	fn push(
+		&mut self,
+		statement: &'ast Stmt<'ast>,
+		enclosing: Option<Option<EnvironmentPointer<'ast>>>,
+	) {
+		self.frames.push(Frame::Statements {
+			statements: std::slice::from_ref(statement),
+			next: 0,
+			enclosing,
+		});
+	}
+}
@@ -1,93 +1,482 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+	cmp::Ordering,
+	collections::{HashMap, HashSet},
+	io::Write,
+	rc::Rc,
+};
+
+use rlox_common::number::{exact_integer, shift_left, shift_right};
 
 use crate::{
-	environment::EnvironmentPointer, expr::Expr, literal::Literal, lox_function::LoxFunction,
-	native_functions, object::Object, stmt::Stmt, token::Token, token_type::TokenTy,
+	environment::{self, EnvironmentCounts, EnvironmentPointer, Globals, Slot},
+	expr::{Expr, ExprId},
+	literal::Literal,
+	lox_callable::LoxCallable,
+	lox_function::LoxFunction,
+	native_functions,
+	object::Object,
+	scanner,
+	stmt::Stmt,
+	token::Token,
+	token_type::TokenTy,
+	trace::Tracer,
 };
 
-pub struct Interpreter {
-	#[allow(dead_code)]
-	pub globals: EnvironmentPointer,
-	locals: HashMap<*const Expr, usize>,
-	pub environment: EnvironmentPointer,
+/// The error for the error nodes of a script that failed to parse, which
+/// is never run.
+const UNPARSED: &str = "Can't run code that failed to parse.";
+
+/// How many calls may run at once before a script is stopped for
+/// recursing too deeply.
+const MAX_CALL_DEPTH: usize = 4096;
+
+/// Local variables by name, as the resolver found them visible at some
+/// point of the program.
+pub type VisibleLocals = Rc<[(Rc<str>, Slot)]>;
+
+/// Executes syntax trees that live for `'ast`. Functions keep pointing into
+/// those trees after the statements that declared them have run, so the
+/// arena they were parsed into must outlive the interpreter.
+pub struct Interpreter<'ast> {
+	pub globals: Globals<'ast>,
+	locals: HashMap<ExprId, Slot>,
+	/// For each `locals` the resolver found called by name, the local
+	/// variables visible where it is called, by name.
+	visible_locals: HashMap<ExprId, VisibleLocals>,
+	/// The local variables visible where the running native was called,
+	/// if it is `locals` called by name.
+	call_locals: Option<VisibleLocals>,
+	/// The top-level `const`s, which the resolver keeps from being assigned
+	/// by any later line of a REPL session too.
+	pub global_constants: HashSet<Rc<str>>,
+	/// The innermost local frame, or `None` when executing top-level code.
+	pub environment: Option<EnvironmentPointer<'ast>>,
+	/// How many local environments this interpreter has alive and has
+	/// created, for `memoryUsage`.
+	pub environment_counts: Rc<EnvironmentCounts>,
+	/// Where `print`, `println` and `write` send their output.
+	output: Box<dyn Write + 'ast>,
+	/// Make arithmetic that produces NaN or an infinity a runtime error.
+	pub strict_math: bool,
+	/// Require booleans in conditions and as operands of `!`, `and` and
+	/// `or`, and make `==` and `!=` on values of different types an
+	/// error, unless one of them is nil.
+	pub strict_types: bool,
+	/// Define every top-level function before running any statement, so
+	/// top-level code can call a function declared below it. The
+	/// declarations themselves then do nothing when reached, so the
+	/// resolver rejects a function that shares its name with another
+	/// top-level declaration.
+	pub hoist_functions: bool,
+	/// Let `exec` run other programs. Off unless whoever runs the
+	/// interpreter turns it on, as `--allow-process` does.
+	pub allow_process: bool,
+	/// The functions registered with `test`, by name, in the order they
+	/// were registered, for `--test` to run.
+	pub tests: Vec<(String, Box<dyn LoxCallable<'ast> + 'ast>)>,
+	/// Where `--trace-exec` logs what runs, if it was given.
+	pub trace: Option<Tracer>,
+	/// How many calls are running.
+	call_depth: usize,
 }
 
-impl Default for Interpreter {
+impl Default for Interpreter<'_> {
 	fn default() -> Self {
-		let mut globals = EnvironmentPointer::default();
+		Self::with_output(Box::new(std::io::stdout()))
+	}
+}
+
+impl<'ast> Interpreter<'ast> {
+	pub fn with_output(output: Box<dyn Write + 'ast>) -> Self {
+		let mut globals = Globals::default();
 		globals.define(
 			"clock".into(),
 			Object::from_callable(native_functions::Clock),
 		);
-		let environment = globals.clone();
+		globals.define(
+			"println".into(),
+			Object::from_callable(native_functions::Println),
+		);
+		globals.define(
+			"write".into(),
+			Object::from_callable(native_functions::Write),
+		);
+		// Only reachable when the parser treats `print` as a plain name.
+		globals.define(
+			"print".into(),
+			Object::from_callable(native_functions::Println),
+		);
+		globals.define(
+			"readLines".into(),
+			Object::from_callable(native_functions::ReadLines),
+		);
+		globals.define(
+			"appendFile".into(),
+			Object::from_callable(native_functions::AppendFile),
+		);
+		globals.define(
+			"readAll".into(),
+			Object::from_callable(native_functions::ReadAll),
+		);
+		globals.define(
+			"withOutput".into(),
+			Object::from_callable(native_functions::WithOutput),
+		);
+		globals.define(
+			"openLines".into(),
+			Object::from_callable(native_functions::OpenLines),
+		);
+		globals.define(
+			"memoryUsage".into(),
+			Object::from_callable(native_functions::MemoryUsage),
+		);
+		globals.define(
+			"prettyPrint".into(),
+			Object::from_callable(native_functions::PrettyPrint),
+		);
+		globals.define("type".into(), Object::from_callable(native_functions::Type));
+		globals.define("bind".into(), Object::from_callable(native_functions::Bind));
+		globals.define(
+			"curry".into(),
+			Object::from_callable(native_functions::Curry),
+		);
+		globals.define("sort".into(), Object::from_callable(native_functions::Sort));
+		globals.define("test".into(), Object::from_callable(native_functions::Test));
+		globals.define(
+			"globals".into(),
+			Object::from_callable(native_functions::Globals),
+		);
+		globals.define(
+			"locals".into(),
+			Object::from_callable(native_functions::Locals),
+		);
+		globals.define(
+			"expect".into(),
+			Object::from_callable(native_functions::Expect),
+		);
+		globals.define(
+			"toFixed".into(),
+			Object::from_callable(native_functions::ToFixed),
+		);
+		globals.define(
+			"toPrecision".into(),
+			Object::from_callable(native_functions::ToPrecision),
+		);
+		globals.define("pad".into(), Object::from_callable(native_functions::Pad));
+		globals.define("exec".into(), Object::from_callable(native_functions::Exec));
+		for (name, delimiter) in [("csv", ','), ("tsv", '\t')] {
+			globals.define(
+				format!("{name}Parse").into(),
+				Object::from_callable(native_functions::TableParse(delimiter)),
+			);
+			globals.define(
+				format!("{name}Stringify").into(),
+				Object::from_callable(native_functions::TableStringify(delimiter)),
+			);
+		}
+		globals.define(
+			"pathJoin".into(),
+			Object::from_callable(native_functions::PathJoin),
+		);
+		globals.define(
+			"exists".into(),
+			Object::from_callable(native_functions::Exists),
+		);
+		globals.define(
+			"isDir".into(),
+			Object::from_callable(native_functions::IsDir),
+		);
+		globals.define(
+			"listDir".into(),
+			Object::from_callable(native_functions::ListDir),
+		);
+		globals.define(
+			"bigAdd".into(),
+			Object::from_callable(native_functions::BigAdd),
+		);
+		globals.define(
+			"bigMul".into(),
+			Object::from_callable(native_functions::BigMul),
+		);
+		for function in [
+			native_functions::StringFunction::BASE64_ENCODE,
+			native_functions::StringFunction::BASE64_DECODE,
+			native_functions::StringFunction::HEX_ENCODE,
+			native_functions::StringFunction::HEX_DECODE,
+			native_functions::StringFunction::DIRNAME,
+			native_functions::StringFunction::BASENAME,
+			#[cfg(feature = "hash")]
+			native_functions::StringFunction::MD5,
+			#[cfg(feature = "hash")]
+			native_functions::StringFunction::SHA256,
+		] {
+			globals.define(function.name.into(), Object::from_callable(function));
+		}
+		#[cfg(feature = "http")]
+		{
+			globals.define(
+				"httpGet".into(),
+				Object::from_callable(native_functions::HttpGet),
+			);
+			globals.define(
+				"httpPost".into(),
+				Object::from_callable(native_functions::HttpPost),
+			);
+		}
 		Self {
 			globals,
-			environment,
+			environment: None,
+			environment_counts: Default::default(),
 			locals: Default::default(),
+			visible_locals: Default::default(),
+			call_locals: None,
+			global_constants: Default::default(),
+			output,
+			strict_math: false,
+			strict_types: false,
+			hoist_functions: false,
+			allow_process: false,
+			tests: Vec::new(),
+			trace: None,
+			call_depth: 0,
 		}
 	}
-}
 
-impl Interpreter {
-	pub fn interpret(&mut self, statements: &[Stmt]) -> Result<()> {
+	/// Captures the global bindings, for `restore` to go back to.
+	pub fn snapshot(&self) -> EnvSnapshot<'ast> {
+		EnvSnapshot {
+			globals: self.globals.clone(),
+			constants: self.global_constants.clone(),
+		}
+	}
+
+	/// Puts the global bindings back as they were when `snapshot` was
+	/// taken: globals defined since are gone, and the others have their
+	/// old values again.
+	pub fn restore(&mut self, snapshot: &EnvSnapshot<'ast>) {
+		let strict = self.globals.strict;
+		self.globals = snapshot.globals.clone();
+		self.globals.strict = strict;
+		self.global_constants = snapshot.constants.clone();
+	}
+
+	/// Runs `statements`, returning the value of the last expression
+	/// statement among them, if there was one.
+	pub fn interpret(
+		&mut self,
+		statements: &'ast [Stmt<'ast>],
+	) -> Result<'ast, Option<Object<'ast>>> {
+		if self.hoist_functions {
+			for statement in statements {
+				if let Stmt::Function(stmt) = statement {
+					let function = LoxFunction::new(stmt, self.environment.clone());
+					self.define(&stmt.name, Object::from_callable(function))?;
+				}
+			}
+		}
+		let result = self.interpret_statements(statements);
+		if let Some(trace) = &mut self.trace {
+			trace.flush();
+		}
+		result
+	}
+
+	fn interpret_statements(
+		&mut self,
+		statements: &'ast [Stmt<'ast>],
+	) -> Result<'ast, Option<Object<'ast>>> {
+		let mut result = None;
 		for statement in statements {
-			self.execute(statement)?;
+			match statement {
+				Stmt::Expression(expr) => {
+					if let Some(trace) = &mut self.trace {
+						trace.statement(statement);
+					}
+					result = Some(self.evaluate(expr)?)
+				}
+				_ => self.execute(statement)?,
+			}
 		}
-		Ok(())
+		Ok(result)
 	}
-	pub fn execute(&mut self, stmt: &Stmt) -> Result<()> {
+
+	pub fn execute(&mut self, stmt: &'ast Stmt<'ast>) -> Result<'ast, ()> {
+		if let Some(trace) = &mut self.trace {
+			trace.statement(stmt);
+		}
 		match stmt {
 			Stmt::Expression(expr) => {
 				self.evaluate(expr)?;
 			}
-			Stmt::Print(expr) => {
-				let value = self.evaluate(expr)?;
-				println!("{value}");
+			Stmt::Print { keyword, value } => {
+				let value = self.evaluate(value)?;
+				if let Err(RuntimeError::Native(message)) = self.print(&value, true) {
+					return Err(RuntimeError::at(keyword, message));
+				}
 			}
-			Stmt::Var { name, initializer } => {
-				let value = initializer
-					.as_ref()
-					.map_or(Ok(().into()), |expr| self.evaluate(expr))?;
-				self.environment.define(name.lexeme.to_owned(), value);
+			Stmt::Var {
+				name, initializer, ..
+			} => match initializer {
+				Some(initializer) => {
+					let value = self.evaluate(initializer)?;
+					self.define(name, value)?;
+				}
+				None => self.declare(name)?,
+			},
+			Stmt::VarTuple {
+				names,
+				equals,
+				initializer,
+			} => {
+				let value = self.evaluate(initializer)?;
+				let values = Self::destructure(equals, value, names.len())?;
+				for (name, value) in names.iter().zip(values.iter()) {
+					self.define(name, value.clone())?;
+				}
 			}
 			Stmt::Block(stmts) => {
-				self.execute_block(stmts, EnvironmentPointer::new(self.environment.clone()))?;
+				let environment =
+					EnvironmentPointer::new(self.environment.clone(), &self.environment_counts);
+				self.execute_block(stmts, environment)?;
 			}
 			Stmt::If {
+				keyword,
 				condition,
 				then_branch,
 				else_branch,
 			} => {
-				if Self::is_truthy(&self.evaluate(condition)?) {
+				let condition = self.evaluate(condition)?;
+				if self.is_truthy(&condition, keyword)? {
 					self.execute(then_branch)?;
 				} else if let Some(else_branch) = else_branch {
 					self.execute(else_branch)?;
 				}
 			}
-			Stmt::While { condition, body } => {
-				while Self::is_truthy(&self.evaluate(condition)?) {
-					self.execute(body)?;
+			Stmt::While {
+				keyword,
+				condition,
+				body,
+			} => loop {
+				let condition = self.evaluate(condition)?;
+				if !self.is_truthy(&condition, keyword)? {
+					break;
+				}
+				match self.execute(body) {
+					Err(RuntimeError::Break) => break,
+					result => result?,
+				}
+			},
+			Stmt::ForIn {
+				keyword,
+				iterable,
+				body,
+				..
+			} => {
+				let iterable = self.evaluate(iterable)?;
+				let mut iteration = Self::iterate(keyword, iterable)?;
+				while let Some(value) = iteration.next(self)? {
+					let mut environment =
+						EnvironmentPointer::new(self.environment.clone(), &self.environment_counts);
+					environment.define(value);
+					match self.execute_block(std::slice::from_ref(*body), environment) {
+						Err(RuntimeError::Break) => break,
+						result => result?,
+					}
 				}
 			}
+			// Already defined by `interpret`.
+			Stmt::Function(_) if self.hoist_functions && self.environment.is_none() => {}
 			Stmt::Function(stmt) => {
-				let function = LoxFunction::new(Rc::clone(stmt), self.environment.clone());
-				self.environment
-					.define(stmt.name.lexeme.to_owned(), Object::from_callable(function));
+				let function = LoxFunction::new(stmt, self.environment.clone());
+				self.define(&stmt.name, Object::from_callable(function))?;
 			}
 			Stmt::Return { value, .. } => {
 				return Err(RuntimeError::Return(self.evaluate(value)?));
 			}
+			Stmt::Break { .. } => return Err(RuntimeError::Break),
+			// Run by the generator itself, which the resolver makes sure of.
+			Stmt::Yield { keyword, .. } => {
+				return Err(RuntimeError::at(
+					keyword,
+					"Can only yield directly in a generator's body.".into(),
+				))
+			}
+			// Scripts that failed to parse are not run.
+			Stmt::Error(token) => return Err(RuntimeError::at(token, UNPARSED.into())),
 		}
 		Ok(())
 	}
 
-	pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-		self.locals.insert(expr as *const Expr, depth);
+	/// Sends output to `output` from now on, returning the old sink.
+	pub fn replace_output(&mut self, output: Box<dyn Write + 'ast>) -> Box<dyn Write + 'ast> {
+		std::mem::replace(&mut self.output, output)
 	}
 
-	pub fn execute_block(&mut self, statements: &[Stmt], env: EnvironmentPointer) -> Result<()> {
-		let previous = self.environment.clone();
-		self.environment = env;
+	/// Writes `value` to the output sink, followed by a newline if asked.
+	/// Fails as a native would, if the sink does.
+	pub fn print(&mut self, value: &Object, newline: bool) -> Result<'ast, ()> {
+		let result = if newline {
+			writeln!(self.output, "{value}")
+		} else {
+			write!(self.output, "{value}")
+		};
+		result.map_err(|err| RuntimeError::Native(format!("Could not write output: {err}.").into()))
+	}
+
+	pub fn resolve(&mut self, id: ExprId, slot: Slot) {
+		self.locals.insert(id, slot);
+	}
+
+	/// Records the local variables visible where the `locals` read by the
+	/// variable expression `id` is called.
+	pub fn resolve_visible_locals(&mut self, id: ExprId, visible: VisibleLocals) {
+		self.visible_locals.insert(id, visible);
+	}
+
+	/// The local variables that have a value where the running native was
+	/// called, sorted by name, or `None` unless it is `locals` called by
+	/// name.
+	pub fn call_locals(&self) -> Option<Vec<(Rc<str>, Object<'ast>)>> {
+		let visible = self.call_locals.as_ref()?;
+		let environment = self.environment.as_ref();
+		Some(
+			visible
+				.iter()
+				.filter_map(|(name, slot)| Some((name.clone(), environment?.get_at(*slot)??)))
+				.collect(),
+		)
+	}
+
+	fn define(&mut self, name: &Token, value: Object<'ast>) -> Result<'ast, ()> {
+		match &mut self.environment {
+			Some(environment) => environment.define(value),
+			None => {
+				self.globals.redefinition(name)?;
+				self.globals.define(name.lexeme.into(), value);
+			}
+		}
+		Ok(())
+	}
+
+	/// Like `define`, but leaves the variable without a value, so reading it
+	/// is an error until it is assigned.
+	fn declare(&mut self, name: &Token) -> Result<'ast, ()> {
+		match &mut self.environment {
+			Some(environment) => environment.declare(),
+			None => {
+				self.globals.redefinition(name)?;
+				self.globals.declare(name.lexeme.into());
+			}
+		}
+		Ok(())
+	}
+
+	pub fn execute_block(
+		&mut self,
+		statements: &'ast [Stmt<'ast>],
+		env: EnvironmentPointer<'ast>,
+	) -> Result<'ast, ()> {
+		let previous = self.environment.replace(env);
 
 		for stmt in statements {
 			match self.execute(stmt) {
@@ -103,7 +492,7 @@ impl Interpreter {
 		Ok(())
 	}
 
-	fn evaluate(&mut self, expr: &Expr) -> Result<Object> {
+	pub fn evaluate(&mut self, expr: &'ast Expr<'ast>) -> Result<'ast, Object<'ast>> {
 		match expr {
 			Expr::Binary {
 				left,
@@ -114,87 +503,143 @@ impl Interpreter {
 				let right = self.evaluate(right)?;
 
 				match operator.ty {
-					TokenTy::Plus => {
-						if let Ok((left, right)) =
-							Self::check_number_operands(operator, &left, &right)
-						{
-							Ok((left + right).into())
-						} else {
-							match (left, right) {
-								(
-									Object::Literal(Literal::String(left)),
-									Object::Literal(Literal::String(right)),
-								) => Ok([left, right].join("").into()),
-								_ => Err(RuntimeError::Custom(
-									operator.clone(),
-									"Operands must be two numbers or two strings.".into(),
-								)),
-							}
+					TokenTy::Plus => match (&left, &right) {
+						(
+							Object::Literal(Literal::String(left)),
+							Object::Literal(Literal::String(right)),
+						) => Ok([&**left, &**right].concat().into()),
+						_ if left.as_number().is_some() && right.as_number().is_some() => {
+							self.arithmetic(operator, &left, &right, i64::checked_add, |a, b| a + b)
 						}
-					}
+						_ => Err(RuntimeError::at(
+							operator,
+							"Operands must be two numbers or two strings.".into(),
+						)),
+					},
 					TokenTy::Minus => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left - right).into())
+						self.arithmetic(operator, &left, &right, i64::checked_sub, |a, b| a - b)
 					}
 					TokenTy::Star => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left * right).into())
+						self.arithmetic(operator, &left, &right, i64::checked_mul, |a, b| a * b)
 					}
+					// Division always gives a double, even of two integers.
 					TokenTy::Slash => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left / right).into())
+						self.arithmetic(operator, &left, &right, |_, _| None, |a, b| a / b)
 					}
-					TokenTy::Greater => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left > right).into())
+					// The remainder has the sign of the dividend, as in C.
+					TokenTy::Percent => {
+						self.arithmetic(operator, &left, &right, i64::checked_rem, |a, b| a % b)
 					}
-					TokenTy::GreaterEqual => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left >= right).into())
+					TokenTy::Ampersand => {
+						Self::bitwise(operator, &left, &right, |a, b| Some(a & b))
 					}
-					TokenTy::Less => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left < right).into())
-					}
-					TokenTy::LessEqual => {
-						let (left, right) = Self::check_number_operands(operator, &left, &right)?;
-						Ok((left <= right).into())
+					TokenTy::Pipe => Self::bitwise(operator, &left, &right, |a, b| Some(a | b)),
+					TokenTy::Caret => Self::bitwise(operator, &left, &right, |a, b| Some(a ^ b)),
+					TokenTy::LessLess => Self::bitwise(operator, &left, &right, shift_left),
+					TokenTy::GreaterGreater => Self::bitwise(operator, &left, &right, shift_right),
+					TokenTy::Greater
+					| TokenTy::GreaterEqual
+					| TokenTy::Less
+					| TokenTy::LessEqual => Ok(Self::compare(operator, &left, &right)?.into()),
+					TokenTy::Is => Self::is_type(operator, &left, &right),
+					TokenTy::EqualEqual => Ok(self.is_equal(operator, left, right)?.into()),
+					TokenTy::BangEqual => Ok((!self.is_equal(operator, left, right)?).into()),
+					_ => Err(unknown_operator(operator)),
+				}
+			}
+			Expr::Comparison {
+				operands,
+				operators,
+			} => {
+				let mut left = self.evaluate(&operands[0])?;
+				for (operator, right) in operators.iter().zip(&operands[1..]) {
+					let right = self.evaluate(right)?;
+					if !Self::compare(operator, &left, &right)? {
+						return Ok(false.into());
 					}
-					TokenTy::EqualEqual => Ok(Self::is_equal(left, right).into()),
-					TokenTy::BangEqual => Ok((!Self::is_equal(left, right)).into()),
-					_ => unreachable!(),
+					left = right;
+				}
+				Ok(true.into())
+			}
+			Expr::Get { object, name } => {
+				let object = self.evaluate(object)?;
+				match native_functions::method(&object, name.lexeme) {
+					Some(method) => Ok(method),
+					None => Err(RuntimeError::at(
+						name,
+						format!(
+							"Undefined method '{}' for {}.",
+							name.lexeme,
+							object.type_name()
+						)
+						.into(),
+					)),
 				}
 			}
 			Expr::Grouping(expr) => self.evaluate(expr),
+			Expr::Index {
+				object,
+				bracket,
+				index,
+			} => {
+				let object = self.evaluate(object)?;
+				let index = self.evaluate(index)?;
+				Self::index(bracket, &object, &index)
+			}
+			// The parser only builds spreads as call arguments.
+			Expr::Spread { ellipsis, .. } => Err(RuntimeError::at(
+				ellipsis,
+				"Can only spread call arguments.".into(),
+			)),
 			Expr::Literal(lit) => Ok(Object::Literal(lit.clone())),
+			Expr::String(value) => Ok(Object::Literal(Literal::String((*value).into()))),
 			Expr::Unary { operator, right } => {
 				let right = self.evaluate(right)?;
 				match operator.ty {
-					TokenTy::Minus => {
-						let right = Self::check_number_operand(operator, &right)?;
-						Ok((-right).into())
-					}
-					TokenTy::Bang => {
-						let right = Self::is_truthy(&right);
-						Ok((!right).into())
-					}
-					_ => unreachable!(),
+					// Integers have no negative zero, so `-0` is a double.
+					TokenTy::Minus => match right {
+						Object::Literal(Literal::Integer(n)) if n != 0 => {
+							Ok(n.checked_neg().map_or((-(n as f64)).into(), Object::from))
+						}
+						_ => Ok((-Self::check_number_operand(operator, &right)?).into()),
+					},
+					TokenTy::Bang => Ok((!self.is_truthy(&right, operator)?).into()),
+					TokenTy::Tilde => Ok((!Self::check_integer_operand(operator, &right)?).into()),
+					_ => Err(unknown_operator(operator)),
 				}
 			}
-			Expr::Variable(name) => self.look_up_variable(name, expr),
-			Expr::Assign { name, value } => {
+			Expr::Variable { id, name } => self.look_up_variable(*id, name),
+			Expr::Assign { id, name, value } => {
 				let value = self.evaluate(value)?;
+				self.assign_variable(*id, name, value.clone())?;
+				Ok(value)
+			}
+			Expr::Tuple(elements) => Ok(Object::Tuple(
+				elements
+					.iter()
+					.map(|element| self.evaluate(element))
+					.collect::<Result<'ast, _>>()?,
+			)),
+			Expr::AssignTuple {
+				targets,
+				equals,
+				value,
+			} => {
+				let value = self.evaluate(value)?;
+				let values = Self::destructure(equals, value.clone(), targets.len())?;
 
-				match self.locals.get(&(expr as *const _)) {
-					Some(&distance) => {
-						self.environment.assign_at(distance, name, value.clone())?;
-					}
-					None => {
-						self.globals.assign(name, value.clone())?;
-					}
+				for (target, value) in targets.iter().zip(values.iter()) {
+					let Expr::Variable { id, name } = target else {
+						return Err(RuntimeError::at(
+							equals,
+							"Invalid assignment target.".into(),
+						));
+					};
+					self.assign_variable(*id, name, value.clone())?;
 				}
 				Ok(value)
 			}
+			Expr::Error(token) => Err(RuntimeError::at(token, UNPARSED.into())),
 			Expr::Logical {
 				left,
 				operator,
@@ -202,77 +647,301 @@ impl Interpreter {
 			} => {
 				let left = self.evaluate(left)?;
 
-				if operator.ty == TokenTy::Or {
-					if Self::is_truthy(&left) {
-						return Ok(left);
-					}
-				} else if !Self::is_truthy(&left) {
+				if self.is_truthy(&left, operator)? == (operator.ty == TokenTy::Or) {
 					return Ok(left);
 				}
 
 				self.evaluate(right)
 			}
 			Expr::Call {
-				callee,
+				callee: callee_expr,
 				paren,
 				arguments,
 			} => {
-				let callee = self.evaluate(callee)?;
+				let callee = self.evaluate(callee_expr)?;
 
-				let arguments = arguments
-					.iter()
-					.map(|arg| self.evaluate(arg))
-					.collect::<Result<Vec<_>>>()?;
+				let mut values = Vec::with_capacity(arguments.len());
+				for argument in *arguments {
+					let Expr::Spread { ellipsis, value } = argument else {
+						values.push(self.evaluate(argument)?);
+						continue;
+					};
+					match self.evaluate(value)? {
+						Object::Tuple(elements) => values.extend(elements.iter().cloned()),
+						value => {
+							return Err(RuntimeError::at(
+								ellipsis,
+								format!(
+									"Can only spread tuples, got {} {}.",
+									value.type_name(),
+									value
+								)
+								.into(),
+							))
+						}
+					}
+				}
+				let arguments = values;
 
 				if let Object::Callable(function) = callee {
-					if arguments.len() == function.arity() {
-						Ok(function.call(self, arguments)?)
+					if self.call_depth == MAX_CALL_DEPTH {
+						return Err(RuntimeError::at(paren, "Stack overflow.".into()));
+					}
+					let arity = function.arity();
+					if arguments.len() == arity || (function.variadic() && arguments.len() > arity)
+					{
+						let visible = match callee_expr {
+							Expr::Variable { id, .. } if !self.visible_locals.is_empty() => {
+								self.visible_locals.get(id).cloned()
+							}
+							_ => None,
+						};
+						let outer = std::mem::replace(&mut self.call_locals, visible);
+						let traced = (self.trace.as_mut())
+							.map(|trace| trace.enter(paren, &function, &arguments));
+						self.call_depth += 1;
+						let result = function.call(self, arguments);
+						self.call_depth -= 1;
+						if let (Some(trace), Some(logged)) = (&mut self.trace, traced) {
+							trace.exit(&function, &result, logged);
+						}
+						self.call_locals = outer;
+						match result {
+							Err(RuntimeError::Native(message)) => {
+								Err(RuntimeError::at(paren, message))
+							}
+							Err(RuntimeError::Error(mut error)) => {
+								error.stack.push(Frame {
+									function: format!("{function:?}"),
+									line: paren.line,
+								});
+								Err(RuntimeError::Error(error))
+							}
+							result => result,
+						}
 					} else {
-						Err(RuntimeError::Custom(
-							paren.clone(),
+						Err(RuntimeError::at(
+							paren,
 							format!(
-								"Expected {} arguments but got {}.",
-								function.arity(),
+								"Expected {}{arity} arguments but got {}.",
+								if function.variadic() { "at least " } else { "" },
 								arguments.len()
 							)
 							.into(),
 						))
 					}
 				} else {
-					Err(RuntimeError::Custom(
-						paren.clone(),
-						"Can only call functions and methods.".into(),
+					Err(RuntimeError::at(
+						paren,
+						format!(
+							"Can only call functions and methods, got {} {}.",
+							callee.type_name(),
+							callee
+						)
+						.into(),
 					))
 				}
 			}
 		}
 	}
 
-	fn look_up_variable(&self, name: &Token, expr: &Expr) -> Result<Object> {
-		match self.locals.get(&(expr as *const Expr)) {
-			Some(&distance) => self.environment.get_at(distance, name),
+	/// Applies an arithmetic operator to two numbers: exactly, with
+	/// `integer`, if both are integers and it gives a result that fits in
+	/// one, or else with `double`.
+	fn arithmetic(
+		&self,
+		operator: &Token,
+		left: &Object<'ast>,
+		right: &Object<'ast>,
+		integer: fn(i64, i64) -> Option<i64>,
+		double: fn(f64, f64) -> f64,
+	) -> Result<'ast, Object<'ast>> {
+		if let (Object::Literal(Literal::Integer(a)), Object::Literal(Literal::Integer(b))) =
+			(left, right)
+		{
+			if let Some(result) = integer(*a, *b) {
+				return Ok(result.into());
+			}
+		}
+		let (left, right) = Self::check_number_operands(operator, left, right)?;
+		self.double_result(operator, left, right, double(left, right))
+	}
+
+	/// Applies an operator that only works on integers, including doubles
+	/// that are whole numbers. `integer` gives `None` for a shift by an
+	/// amount out of range.
+	fn bitwise(
+		operator: &Token,
+		left: &Object<'ast>,
+		right: &Object<'ast>,
+		integer: fn(i64, i64) -> Option<i64>,
+	) -> Result<'ast, Object<'ast>> {
+		let (Some(a), Some(b)) = (integer_operand(left), integer_operand(right)) else {
+			return Err(RuntimeError::at(
+				operator,
+				"Operands must be integers.".into(),
+			));
+		};
+		integer(a, b)
+			.map(Object::from)
+			.ok_or_else(|| RuntimeError::at(operator, "Shift amount must be from 0 to 63.".into()))
+	}
+
+	fn check_integer_operand(operator: &Token, operand: &Object) -> Result<'ast, i64> {
+		integer_operand(operand)
+			.ok_or_else(|| RuntimeError::at(operator, "Operand must be an integer.".into()))
+	}
+
+	/// The `result` of `left operator right`, unless strict math forbids it.
+	fn double_result(
+		&self,
+		operator: &Token,
+		left: f64,
+		right: f64,
+		result: f64,
+	) -> Result<'ast, Object<'ast>> {
+		if !self.strict_math || result.is_finite() {
+			return Ok(result.into());
+		}
+		let what = if result.is_nan() {
+			"not a number"
+		} else {
+			"infinite"
+		};
+		Err(RuntimeError::at(
+			operator,
+			format!(
+				"{} {} {} is {what}.",
+				Object::from(left),
+				operator.lexeme,
+				Object::from(right)
+			)
+			.into(),
+		))
+	}
+
+	fn look_up_variable(&self, id: ExprId, name: &Token) -> Result<'ast, Object<'ast>> {
+		match self.locals.get(&id) {
+			Some(&slot) => match self.environment.as_ref().and_then(|env| env.get_at(slot)) {
+				Some(Some(value)) => Ok(value),
+				Some(None) => Err(environment::uninitialized(name)),
+				None => Err(environment::missing_slot(name)),
+			},
 			None => self.globals.get(name),
 		}
 	}
 
-	fn is_equal(left: Object, right: Object) -> bool {
-		left == right
+	fn assign_variable(
+		&mut self,
+		id: ExprId,
+		name: &Token,
+		value: Object<'ast>,
+	) -> Result<'ast, ()> {
+		match self.locals.get(&id) {
+			Some(&slot) => self
+				.environment
+				.as_ref()
+				.and_then(|env| env.assign_at(slot, value))
+				.ok_or_else(|| environment::missing_slot(name)),
+			None => self.globals.assign(name, value),
+		}
+	}
+
+	/// Unpacks a tuple of exactly `count` values, for destructuring.
+	fn destructure(
+		equals: &Token,
+		value: Object<'ast>,
+		count: usize,
+	) -> Result<'ast, Rc<[Object<'ast>]>> {
+		match value {
+			Object::Tuple(values) if values.len() == count => Ok(values),
+			Object::Tuple(values) => Err(RuntimeError::at(
+				equals,
+				format!("Expected {count} values but got {}.", values.len()).into(),
+			)),
+			_ => Err(RuntimeError::at(
+				equals,
+				"Can only destructure a tuple.".into(),
+			)),
+		}
+	}
+
+	/// Starts iterating over `iterable` for a for-in loop.
+	pub fn iterate(keyword: &Token, iterable: Object<'ast>) -> Result<'ast, Iteration<'ast>> {
+		match iterable {
+			Object::Tuple(elements) => Ok(Iteration::Tuple(elements, 0)),
+			Object::Callable(next) if next.arity() == 0 => Ok(Iteration::Function(next)),
+			_ => Err(RuntimeError::at(
+				keyword,
+				"Can only iterate over tuples and iterator functions.".into(),
+			)),
+		}
+	}
+
+	/// `value is "name"`: whether `value`'s type is the one named.
+	fn is_type(operator: &Token, value: &Object, name: &Object) -> Result<'ast, Object<'ast>> {
+		match name {
+			Object::Literal(Literal::String(name)) if Object::TYPE_NAMES.contains(&&**name) => {
+				Ok((value.type_name() == &**name).into())
+			}
+			Object::Literal(Literal::String(name)) => Err(RuntimeError::at(
+				operator,
+				format!("Unknown type name '{name}'.").into(),
+			)),
+			_ => Err(RuntimeError::at(
+				operator,
+				"Right operand of 'is' must be a type name.".into(),
+			)),
+		}
+	}
+
+	fn is_equal(
+		&self,
+		operator: &Token,
+		left: Object<'ast>,
+		right: Object<'ast>,
+	) -> Result<'ast, bool> {
+		let nil = Object::Literal(Literal::Nil);
+		if self.strict_types && left.type_name() != right.type_name() && left != nil && right != nil
+		{
+			return Err(RuntimeError::at(
+				operator,
+				format!(
+					"Can't compare a {} with a {} in strict mode.",
+					left.type_name(),
+					right.type_name()
+				)
+				.into(),
+			));
+		}
+		Ok(left == right)
 	}
 
-	fn is_truthy(obj: &Object) -> bool {
-		match obj {
-			Object::Literal(Literal::Nil) => false,
-			Object::Literal(Literal::Boolean(b)) => *b,
-			_ => true,
+	/// Whether `value` counts as true where `at` tests it. Only nil and
+	/// false do not, unless in strict mode, where anything but a boolean
+	/// is an error.
+	pub fn is_truthy(&self, value: &Object, at: &Token) -> Result<'ast, bool> {
+		match value {
+			Object::Literal(Literal::Boolean(b)) => Ok(*b),
+			_ if self.strict_types => Err(RuntimeError::at(
+				at,
+				format!(
+					"Expect a boolean in strict mode, got {}.",
+					value.type_name()
+				)
+				.into(),
+			)),
+			Object::Literal(Literal::Nil) => Ok(false),
+			_ => Ok(true),
 		}
 	}
 
-	fn check_number_operand(operator: &Token, operand: &Object) -> Result<f64> {
-		if let Object::Literal(Literal::Number(n)) = *operand {
+	fn check_number_operand(operator: &Token, operand: &Object) -> Result<'ast, f64> {
+		if let Some(n) = operand.as_number() {
 			Ok(n)
 		} else {
-			Err(RuntimeError::Custom(
-				operator.clone(),
+			Err(RuntimeError::at(
+				operator,
 				"Operand must be a number.".into(),
 			))
 		}
@@ -282,23 +951,242 @@ impl Interpreter {
 		operator: &Token,
 		left: &Object,
 		right: &Object,
-	) -> Result<(f64, f64)> {
-		match (left, right) {
-			(Object::Literal(Literal::Number(left)), Object::Literal(Literal::Number(right))) => {
-				Ok((*left, *right))
-			}
-			_ => Err(RuntimeError::Custom(
-				operator.clone(),
+	) -> Result<'ast, (f64, f64)> {
+		match (left.as_number(), right.as_number()) {
+			(Some(left), Some(right)) => Ok((left, right)),
+			_ => Err(RuntimeError::at(
+				operator,
 				"Operands must be numbers.".into(),
 			)),
 		}
 	}
+
+	/// Applies one of `>`, `>=`, `<` and `<=`.
+	fn compare(operator: &Token, left: &Object, right: &Object) -> Result<'ast, bool> {
+		let ordering = Self::compare_operands(operator, left, right)?;
+		Ok(match operator.ty {
+			TokenTy::Greater => matches!(ordering, Some(Ordering::Greater)),
+			TokenTy::GreaterEqual => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+			TokenTy::Less => matches!(ordering, Some(Ordering::Less)),
+			TokenTy::LessEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+			_ => return Err(unknown_operator(operator)),
+		})
+	}
+
+	/// Orders two numbers, or two strings lexicographically. `None` means
+	/// the numbers are unordered, i.e. one of them is NaN.
+	fn compare_operands(
+		operator: &Token,
+		left: &Object,
+		right: &Object,
+	) -> Result<'ast, Option<Ordering>> {
+		match (left, right) {
+			(Object::Literal(Literal::Integer(left)), Object::Literal(Literal::Integer(right))) => {
+				Ok(Some(left.cmp(right)))
+			}
+			(Object::Literal(Literal::String(left)), Object::Literal(Literal::String(right))) => {
+				Ok(Some(left.cmp(right)))
+			}
+			_ => match (left.as_number(), right.as_number()) {
+				(Some(left), Some(right)) => Ok(left.partial_cmp(&right)),
+				_ => Err(RuntimeError::at(
+					operator,
+					"Operands must be two numbers or two strings.".into(),
+				)),
+			},
+		}
+	}
+
+	/// The element of a tuple, or the character of a string as a string of
+	/// its own, at `index`, counting from zero.
+	fn index(bracket: &Token, object: &Object<'ast>, index: &Object) -> Result<'ast, Object<'ast>> {
+		let error = |message: String| Err(RuntimeError::at(bracket, message.into()));
+		let n = match index.as_number() {
+			Some(n) if n.fract() == 0.0 => n,
+			_ => return error(format!("Index must be a whole number, got {index}.")),
+		};
+		// Compared as a double, as converting would clamp a negative or
+		// huge index into range.
+		let in_range = |len: usize| n >= 0.0 && n < len as f64;
+		let element = match object {
+			Object::Tuple(elements) => {
+				in_range(elements.len()).then(|| elements[n as usize].clone())
+			}
+			Object::Literal(Literal::String(string)) => in_range(string.chars().count())
+				.then(|| string.chars().nth(n as usize))
+				.flatten()
+				.map(|ch| Object::from(ch.to_string())),
+			_ => {
+				return error(format!(
+					"Can only index tuples and strings, got {}.",
+					object.type_name()
+				))
+			}
+		};
+		match element {
+			Some(element) => Ok(element),
+			None => error(format!("Index {index} is out of range.")),
+		}
+	}
 }
 
-pub type Result<T> = std::result::Result<T, RuntimeError>;
+/// The value of a number that is a whole number, as an integer.
+fn integer_operand(operand: &Object) -> Option<i64> {
+	match *operand {
+		Object::Literal(Literal::Integer(n)) => Some(n),
+		Object::Literal(Literal::Number(n)) => exact_integer(n),
+		_ => None,
+	}
+}
+
+/// The error for an operator the parser never builds an expression with,
+/// so that a mistake there is reported rather than panicking.
+fn unknown_operator<'ast>(operator: &Token) -> RuntimeError<'ast> {
+	RuntimeError::at(
+		operator,
+		format!("Unknown operator '{}'.", operator.lexeme).into(),
+	)
+}
 
-pub enum RuntimeError {
+/// The global bindings at one moment, taken by `Interpreter::snapshot`.
+///
+/// Values are kept as they are rather than copied. Numbers, strings and
+/// tuples never change, so restoring them is exact, but a function shares
+/// the variables it captured, and a generator its progress, with the live
+/// value.
+#[derive(Clone)]
+pub struct EnvSnapshot<'ast> {
+	globals: Globals<'ast>,
+	constants: HashSet<Rc<str>>,
+}
+
+impl EnvSnapshot<'_> {
+	/// The globals that hold a number, string, boolean or nil, as a script
+	/// that defines them again. Functions, tuples and numbers that are not
+	/// finite have no literal to write, so they are left out.
+	pub fn to_source(&self) -> String {
+		let mut source = String::new();
+		for (name, value) in self.globals.iter() {
+			let Object::Literal(literal) = value else {
+				continue;
+			};
+			let value = match literal {
+				Literal::Number(n) if !n.is_finite() => continue,
+				// Written with a fraction so it is read back as a double.
+				Literal::Number(n) if n.fract() == 0.0 => format!("{n:.1}"),
+				Literal::Number(n) => n.to_string(),
+				Literal::Integer(n) => n.to_string(),
+				Literal::String(s) => scanner::escape(s),
+				Literal::Boolean(b) => b.to_string(),
+				Literal::Nil => "nil".to_string(),
+			};
+			let keyword = if self.constants.contains(name) {
+				"const"
+			} else {
+				"var"
+			};
+			source.push_str(&format!("{keyword} {name} = {value};\n"));
+		}
+		source
+	}
+}
+
+/// The state of a running for-in loop.
+pub enum Iteration<'ast> {
+	Tuple(Rc<[Object<'ast>]>, usize),
+	/// A function taking no arguments, called once per step until it returns
+	/// nil.
+	Function(Box<dyn LoxCallable<'ast> + 'ast>),
+}
+
+impl<'ast> Iteration<'ast> {
+	pub fn next(&mut self, intpr: &mut Interpreter<'ast>) -> Result<'ast, Option<Object<'ast>>> {
+		match self {
+			Iteration::Tuple(elements, index) => {
+				let element = elements.get(*index).cloned();
+				*index += 1;
+				Ok(element)
+			}
+			Iteration::Function(next) => match next.call(intpr, Vec::new())? {
+				Object::Literal(Literal::Nil) => Ok(None),
+				value => Ok(Some(value)),
+			},
+		}
+	}
+}
+
+pub type Result<'ast, T> = std::result::Result<T, RuntimeError<'ast>>;
+
+pub enum RuntimeError<'ast> {
 	// a hack
-	Return(Object),
-	Custom(Token, std::borrow::Cow<'static, str>),
+	Return(Object<'ast>),
+	/// Leaves the innermost loop, which catches it. The resolver makes
+	/// sure there is one.
+	Break,
+	Error(LoxError),
+	/// Raised by a native function, which has no token of its own to
+	/// report. The call expression turns it into an `Error` at its closing
+	/// parenthesis.
+	Native(std::borrow::Cow<'static, str>),
+}
+
+impl RuntimeError<'_> {
+	/// An error at `token`, which has not left any function yet.
+	pub fn at(token: &Token, message: std::borrow::Cow<'static, str>) -> Self {
+		RuntimeError::Error(LoxError {
+			message,
+			line: token.line,
+			stack: Vec::new(),
+		})
+	}
+}
+
+/// A runtime error that stops the script, as reported to the user or
+/// returned to whoever runs the interpreter.
+#[derive(Debug, Clone)]
+pub struct LoxError {
+	pub message: std::borrow::Cow<'static, str>,
+	/// Where the error happened.
+	pub line: usize,
+	/// The calls the error left, innermost first.
+	pub stack: Vec<Frame>,
+}
+
+/// A call that was running when an error happened.
+#[derive(Debug, Clone)]
+pub struct Frame {
+	/// The function called, as it is printed.
+	pub function: String,
+	/// Where it was called.
+	pub line: usize,
+}
+
+impl std::fmt::Display for LoxError {
+	/// The message, then the line it happened on and the line of each
+	/// call it left:
+	///
+	/// ```text
+	/// Operands must be numbers.
+	/// [line 2]
+	/// [line 5] in call to <fn inner>
+	/// [line 7] in call to <fn outer>
+	/// ```
+	///
+	/// Past `FRAMES_SHOWN` calls, as recursing too deeply leaves, only the
+	/// innermost and outermost are shown.
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		const FRAMES_SHOWN: usize = 20;
+		write!(f, "{}\n[line {}]", self.message, self.line)?;
+		for (index, frame) in self.stack.iter().enumerate() {
+			let hidden = self.stack.len().saturating_sub(FRAMES_SHOWN);
+			if index == FRAMES_SHOWN / 2 && hidden > 0 {
+				write!(f, "\n... {hidden} more calls")?;
+			}
+			if (FRAMES_SHOWN / 2..FRAMES_SHOWN / 2 + hidden).contains(&index) {
+				continue;
+			}
+			write!(f, "\n[line {}] in call to {}", frame.line, frame.function)?;
+		}
+		Ok(())
+	}
 }
@@ -1,19 +1,57 @@
-use std::borrow::Cow;
+use std::rc::Rc;
 
-#[derive(Debug, Clone, PartialEq)]
+use rlox_common::number::{integer_equals, Number};
+
+#[derive(Debug, Clone)]
 pub enum Literal {
 	Number(f64),
-	String(Cow<'static, str>),
+	/// A whole number, kept exact while arithmetic on it fits. It is a
+	/// number like any other, and equal to the double of the same value.
+	Integer(i64),
+	String(Rc<str>),
 	Boolean(bool),
 	Nil,
 }
 
+impl Literal {
+	/// The value of a number, however it is kept.
+	pub fn as_number(&self) -> Option<f64> {
+		match *self {
+			Self::Number(n) => Some(n),
+			Self::Integer(n) => Some(n as f64),
+			_ => None,
+		}
+	}
+}
+
+impl PartialEq for Literal {
+	fn eq(&self, other: &Self) -> bool {
+		match (self, other) {
+			(Self::Number(a), Self::Number(b)) => a == b,
+			(Self::Integer(a), Self::Integer(b)) => a == b,
+			(Self::Integer(a), Self::Number(b)) | (Self::Number(b), Self::Integer(a)) => {
+				integer_equals(*a, *b)
+			}
+			(Self::String(a), Self::String(b)) => a == b,
+			(Self::Boolean(a), Self::Boolean(b)) => a == b,
+			(Self::Nil, Self::Nil) => true,
+			_ => false,
+		}
+	}
+}
+
 impl From<f64> for Literal {
 	fn from(n: f64) -> Self {
 		Self::Number(n)
 	}
 }
 
+impl From<i64> for Literal {
+	fn from(n: i64) -> Self {
+		Self::Integer(n)
+	}
+}
+
 impl From<String> for Literal {
 	fn from(s: String) -> Self {
 		Self::String(s.into())
@@ -35,7 +73,8 @@ impl From<()> for Literal {
 impl std::fmt::Display for Literal {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
-			Literal::Number(n) => n.fmt(f),
+			Literal::Number(n) => Number(*n).fmt(f),
+			Literal::Integer(n) => n.fmt(f),
 			Literal::String(s) => s.fmt(f),
 			Literal::Boolean(b) => b.fmt(f),
 			Literal::Nil => "nil".fmt(f),
@@ -3,42 +3,58 @@ use crate::{
 	object::Object,
 };
 
-pub trait LoxCallable: std::fmt::Debug + BoxedPartialEq + BoxedClone {
+/// Something that can be called from Lox. `'ast` is the lifetime of the
+/// syntax trees that user-defined functions point back into.
+pub trait LoxCallable<'ast>: std::fmt::Debug + BoxedClone<'ast> {
 	fn arity(&self) -> usize;
-	fn call(&self, intpr: &mut Interpreter, args: Vec<Object>) -> Result<Object, RuntimeError>;
-}
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>>;
 
-pub trait BoxedClone {
-	fn clone_box(&self) -> Box<dyn LoxCallable>;
-}
+	/// Whether calls may pass more arguments than `arity`, which is then
+	/// the fewest they must pass.
+	fn variadic(&self) -> bool {
+		false
+	}
 
-impl<T: 'static + Clone + LoxCallable> BoxedClone for T {
-	fn clone_box(&self) -> Box<dyn LoxCallable> {
-		Box::new(self.clone())
+	/// Tells apart callables of the same type for `==`. Reading a variable
+	/// copies its value, so copies must keep the identity of the original.
+	/// Natives without state are all the same function, hence the default.
+	fn identity(&self) -> usize {
+		0
+	}
+
+	/// Whether it is built into the interpreter rather than declared in Lox.
+	fn is_native(&self) -> bool {
+		true
+	}
+
+	/// The Rust type, so that callables of different types are never equal.
+	fn concrete_type(&self) -> &'static str {
+		std::any::type_name::<Self>()
 	}
 }
 
-pub trait BoxedPartialEq {
-	fn eq_box(&self, other: &dyn LoxCallable) -> bool;
+pub trait BoxedClone<'ast> {
+	fn clone_box(&self) -> Box<dyn LoxCallable<'ast> + 'ast>;
 }
 
-impl<T: 'static + LoxCallable> BoxedPartialEq for T {
-	fn eq_box(&self, other: &dyn LoxCallable) -> bool {
-		std::ptr::eq(
-			self as *const _ as *const (),
-			other as *const _ as *const (),
-		)
+impl<'ast, T: 'ast + Clone + LoxCallable<'ast>> BoxedClone<'ast> for T {
+	fn clone_box(&self) -> Box<dyn LoxCallable<'ast> + 'ast> {
+		Box::new(self.clone())
 	}
 }
 
-impl Clone for Box<dyn LoxCallable> {
+impl<'ast> Clone for Box<dyn LoxCallable<'ast> + 'ast> {
 	fn clone(&self) -> Self {
 		self.clone_box()
 	}
 }
 
-impl PartialEq for Box<dyn LoxCallable> {
+impl<'ast> PartialEq for Box<dyn LoxCallable<'ast> + 'ast> {
 	fn eq(&self, other: &Self) -> bool {
-		self.eq_box(other.as_ref())
+		self.concrete_type() == other.concrete_type() && self.identity() == other.identity()
 	}
 }
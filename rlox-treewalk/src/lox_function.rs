@@ -2,44 +2,74 @@ use std::rc::Rc;
 
 use crate::{
 	environment::EnvironmentPointer,
+	generator::Generator,
 	interpreter::{Interpreter, RuntimeError},
 	lox_callable::LoxCallable,
 	object::Object,
 	stmt::StmtFunction,
 };
 
+/// A function declared in Lox. Every copy of the value made when the
+/// declaration ran shares one allocation, which is what makes them equal.
 #[derive(Clone)]
-pub struct LoxFunction {
-	closure: EnvironmentPointer,
-	declaration: Rc<StmtFunction>,
+pub struct LoxFunction<'ast>(Rc<Closure<'ast>>);
+
+struct Closure<'ast> {
+	closure: Option<EnvironmentPointer<'ast>>,
+	declaration: &'ast StmtFunction<'ast>,
 }
 
-impl LoxFunction {
-	pub fn new(declaration: Rc<StmtFunction>, closure: EnvironmentPointer) -> Self {
-		Self {
+impl<'ast> LoxFunction<'ast> {
+	pub fn new(
+		declaration: &'ast StmtFunction<'ast>,
+		closure: Option<EnvironmentPointer<'ast>>,
+	) -> Self {
+		Self(Rc::new(Closure {
 			declaration,
 			closure,
-		}
+		}))
 	}
 }
 
-impl std::fmt::Debug for LoxFunction {
+impl std::fmt::Debug for LoxFunction<'_> {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		writeln!(f, "<fn {}>", self.declaration.name.lexeme)
+		write!(f, "<fn {}>", self.0.declaration.name.lexeme)
 	}
 }
 
-impl LoxCallable for LoxFunction {
+impl<'ast> LoxCallable<'ast> for LoxFunction<'ast> {
 	fn arity(&self) -> usize {
-		self.declaration.params.len()
+		self.0.declaration.params.len()
 	}
 
-	fn call(&self, intpr: &mut Interpreter, args: Vec<Object>) -> Result<Object, RuntimeError> {
-		let mut environment = EnvironmentPointer::new(self.closure.clone());
-		for (token, value) in self.declaration.params.iter().zip(args.into_iter()) {
-			environment.define(token.lexeme.to_owned(), value.clone());
+	fn identity(&self) -> usize {
+		Rc::as_ptr(&self.0) as usize
+	}
+
+	fn is_native(&self) -> bool {
+		false
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let Closure {
+			closure,
+			declaration,
+		} = &*self.0;
+		let mut environment = EnvironmentPointer::new(closure.clone(), &intpr.environment_counts);
+		for value in args {
+			environment.define(value);
+		}
+		if declaration.generator {
+			return Ok(Object::from_callable(Generator::new(
+				declaration,
+				environment,
+			)));
 		}
-		match intpr.execute_block(&self.declaration.body, environment) {
+		match intpr.execute_block(declaration.body, environment) {
 			Err(RuntimeError::Return(val)) => Ok(val),
 			otherwise => otherwise.map(|_| ().into()),
 		}
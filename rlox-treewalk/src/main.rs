@@ -1,14 +1,36 @@
-use std::{borrow::Cow, io::BufRead, process::exit};
+use std::{
+	borrow::Cow,
+	cell::RefCell,
+	collections::HashSet,
+	fs::{File, OpenOptions},
+	io::{BufRead, Write},
+	path::{Path, PathBuf},
+	process::exit,
+	rc::Rc,
+};
 
-use interpreter::{Interpreter, RuntimeError};
+use bumpalo::Bump;
+use completion::Completion;
+use interpreter::{EnvSnapshot, Interpreter, LoxError, RuntimeError};
+use literal::Literal;
+use object::Object;
 use parser::{ParseError, Parser};
-use resolver::{ResolveError, Resolver};
-use scanner::{ScanError, Scanner};
+use resolver::{Reference, ResolveError, ResolveWarning, Resolver};
+use scanner::{IncrementalScanner, ScanError, Scanner};
+use stmt::Stmt;
+use token::Token;
 use token_type::TokenTy;
+use trace::Tracer;
 
 mod ast_printer;
+mod big_int;
+mod call_graph;
+mod completion;
+mod csv;
+mod encoding;
 mod environment;
 mod expr;
+mod generator;
 mod interpreter;
 mod literal;
 mod lox_callable;
@@ -16,93 +38,845 @@ mod lox_function;
 mod native_functions;
 mod object;
 mod parser;
+mod rename;
 mod resolver;
 mod scanner;
 mod stmt;
 mod token;
 mod token_type;
+mod trace;
+
+/// Lox source run before any user code.
+const PRELUDE: &str = include_str!("prelude.lox");
+
+/// How much stack the interpreter runs with. Each Lox call takes several
+/// Rust calls, so the main thread's would run out long before a script
+/// recursed deeply enough to be stopped.
+const STACK_SIZE: usize = 1 << 30;
 
 fn main() {
-	let mut args = std::env::args();
-	if args.len() > 2 {
-		println!("Usage: rslox [script]");
-		exit(1);
+	let interpreter = std::thread::Builder::new()
+		.stack_size(STACK_SIZE)
+		.spawn(run)
+		.unwrap_or_else(|err| {
+			eprintln!("Could not start the interpreter: {err}");
+			exit(70)
+		});
+	// A panic has already been reported by the hook.
+	if interpreter.join().is_err() {
+		exit(101);
+	}
+}
+
+fn run() {
+	let mut arguments = Vec::new();
+	let mut options = Options {
+		print_statement: true,
+		prelude: true,
+		strict: false,
+		strict_math: false,
+		exit_with_result: false,
+		test: false,
+		hoist: false,
+		allow_process: false,
+		defined: HashSet::new(),
+	};
+	let mut record = None;
+	let mut find_def = None;
+	let mut position = (None, None);
+	let mut diff = false;
+	let mut dump_call_graph = false;
+	let mut trace = None;
+	// first arg is program name, e.g rslox
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--no-print-statement" => options.print_statement = false,
+			"--no-prelude" => options.prelude = false,
+			"--strict" => options.strict = true,
+			"--strict-math" => options.strict_math = true,
+			"--exit-with-result" => options.exit_with_result = true,
+			"--test" => options.test = true,
+			"--hoist" => options.hoist = true,
+			"--allow-process" => options.allow_process = true,
+			"--define" => {
+				let name = args.next().unwrap_or_else(|| usage());
+				options.defined.insert(name);
+			}
+			"--record" => record = Some(args.next().unwrap_or_else(|| usage())),
+			"--find-def" => find_def = Some(args.next().unwrap_or_else(|| usage())),
+			"--line" => position.0 = args.next().and_then(|line| line.parse().ok()),
+			"--col" => position.1 = args.next().and_then(|column| column.parse().ok()),
+			"--diff" => diff = true,
+			"--dump-callgraph=dot" => dump_call_graph = true,
+			_ if arg.starts_with("--trace-exec=") => {
+				trace = Some(PathBuf::from(&arg["--trace-exec=".len()..]))
+			}
+			_ if arg.starts_with("--") => usage(),
+			_ => arguments.push(arg),
+		}
+	}
+	let arena = Bump::new();
+	// Only running a script or the REPL is traced.
+	let runs = matches!(arguments.as_slice(), [_] | []) && find_def.is_none() && !dump_call_graph;
+	if trace.is_some() && !runs {
+		usage();
+	}
+	let lox = || {
+		let mut lox = options.lox(&arena);
+		if let Some(path) = &trace {
+			let tracer = Tracer::create(path).unwrap_or_else(|err| {
+				eprintln!("Could not open \"{}\" to trace to: {err}", path.display());
+				exit(74);
+			});
+			lox.interpreter.trace = Some(tracer);
+		}
+		lox
+	};
+	if let Some(query) = find_def {
+		if !arguments.is_empty() || record.is_some() {
+			usage();
+		}
+		exit(find_definition(&query, &mut options.lox(&arena)));
 	}
-	args.next(); // first arg is program name, e.g rslox
-	let mut lox = Lox::default();
-	match args.next() {
-		Some(arg) => lox.run_file(arg),
-		None => lox.run_prompt(),
+	match arguments.as_slice() {
+		[script] if dump_call_graph && record.is_none() => {
+			exit(options.lox(&arena).dump_call_graph(Path::new(script)))
+		}
+		_ if dump_call_graph => usage(),
+		[command, dir] if command == "run-all" && record.is_none() => {
+			exit(run_all(Path::new(dir), &options))
+		}
+		[script] if record.is_none() => exit(lox().run_file(Path::new(script))),
+		[command, path, old, new] if command == "rename" && record.is_none() => {
+			let (Some(line), Some(column)) = position else {
+				usage();
+			};
+			let mut lox = options.lox(&arena);
+			exit(rename_in_file(
+				&mut lox,
+				path,
+				old,
+				new,
+				(line, column),
+				diff,
+			))
+		}
+		[] => {
+			let record = record.map(|path| {
+				let file = OpenOptions::new().create(true).append(true).open(&path);
+				file.unwrap_or_else(|err| {
+					eprintln!("Could not open \"{path}\" to record to: {err}");
+					exit(74);
+				})
+			});
+			lox().run_prompt(record)
+		}
+		_ => usage(),
 	}
 }
 
-#[derive(Default)]
-struct Lox {
+fn usage() -> ! {
+	println!(
+		"Usage: rslox [--no-print-statement] [--no-prelude] [--strict] [--strict-math] \
+		 [--exit-with-result] [--test] [--hoist] [--allow-process] [--define name]... [--record file] [--trace-exec=file] [script | run-all dir]\n\
+		 \x20      rslox [options] --find-def file:line:col\n\
+		 \x20      rslox [options] rename file old new --line L --col C [--diff]\n\
+		 \x20      rslox [options] --dump-callgraph=dot script"
+	);
+	exit(1);
+}
+
+/// What the command-line flags ask of each interpreter.
+struct Options {
+	print_statement: bool,
+	prelude: bool,
+	strict: bool,
+	strict_math: bool,
+	exit_with_result: bool,
+	test: bool,
+	hoist: bool,
+	allow_process: bool,
+	/// The symbols `//#if` directives find defined.
+	defined: HashSet<String>,
+}
+
+impl Options {
+	/// A fresh interpreter set up as asked, with the prelude already run.
+	fn lox<'ast>(&self, arena: &'ast Bump) -> Lox<'ast> {
+		let mut lox = Lox::new(arena);
+		lox.print_statement = self.print_statement;
+		lox.interpreter.globals.strict = self.strict;
+		lox.interpreter.strict_types = self.strict;
+		lox.interpreter.strict_math = self.strict_math;
+		lox.interpreter.hoist_functions = self.hoist;
+		lox.interpreter.allow_process = self.allow_process;
+		lox.exit_with_result = self.exit_with_result;
+		lox.test = self.test;
+		lox.defined = self.defined.clone();
+		if self.prelude {
+			lox.eval(PRELUDE.into());
+		}
+		lox
+	}
+}
+
+/// Runs every `.lox` file under `dir`, in path order, each with a fresh
+/// interpreter, and reports which passed, i.e. exited with 0. What a
+/// script prints is only shown if it fails. Returns the highest exit code
+/// of any file.
+fn run_all(dir: &Path, options: &Options) -> i32 {
+	let mut scripts = Vec::new();
+	if let Err(err) = find_scripts(dir, &mut scripts) {
+		eprintln!("Could not read directory \"{}\": {err}", dir.display());
+		return 74;
+	}
+	scripts.sort();
+
+	let mut failed = 0;
+	let mut code = 0;
+	for script in &scripts {
+		let arena = Bump::new();
+		let mut lox = options.lox(&arena);
+		let mut error = None;
+		let status = match std::fs::read_to_string(script) {
+			Ok(source) => {
+				let outcome = lox.run_capturing(source);
+				let status = lox.exit_status(outcome.value);
+				if status != 0 {
+					print!("{}", outcome.stdout);
+					for diagnostic in outcome.diagnostics {
+						eprintln!("{}", diagnostic.message);
+					}
+				}
+				error = outcome.error;
+				status
+			}
+			Err(err) => {
+				eprintln!("Could not open file \"{}\": {err}", script.display());
+				74
+			}
+		};
+		if status == 0 {
+			eprintln!("PASS {}", script.display());
+			continue;
+		}
+		match error {
+			Some(error) => eprintln!(
+				"FAIL {} (exit code {status}, line {}: {})",
+				script.display(),
+				error.line,
+				error.message
+			),
+			None => eprintln!("FAIL {} (exit code {status})", script.display()),
+		}
+		failed += 1;
+		code = code.max(status);
+	}
+	eprintln!("{} passed, {failed} failed", scripts.len() - failed);
+	code
+}
+
+fn find_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> std::io::Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			find_scripts(&path, scripts)?;
+		} else if path.extension().is_some_and(|extension| extension == "lox") {
+			scripts.push(path);
+		}
+	}
+	Ok(())
+}
+
+/// Prints where the name at `query`, a `file:line:col` position counted
+/// from 1 as editors do, was declared, in the same form. Returns the code
+/// to exit with.
+fn find_definition(query: &str, lox: &mut Lox) -> i32 {
+	let mut parts = query.rsplitn(3, ':');
+	let (Some(column), Some(line), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+		usage();
+	};
+	let (Ok(line), Ok(column)) = (line.parse(), column.parse()) else {
+		usage();
+	};
+	let source = match std::fs::read_to_string(path) {
+		Ok(source) => source,
+		Err(err) => {
+			eprintln!("Could not open file \"{path}\": {err}");
+			return 74;
+		}
+	};
+	let Some(offset) = offset_at(&source, line, column) else {
+		eprintln!("There is no line {line}, column {column} in \"{path}\".");
+		return 65;
+	};
+
+	let references = lox.references(source.clone());
+	match references
+		.iter()
+		.find(|reference| reference.name.touches(offset))
+	{
+		Some(reference) => {
+			let (line, column) = line_column(&source, reference.declaration.offset);
+			println!("{path}:{line}:{column}");
+			0
+		}
+		None => {
+			eprintln!("No definition found at {query}.");
+			1
+		}
+	}
+}
+
+/// Renames `old`, the name at the 1-based `line` and `column` of the
+/// script at `path`, to `new` everywhere it is referred to. Writes the
+/// renamed script back, or with `diff` only prints what would change.
+/// Returns the code to exit with.
+fn rename_in_file(
+	lox: &mut Lox,
+	path: &str,
+	old: &str,
+	new: &str,
+	(line, column): (usize, usize),
+	diff: bool,
+) -> i32 {
+	let source = match std::fs::read_to_string(path) {
+		Ok(source) => source,
+		Err(err) => {
+			eprintln!("Could not open file \"{path}\": {err}");
+			return 74;
+		}
+	};
+	let Some(offset) = offset_at(&source, line, column) else {
+		eprintln!("There is no line {line}, column {column} in \"{path}\".");
+		return 65;
+	};
+
+	let scanner = Scanner::new(&source).with_defined(lox.defined.clone());
+	let (tokens, _) = scanner.scan_tokens();
+	let references = lox.references(source.clone());
+	let globals = lox
+		.interpreter
+		.globals
+		.iter()
+		.map(|(name, _)| name.clone())
+		.collect();
+	let renamed = rename::rename(&source, &tokens, &references, &globals, offset, old, new);
+	match renamed {
+		Ok(renamed) if diff => {
+			print!("{}", rename::unified_diff(path, &source, &renamed));
+			0
+		}
+		Ok(renamed) => match std::fs::write(path, renamed) {
+			Ok(()) => 0,
+			Err(err) => {
+				eprintln!("Could not write file \"{path}\": {err}");
+				74
+			}
+		},
+		Err(message) => {
+			eprintln!("{message}");
+			65
+		}
+	}
+}
+
+/// The byte offset of a 1-based `line` and `column` of `source`, counting
+/// columns in characters.
+fn offset_at(source: &str, line: usize, column: usize) -> Option<usize> {
+	let start = match line.checked_sub(1)? {
+		0 => 0,
+		line => source.match_indices('\n').nth(line - 1)?.0 + 1,
+	};
+	let text = source[start..].split('\n').next()?;
+	let before = column.checked_sub(1)?;
+	match text.char_indices().nth(before) {
+		Some((offset, _)) => Some(start + offset),
+		None if before == text.chars().count() => Some(start + text.len()),
+		None => None,
+	}
+}
+
+/// The 1-based line and column of byte `offset` of `source`.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+	let before = &source[..offset];
+	let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+	let line = before.matches('\n').count() + 1;
+	(line, before[line_start..].chars().count() + 1)
+}
+
+/// The exit code `--exit-with-result` turns a script's result into: a
+/// whole number from 0 to 255 as it is, `true` as success and `false` as
+/// failure. No result, or nil, is success.
+fn exit_code(result: Option<Object>) -> i32 {
+	match result {
+		None | Some(Object::Literal(Literal::Nil | Literal::Boolean(true))) => 0,
+		Some(Object::Literal(Literal::Boolean(false))) => 1,
+		Some(Object::Literal(Literal::Integer(code))) if (0..=255).contains(&code) => code as i32,
+		Some(Object::Literal(Literal::Number(code)))
+			if code.fract() == 0.0 && (0.0..=255.0).contains(&code) =>
+		{
+			code as i32
+		}
+		Some(result) => {
+			eprintln!("Can't exit with {result}, which is not a whole number from 0 to 255.");
+			70
+		}
+	}
+}
+
+/// Whether `command` is one that `Lox::run_snapshot_command` runs.
+fn is_snapshot_command(command: &str) -> bool {
+	command == ":save" || command == ":restore" || command.starts_with(":save ")
+}
+
+/// Appends `source` to the session being recorded, if there is one, and
+/// stops recording if that fails.
+fn write_record(record: &mut Option<File>, source: &str) {
+	if let Some(file) = record {
+		let newline = if source.ends_with('\n') { "" } else { "\n" };
+		if let Err(err) = write!(file, "{source}{newline}") {
+			eprintln!("Could not record the session, stopped recording: {err}");
+			*record = None;
+		}
+	}
+}
+
+/// Whether `tokens` leave a block or parentheses open, so that input at
+/// the REPL goes on to the next line.
+fn is_unfinished(tokens: impl Iterator<Item = TokenTy>) -> bool {
+	let depth = tokens.fold(0_isize, |depth, ty| match ty {
+		TokenTy::LeftBrace | TokenTy::LeftParen | TokenTy::LeftBracket => depth + 1,
+		TokenTy::RightBrace | TokenTy::RightParen | TokenTy::RightBracket => depth - 1,
+		_ => depth,
+	});
+	depth > 0
+}
+
+/// Something said about a run on stderr: an error, a warning or a test
+/// result.
+#[derive(Debug)]
+struct Diagnostic {
+	/// What is printed for it, without the final newline.
+	message: String,
+}
+
+/// Everything a run produced, as returned by `Lox::run_capturing`.
+struct RunOutcome<'ast> {
+	/// What the script printed.
+	stdout: String,
+	diagnostics: Vec<Diagnostic>,
+	/// The value of the last expression statement, if it had one and
+	/// nothing went wrong.
+	value: Option<Object<'ast>>,
+	/// The runtime error that stopped the script, if one did.
+	error: Option<LoxError>,
+}
+
+/// An output sink whose contents can still be read after it has been
+/// handed to the interpreter.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.borrow_mut().write(buf)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+struct Lox<'ast> {
+	/// Holds the syntax trees of everything run so far. Functions declared
+	/// by one REPL line are still callable from the next, so nothing is
+	/// freed until the session ends.
+	arena: &'ast Bump,
 	had_input_error: bool,
 	had_runtime_error: bool,
-	interpreter: Interpreter,
+	/// Parse `print` as a statement, as in the book. When off, `print` is
+	/// just a native function like `println`.
+	print_statement: bool,
+	/// Exit with the code the script's result stands for, as decided by
+	/// `exit_code`, once the script has run without errors.
+	exit_with_result: bool,
+	/// Run the tests a script registers once it has run without errors,
+	/// and exit with whether they passed.
+	test: bool,
+	/// The symbols `//#if` directives find defined.
+	defined: HashSet<String>,
+	/// Where diagnostics go while `run_capturing` runs. `None` prints them
+	/// to stderr.
+	diagnostics: Option<Vec<Diagnostic>>,
+	/// The last runtime error reported.
+	last_error: Option<LoxError>,
+	interpreter: Interpreter<'ast>,
 }
 
-impl Lox {
-	fn run_file(&mut self, path: String) {
-		let program =
-			std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("failed to open {}", path));
-		self.run(program);
+impl<'ast> Lox<'ast> {
+	fn new(arena: &'ast Bump) -> Self {
+		Self {
+			arena,
+			had_input_error: false,
+			had_runtime_error: false,
+			print_statement: true,
+			exit_with_result: false,
+			test: false,
+			defined: HashSet::new(),
+			diagnostics: None,
+			last_error: None,
+			interpreter: Interpreter::default(),
+		}
+	}
 
+	/// Runs the script at `path`, returning the code to exit with.
+	fn run_file(&mut self, path: &Path) -> i32 {
+		let program = match std::fs::read_to_string(path) {
+			Ok(program) => program,
+			Err(err) => {
+				eprintln!("Could not open file \"{}\": {err}", path.display());
+				return 74;
+			}
+		};
+		let result = self.eval(program);
+		self.exit_status(result)
+	}
+
+	/// The code to exit with once a script has run, with `result` as its
+	/// result. With `--test`, the tests the script registered are run
+	/// first.
+	fn exit_status(&mut self, result: Option<Object>) -> i32 {
 		if self.had_input_error {
-			exit(65);
+			return 65;
 		}
 
 		if self.had_runtime_error {
-			exit(70);
+			return 70;
+		}
+
+		if self.test {
+			return self.run_tests();
+		}
+
+		if self.exit_with_result {
+			return exit_code(result);
 		}
+		0
 	}
 
-	fn run_prompt(&mut self) {
+	/// Runs every registered test, reporting each and then how many
+	/// failed. Returns 1 if any did, 0 otherwise.
+	fn run_tests(&mut self) -> i32 {
+		let tests = std::mem::take(&mut self.interpreter.tests);
+		let mut failed = 0;
+		for (name, function) in &tests {
+			match function.call(&mut self.interpreter, Vec::new()) {
+				Ok(_) => self.diagnose(format!("test {name} ... ok")),
+				Err(err) => {
+					self.diagnose(format!("test {name} ... FAILED"));
+					self.runtime_error(err);
+					failed += 1;
+				}
+			}
+		}
+		let passed = tests.len() - failed;
+		self.diagnose(format!("{passed} passed, {failed} failed"));
+		(failed > 0) as i32
+	}
+
+	/// Runs lines from stdin until it ends. `:replay path` runs the script
+	/// at `path` as if it had been typed in. `:save` takes a snapshot of
+	/// the globals, replacing any earlier one, and `:restore` goes back to
+	/// it; `:save path` also writes the globals that hold a number, string,
+	/// boolean or nil to `path` as a script. `:env` lists the globals
+	/// defined since the session started, i.e. not by the interpreter or
+	/// the prelude. `:complete text` lists the names that could finish the
+	/// word `text` ends with, as if it were typed in. If `record` is given,
+	/// every line or replayed script that runs without errors is appended
+	/// to it, as are `:save` and `:restore`, so `:replay` can run the
+	/// session again.
+	///
+	/// Input that leaves a block or parentheses open waits for the lines
+	/// that close them, or for a blank line, before it runs.
+	fn run_prompt(&mut self, mut record: Option<File>) {
 		let mut reader = std::io::BufReader::new(std::io::stdin());
+		let mut snapshot = None;
+		let builtins = self
+			.interpreter
+			.globals
+			.iter()
+			.map(|(name, _)| name.clone())
+			.collect::<HashSet<_>>();
+		let mut pending: Option<IncrementalScanner> = None;
 		loop {
 			let mut line = String::new();
-			if reader.read_line(&mut line).expect("failed to read line") == 0 {
-				break;
+			match reader.read_line(&mut line) {
+				Ok(0) => break,
+				Ok(_) => {}
+				Err(err) => {
+					eprintln!("Could not read input: {err}");
+					break;
+				}
+			}
+			if let Some(mut scanner) = pending.take() {
+				let end = scanner.source().len();
+				scanner.edit(end..end, &line);
+				self.run_input(scanner, &line, &mut pending, &mut record);
+				continue;
 			}
-			self.run(line);
-			self.had_input_error = false;
-			self.had_runtime_error = false;
+			let source = match line.trim() {
+				command if is_snapshot_command(command) => {
+					self.run_snapshot_command(command, &mut snapshot, &mut record);
+					continue;
+				}
+				":env" => {
+					let mut globals = self
+						.interpreter
+						.globals
+						.iter()
+						.filter(|(name, _)| !builtins.contains(*name))
+						.collect::<Vec<_>>();
+					globals.sort_by_key(|(name, _)| *name);
+					for (name, value) in globals {
+						println!("{name} = {value}");
+					}
+					continue;
+				}
+				command if command.starts_with(":complete ") => {
+					let text = &line.trim_start()[":complete ".len()..].trim_end_matches('\n');
+					let word = text
+						.rfind(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+						.map_or(0, |before| before + 1);
+					for completion in self.completions(text.to_string(), text.len()) {
+						if completion.name.starts_with(&text[word..]) {
+							println!("{} ({})", completion.name, completion.kind);
+						}
+					}
+					continue;
+				}
+				command => match command.strip_prefix(":replay ") {
+					Some(path) => match std::fs::read_to_string(path.trim()) {
+						Ok(source) => {
+							// A recorded session can have `:save` and `:restore`
+							// lines, which run between the source around them.
+							let mut chunk = String::new();
+							for script_line in source.split_inclusive('\n') {
+								match script_line.trim() {
+									command if is_snapshot_command(command) => {
+										if !chunk.trim().is_empty() {
+											let chunk = std::mem::take(&mut chunk);
+											let scanner = IncrementalScanner::new(
+												chunk,
+												self.defined.clone(),
+											);
+											self.run_input(scanner, "", &mut pending, &mut record);
+										}
+										chunk.clear();
+										self.run_snapshot_command(
+											command,
+											&mut snapshot,
+											&mut record,
+										);
+									}
+									_ => chunk.push_str(script_line),
+								}
+							}
+							chunk
+						}
+						Err(err) => {
+							eprintln!("Could not open file \"{}\": {err}", path.trim());
+							continue;
+						}
+					},
+					None => line.clone(),
+				},
+			};
+			let scanner = IncrementalScanner::new(source, self.defined.clone());
+			self.run_input(scanner, &line, &mut pending, &mut record);
 		}
 	}
 
-	fn run(&mut self, source: String) {
-		let scanner = Scanner::new(source);
+	/// Runs `:save`, `:save path` or `:restore`, recording it if it worked so that
+	/// running the session again goes back to the same globals.
+	fn run_snapshot_command(
+		&mut self,
+		command: &str,
+		snapshot: &mut Option<EnvSnapshot<'ast>>,
+		record: &mut Option<File>,
+	) {
+		if let Some(path) = command.strip_prefix(":save") {
+			let saved = self.interpreter.snapshot();
+			let path = path.trim();
+			if !path.is_empty() {
+				if let Err(err) = std::fs::write(path, saved.to_source()) {
+					eprintln!("Could not write file \"{path}\": {err}");
+					return;
+				}
+			}
+			*snapshot = Some(saved);
+		} else if let Some(snapshot) = snapshot {
+			self.interpreter.restore(snapshot);
+		} else {
+			eprintln!("Nothing to restore, use :save first.");
+			return;
+		}
+		write_record(record, command);
+	}
 
-		let tokens = match scanner.scan_tokens() {
-			Ok(tokens) => tokens,
+	/// The names that can be completed at byte `cursor` of `source`.
+	fn completions(&mut self, source: String, cursor: usize) -> Vec<Completion> {
+		let statements = self.parse_quietly(source);
+		completion::completions(&mut self.interpreter, statements, cursor)
+	}
+
+	/// What each name in `source` refers to, as `Resolver::references`
+	/// finds.
+	fn references(&mut self, source: String) -> Vec<Reference> {
+		let statements = self.parse_quietly(source);
+		Resolver::new(&mut self.interpreter).references(statements)
+	}
+
+	/// Prints the call graph of the script at `path` in DOT, without
+	/// running it. Returns the code to exit with.
+	fn dump_call_graph(&mut self, path: &Path) -> i32 {
+		let source = match std::fs::read_to_string(path) {
+			Ok(source) => source,
 			Err(err) => {
-				self.had_input_error = true;
-				return self.scan_error(err);
+				eprintln!("Could not open file \"{}\": {err}", path.display());
+				return 74;
 			}
 		};
+		let statements = self.parse_quietly(source);
+		let graph = Resolver::new(&mut self.interpreter).call_graph(statements);
+		print!("{}", graph.to_dot());
+		0
+	}
 
-		let parser = Parser::new(tokens);
+	/// Parses `source` as far as it can be, for looking at rather than
+	/// running, so without reporting any errors.
+	fn parse_quietly(&self, source: String) -> &'ast [Stmt<'ast>] {
+		let scanner =
+			Scanner::new(self.arena.alloc_str(&source)).with_defined(self.defined.clone());
+		let (tokens, _) = scanner.scan_tokens();
+		let parser = Parser::new(self.arena, tokens).with_print_statement(self.print_statement);
+		parser.parse().0
+	}
 
-		let statements = match parser.parse() {
-			Ok(statements) => statements,
-			Err(err) => {
-				self.had_input_error = true;
-				return self.parse_error(err);
+	/// Runs the input scanned by `scanner`, which `line` was the last of,
+	/// unless it is unfinished and `line` is not blank, in which case it is
+	/// left in `pending` for the next line to be added to.
+	fn run_input(
+		&mut self,
+		scanner: IncrementalScanner,
+		line: &str,
+		pending: &mut Option<IncrementalScanner>,
+		record: &mut Option<File>,
+	) {
+		if is_unfinished(scanner.types()) && !line.trim().is_empty() {
+			*pending = Some(scanner);
+			return;
+		}
+
+		let (tokens, scan_error) = scanner.tokens(self.arena);
+
+		match self.eval_tokens(tokens, scan_error) {
+			None | Some(Object::Literal(Literal::Nil)) => {}
+			Some(result) => println!("{result}"),
+		}
+		if !self.had_input_error && !self.had_runtime_error {
+			write_record(record, scanner.source());
+		}
+		self.had_input_error = false;
+		self.had_runtime_error = false;
+	}
+
+	/// Runs `source` like `eval`, but returns what it prints and the
+	/// diagnostics about it instead of writing them to stdout and stderr.
+	fn run_capturing(&mut self, source: String) -> RunOutcome<'ast> {
+		let stdout = SharedBuffer::default();
+		let output = self.interpreter.replace_output(Box::new(stdout.clone()));
+		let diagnostics = self.diagnostics.replace(Vec::new());
+		self.last_error = None;
+
+		let value = self.eval(source);
+
+		self.interpreter.replace_output(output);
+		let captured = std::mem::replace(&mut self.diagnostics, diagnostics);
+		let stdout = String::from_utf8_lossy(&stdout.0.borrow()).into_owned();
+		RunOutcome {
+			stdout,
+			diagnostics: captured.unwrap_or_default(),
+			value,
+			error: self.last_error.take(),
+		}
+	}
+
+	/// Runs `source`, returning the value of its last expression statement
+	/// if it has one and nothing went wrong.
+	fn eval(&mut self, source: String) -> Option<Object<'ast>> {
+		let scanner =
+			Scanner::new(self.arena.alloc_str(&source)).with_defined(self.defined.clone());
+		let (tokens, scan_error) = scanner.scan_tokens();
+		self.eval_tokens(tokens, scan_error)
+	}
+
+	/// Runs a script already scanned to `tokens`, with `scan_error` what
+	/// went wrong scanning it, like `eval`.
+	fn eval_tokens(
+		&mut self,
+		tokens: Vec<Token<'ast>>,
+		scan_error: Option<ScanError>,
+	) -> Option<Object<'ast>> {
+		let scanned = scan_error.is_none();
+		if let Some(err) = scan_error {
+			self.had_input_error = true;
+			self.scan_error(err);
+		}
+
+		let parser = Parser::new(self.arena, tokens).with_print_statement(self.print_statement);
+		let (statements, parse_error) = parser.parse();
+		let parsed = parse_error.is_none();
+		if let Some(err) = parse_error {
+			self.had_input_error = true;
+			self.parse_error(err);
+		}
+
+		// The constants the script declares are only kept if all of it
+		// runs.
+		let constants = self.interpreter.global_constants.clone();
+		if !scanned || !parsed {
+			// Resolve what did parse for the errors in it too.
+			if let Err(err) = Resolver::new(&mut self.interpreter).resolve(statements) {
+				self.resolve_error(err);
 			}
-		};
+			self.interpreter.global_constants = constants;
+			return None;
+		}
 
 		let resolver = Resolver::new(&mut self.interpreter);
-		if let Err(err) = resolver.resolve(&statements) {
-			self.had_input_error = true;
-			return self.resolve_error(err);
+		match resolver.resolve(statements) {
+			Ok(warnings) => {
+				for warning in warnings {
+					self.resolve_warning(warning);
+				}
+			}
+			Err(err) => {
+				self.had_input_error = true;
+				self.resolve_error(err);
+				self.interpreter.global_constants = constants;
+				return None;
+			}
 		}
 
-		match self.interpreter.interpret(&statements) {
-			Ok(_) => {}
+		match self.interpreter.interpret(statements) {
+			Ok(result) => result,
 			Err(err) => {
 				self.had_runtime_error = true;
-				self.runtime_error(err)
+				self.runtime_error(err);
+				self.interpreter.global_constants = constants;
+				None
 			}
 		}
 	}
@@ -143,10 +917,18 @@ impl Lox {
 
 	fn runtime_error(&mut self, error: RuntimeError) {
 		match error {
-			RuntimeError::Custom(token, message) => {
-				eprintln!("{message}\n[line {}]", token.line);
+			RuntimeError::Error(error) => {
+				self.diagnose(error.to_string());
+				self.last_error = Some(error);
+			}
+			// Only a native called by the interpreter itself, as the
+			// iterator of a for-in loop, gets here without a line.
+			RuntimeError::Native(message) => self.diagnose(message.into_owned()),
+			// The resolver rejects `return` outside a function and `break`
+			// outside a loop, so neither should get this far.
+			RuntimeError::Return(_) | RuntimeError::Break => {
+				self.diagnose("Can't return or break out of the script.".into())
 			}
-			RuntimeError::Return(_) => unreachable!(),
 		}
 		self.had_runtime_error = true;
 	}
@@ -172,8 +954,24 @@ impl Lox {
 		}
 	}
 
+	fn resolve_warning(&mut self, warning: ResolveWarning) {
+		let ResolveWarning(token, message) = warning;
+		self.diagnose(format!(
+			"[line {}] Warning at '{}': {}",
+			token.line, token.lexeme, message
+		));
+	}
+
 	fn report(&mut self, line: usize, location: Cow<'_, str>, message: Cow<'_, str>) {
-		eprintln!("[line {}] Error {}: {}", line, location, message);
+		self.diagnose(format!("[line {}] Error {}: {}", line, location, message));
 		self.had_input_error = true;
 	}
+
+	/// Prints `message` to stderr, or keeps it if output is being captured.
+	fn diagnose(&mut self, message: String) {
+		match &mut self.diagnostics {
+			Some(diagnostics) => diagnostics.push(Diagnostic { message }),
+			None => eprintln!("{message}"),
+		}
+	}
 }
@@ -1,7 +1,20 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+	cell::RefCell,
+	cmp::Ordering,
+	fs::{File, OpenOptions},
+	io::{BufRead, BufReader, BufWriter, Read as _, Write as _},
+	path::Path,
+	rc::Rc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use rlox_common::number::Number;
 
 use crate::{
-	interpreter::{Interpreter, RuntimeError},
+	big_int::BigInt,
+	csv, encoding,
+	interpreter::{Interpreter, Iteration, RuntimeError},
+	literal::Literal,
 	lox_callable::LoxCallable,
 	object::Object,
 };
@@ -15,12 +28,16 @@ impl std::fmt::Debug for Clock {
 	}
 }
 
-impl LoxCallable for Clock {
+impl<'ast> LoxCallable<'ast> for Clock {
 	fn arity(&self) -> usize {
 		0
 	}
 
-	fn call(&self, _: &mut Interpreter, _: Vec<Object>) -> Result<Object, RuntimeError> {
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
 		Ok(SystemTime::now()
 			.duration_since(UNIX_EPOCH)
 			.unwrap()
@@ -28,3 +45,1553 @@ impl LoxCallable for Clock {
 			.into())
 	}
 }
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Println;
+
+impl std::fmt::Debug for Println {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn println>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Println {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		intpr.print(&args[0], true)?;
+		Ok(().into())
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Write;
+
+impl std::fmt::Debug for Write {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn write>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Write {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		intpr.print(&args[0], false)?;
+		Ok(().into())
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct ReadLines;
+
+impl std::fmt::Debug for ReadLines {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn readLines>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for ReadLines {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let path = string_argument(&args[0], "Path")?;
+		let contents = std::fs::read_to_string(path).map_err(|error| io_error(path, error))?;
+		Ok(Object::Tuple(
+			contents
+				.lines()
+				.map(|line| Object::from(line.to_owned()))
+				.collect(),
+		))
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct AppendFile;
+
+impl std::fmt::Debug for AppendFile {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn appendFile>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for AppendFile {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let path = string_argument(&args[0], "Path")?;
+		let text = string_argument(&args[1], "Text")?;
+		OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(path)
+			.and_then(|mut file| file.write_all(text.as_bytes()))
+			.map_err(|error| io_error(path, error))?;
+		Ok(().into())
+	}
+}
+
+/// Reads the rest of stdin as one string.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ReadAll;
+
+impl std::fmt::Debug for ReadAll {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn readAll>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for ReadAll {
+	fn arity(&self) -> usize {
+		0
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let mut input = String::new();
+		std::io::stdin()
+			.read_to_string(&mut input)
+			.map_err(|error| {
+				RuntimeError::Native(format!("Could not read stdin: {error}.").into())
+			})?;
+		Ok(input.into())
+	}
+}
+
+/// Calls a function of no arguments with what `print`, `println` and
+/// `write` output going to the file at a path instead, which it replaces.
+/// Returns what the function returns.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WithOutput;
+
+impl std::fmt::Debug for WithOutput {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn withOutput>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for WithOutput {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		mut args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let function = match args.pop().unwrap() {
+			Object::Callable(function) if function.arity() == 0 => function,
+			_ => {
+				return Err(RuntimeError::Native(
+					"Output must go to a function of no arguments.".into(),
+				))
+			}
+		};
+		let path = string_argument(&args[0], "Path")?;
+		let file = File::create(path).map_err(|error| io_error(path, error))?;
+
+		let output = intpr.replace_output(Box::new(BufWriter::new(file)));
+		let result = function.call(intpr, Vec::new());
+		let mut file = intpr.replace_output(output);
+		file.flush().map_err(|error| io_error(path, error))?;
+		result
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct OpenLines;
+
+impl std::fmt::Debug for OpenLines {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn openLines>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for OpenLines {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let path = string_argument(&args[0], "Path")?;
+		let file = File::open(path).map_err(|error| io_error(path, error))?;
+		Ok(Object::from_callable(Lines {
+			path: path.into(),
+			reader: Rc::new(RefCell::new(BufReader::new(file))),
+		}))
+	}
+}
+
+/// What `openLines` returns. Each call reads the next line of the file,
+/// without its line ending, or returns nil at the end, so a for-in loop
+/// can walk a file without holding all of it in memory.
+#[derive(Clone)]
+pub struct Lines {
+	path: Rc<str>,
+	reader: Rc<RefCell<BufReader<File>>>,
+}
+
+impl std::fmt::Debug for Lines {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<lines {}>", self.path)
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Lines {
+	fn arity(&self) -> usize {
+		0
+	}
+
+	fn identity(&self) -> usize {
+		Rc::as_ptr(&self.reader) as usize
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let mut line = String::new();
+		let read = self
+			.reader
+			.borrow_mut()
+			.read_line(&mut line)
+			.map_err(|error| io_error(&self.path, error))?;
+		if read == 0 {
+			return Ok(().into());
+		}
+		if line.ends_with('\n') {
+			line.pop();
+			if line.ends_with('\r') {
+				line.pop();
+			}
+		}
+		Ok(line.into())
+	}
+}
+
+/// Returns the name `is` tests a value's type against, e.g. "function" for
+/// natives and Lox functions alike.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Type;
+
+impl std::fmt::Debug for Type {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn type>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Type {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		Ok(args[0].type_name().to_string().into())
+	}
+}
+
+/// Fixes the first arguments of a function, returning a function of the
+/// rest, e.g. `bind(add, 1)(2)` is `add(1, 2)`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Bind;
+
+impl std::fmt::Debug for Bind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn bind>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Bind {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn variadic(&self) -> bool {
+		true
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		mut args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let arguments = args.split_off(1);
+		let callee = function_argument(args.pop().unwrap(), "bind")?;
+		if arguments.len() > callee.arity() && !callee.variadic() {
+			return Err(RuntimeError::Native(
+				format!(
+					"Can't bind {} arguments to a function of {}.",
+					arguments.len(),
+					callee.arity()
+				)
+				.into(),
+			));
+		}
+		Ok(Object::from_callable(Bound {
+			callee,
+			arguments: arguments.into(),
+		}))
+	}
+}
+
+/// Turns a function of several arguments into one that takes them one
+/// call at a time, e.g. `curry(add)(1)(2)` is `add(1, 2)`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Curry;
+
+impl std::fmt::Debug for Curry {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn curry>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Curry {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		mut args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let callee = function_argument(args.pop().unwrap(), "curry")?;
+		if callee.arity() == 1 {
+			return Ok(Object::Callable(callee));
+		}
+		Ok(Object::from_callable(Curried {
+			callee,
+			arguments: Rc::new([]),
+		}))
+	}
+}
+
+/// What `bind` returns: `callee` with its first `arguments` given. Each
+/// `bind` makes its own `arguments`, which tells the results apart.
+#[derive(Clone)]
+pub struct Bound<'ast> {
+	callee: Box<dyn LoxCallable<'ast> + 'ast>,
+	arguments: Rc<[Object<'ast>]>,
+}
+
+impl std::fmt::Debug for Bound<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<bound {:?}>", self.callee)
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Bound<'ast> {
+	fn arity(&self) -> usize {
+		self.callee.arity().saturating_sub(self.arguments.len())
+	}
+
+	fn variadic(&self) -> bool {
+		self.callee.variadic()
+	}
+
+	fn identity(&self) -> usize {
+		Rc::as_ptr(&self.arguments) as *const () as usize
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let mut arguments = self.arguments.to_vec();
+		arguments.extend(args);
+		self.callee.call(intpr, arguments)
+	}
+}
+
+/// What `curry` returns: takes one more argument of `callee` per call,
+/// and calls it once it has them all.
+#[derive(Clone)]
+pub struct Curried<'ast> {
+	callee: Box<dyn LoxCallable<'ast> + 'ast>,
+	arguments: Rc<[Object<'ast>]>,
+}
+
+impl std::fmt::Debug for Curried<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<curried {:?}>", self.callee)
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Curried<'ast> {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn identity(&self) -> usize {
+		Rc::as_ptr(&self.arguments) as *const () as usize
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let mut arguments = self.arguments.to_vec();
+		arguments.extend(args);
+		if arguments.len() == self.callee.arity() {
+			return self.callee.call(intpr, arguments);
+		}
+		Ok(Object::from_callable(Curried {
+			callee: self.callee.clone(),
+			arguments: arguments.into(),
+		}))
+	}
+}
+
+/// What `value.name` gives when `name` is a method of `value`'s type: the
+/// method with `value` bound as its first argument, so `"abc".len()` is
+/// the method `len` called with `"abc"`.
+pub fn method<'ast>(value: &Object<'ast>, name: &str) -> Option<Object<'ast>> {
+	let methods: &[Method] = match value {
+		Object::Literal(Literal::String(_)) => &STRING_METHODS,
+		Object::Tuple(_) => &TUPLE_METHODS,
+		_ => &[],
+	};
+	let method = methods.iter().find(|method| method.name == name)?;
+	Some(Object::from_callable(Bound {
+		callee: Box::new(method.clone()),
+		arguments: Rc::new([value.clone()]),
+	}))
+}
+
+const STRING_METHODS: [Method; 6] = [
+	Method {
+		name: "len",
+		arity: 1,
+		body: |args| Ok((string_receiver(&args[0])?.chars().count() as i64).into()),
+	},
+	Method {
+		name: "upper",
+		arity: 1,
+		body: |args| Ok(string_receiver(&args[0])?.to_uppercase().into()),
+	},
+	Method {
+		name: "lower",
+		arity: 1,
+		body: |args| Ok(string_receiver(&args[0])?.to_lowercase().into()),
+	},
+	Method {
+		name: "trim",
+		arity: 1,
+		body: |args| Ok(string_receiver(&args[0])?.trim().to_owned().into()),
+	},
+	Method {
+		name: "contains",
+		arity: 2,
+		body: |args| {
+			let part = string_argument(&args[1], "Argument of contains")?;
+			Ok(string_receiver(&args[0])?.contains(part).into())
+		},
+	},
+	Method {
+		name: "split",
+		arity: 2,
+		body: |args| {
+			let separator = string_argument(&args[1], "Separator")?;
+			if separator.is_empty() {
+				return Err(RuntimeError::Native("Separator must not be empty.".into()));
+			}
+			Ok(Object::Tuple(
+				string_receiver(&args[0])?
+					.split(separator)
+					.map(|part| Object::from(part.to_owned()))
+					.collect(),
+			))
+		},
+	},
+];
+
+const TUPLE_METHODS: [Method; 2] = [
+	Method {
+		name: "len",
+		arity: 1,
+		body: |args| Ok((tuple_receiver(&args[0])?.len() as i64).into()),
+	},
+	Method {
+		name: "contains",
+		arity: 2,
+		body: |args| Ok(tuple_receiver(&args[0])?.contains(&args[1]).into()),
+	},
+];
+
+/// A method of a built-in type, which takes the value it is called on as
+/// its first argument.
+#[derive(Clone)]
+pub struct Method {
+	name: &'static str,
+	arity: usize,
+	body: for<'ast> fn(Vec<Object<'ast>>) -> Result<Object<'ast>, RuntimeError<'ast>>,
+}
+
+impl std::fmt::Debug for Method {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<native method {}>", self.name)
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Method {
+	fn arity(&self) -> usize {
+		self.arity
+	}
+
+	fn identity(&self) -> usize {
+		self.body as usize
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		(self.body)(args)
+	}
+}
+
+/// The string a string method is called on, which `method` made sure of.
+fn string_receiver<'a, 'ast>(receiver: &'a Object<'ast>) -> Result<&'a str, RuntimeError<'ast>> {
+	string_argument(receiver, "Receiver of a string method")
+}
+
+/// The elements of the tuple a tuple method is called on.
+fn tuple_receiver<'a, 'ast>(
+	receiver: &'a Object<'ast>,
+) -> Result<&'a [Object<'ast>], RuntimeError<'ast>> {
+	match receiver {
+		Object::Tuple(elements) => Ok(elements),
+		_ => Err(RuntimeError::Native(
+			"Receiver of a tuple method must be a tuple.".into(),
+		)),
+	}
+}
+
+/// Sorts the elements of a sequence into a tuple, calling back into Lox
+/// to order them: in `sort(sequence, compare)`, `compare(a, b)` returns a
+/// negative number if `a` comes first, a positive one if `b` does, and
+/// zero to keep their order.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Sort;
+
+impl std::fmt::Debug for Sort {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn sort>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Sort {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		mut args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let compare = match args.pop().unwrap() {
+			Object::Callable(compare) if compare.arity() == 2 => compare,
+			_ => {
+				return Err(RuntimeError::Native(
+					"Second argument of sort must be a function of two arguments.".into(),
+				))
+			}
+		};
+		let mut iteration = match args.pop().unwrap() {
+			Object::Tuple(elements) => Iteration::Tuple(elements, 0),
+			Object::Callable(next) if next.arity() == 0 => Iteration::Function(next),
+			_ => {
+				return Err(RuntimeError::Native(
+					"First argument of sort must be a sequence.".into(),
+				))
+			}
+		};
+		let mut elements = Vec::new();
+		while let Some(element) = iteration.next(intpr)? {
+			elements.push(element);
+		}
+		let sorted = merge_sort(elements, &mut |a, b| match compare
+			.call(intpr, vec![a.clone(), b.clone()])?
+			.as_number()
+		{
+			Some(n) => Ok(n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+			None => Err(RuntimeError::Native(
+				"Comparison function of sort must return a number.".into(),
+			)),
+		})?;
+		Ok(Object::Tuple(sorted.into()))
+	}
+}
+
+/// Sorts `elements` stably, stopping at the first error `compare` gives.
+/// Unlike `sort_by`, a `compare` that is not a total order only leaves
+/// the elements in some order rather than panicking.
+fn merge_sort<T, E>(
+	mut elements: Vec<T>,
+	compare: &mut impl FnMut(&T, &T) -> Result<Ordering, E>,
+) -> Result<Vec<T>, E> {
+	if elements.len() <= 1 {
+		return Ok(elements);
+	}
+	let right = elements.split_off(elements.len() / 2);
+	let left = merge_sort(elements, compare)?;
+	let right = merge_sort(right, compare)?;
+	let mut merged = Vec::with_capacity(left.len() + right.len());
+	let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+	while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+		// Ties go to the left, which keeps equal elements in order.
+		let next = if compare(a, b)? == Ordering::Greater {
+			right.next()
+		} else {
+			left.next()
+		};
+		merged.extend(next);
+	}
+	merged.extend(left);
+	merged.extend(right);
+	Ok(merged)
+}
+
+/// Registers a function of no arguments as a test, for `--test` to run
+/// once the script has finished. Otherwise tests are never run.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Test;
+
+impl std::fmt::Debug for Test {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn test>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Test {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		mut args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let function = match args.pop().unwrap() {
+			Object::Callable(function) if function.arity() == 0 => function,
+			_ => {
+				return Err(RuntimeError::Native(
+					"Test must be a function of no arguments.".into(),
+				))
+			}
+		};
+		let name = string_argument(&args[0], "Test name")?.to_owned();
+		intpr.tests.push((name, function));
+		Ok(().into())
+	}
+}
+
+/// Raises an error unless `actual == expected`, which fails the test it
+/// is called from.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Expect;
+
+impl std::fmt::Debug for Expect {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn expect>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Expect {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let (actual, expected) = (&args[0], &args[1]);
+		if actual != expected {
+			return Err(RuntimeError::Native(
+				format!("Expected {expected} but got {actual}.").into(),
+			));
+		}
+		Ok(().into())
+	}
+}
+
+/// Formats a number with a given count of digits after the decimal point,
+/// e.g. `toFixed(3.14159, 2)` is "3.14".
+#[derive(Clone, PartialEq, Eq)]
+pub struct ToFixed;
+
+impl std::fmt::Debug for ToFixed {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn toFixed>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for ToFixed {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let x = number_argument(&args[0], "Number")?;
+		let digits = count_argument(&args[1], "Digits", 0..=MAX_DIGITS)?;
+		if !x.is_finite() {
+			return Ok(Number(x).to_string().into());
+		}
+		Ok(format!("{x:.digits$}").into())
+	}
+}
+
+/// Formats a number with a given count of significant digits, e.g.
+/// `toPrecision(3.14159, 3)` is "3.14". Magnitudes too large or too
+/// small for that many digits are written with an exponent instead, as in
+/// "1.2e+3".
+#[derive(Clone, PartialEq, Eq)]
+pub struct ToPrecision;
+
+impl std::fmt::Debug for ToPrecision {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn toPrecision>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for ToPrecision {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let x = number_argument(&args[0], "Number")?;
+		let digits = count_argument(&args[1], "Digits", 1..=MAX_DIGITS)?;
+		if !x.is_finite() {
+			return Ok(Number(x).to_string().into());
+		}
+		// Rounding to the digits first gives the exponent of what is
+		// printed, which may be one more than that of `x` itself.
+		let scientific = format!("{:.*e}", digits - 1, x);
+		let (mantissa, exponent) = scientific.split_once('e').expect("`e` format");
+		let exponent: i32 = exponent.parse().expect("`e` format exponent");
+		if (-6..digits as i32).contains(&exponent) {
+			let decimals = (digits as i32 - 1 - exponent) as usize;
+			Ok(format!("{x:.decimals$}").into())
+		} else {
+			let sign = if exponent < 0 { '-' } else { '+' };
+			Ok(format!("{mantissa}e{sign}{}", exponent.unsigned_abs()).into())
+		}
+	}
+}
+
+/// The most digits `toFixed` and `toPrecision` give.
+const MAX_DIGITS: usize = 100;
+
+/// The widest `pad` pads to, so that a mistaken width is an error rather
+/// than an allocation that fails.
+const MAX_WIDTH: usize = 1 << 20;
+
+/// Pads a value as printed with a one-character string up to a width:
+/// on the left for a positive width, aligning it right, and on the right
+/// for a negative one. Longer values are left as they are.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pad;
+
+impl std::fmt::Debug for Pad {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn pad>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Pad {
+	fn arity(&self) -> usize {
+		3
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let text = args[0].to_string();
+		let width = match args[1].as_number() {
+			Some(width) if width.fract() == 0.0 && width.abs() <= MAX_WIDTH as f64 => width,
+			_ => {
+				return Err(RuntimeError::Native(
+					format!("Width must be a whole number from -{MAX_WIDTH} to {MAX_WIDTH}.")
+						.into(),
+				))
+			}
+		};
+		let mut fill = string_argument(&args[2], "Fill")?.chars();
+		let (Some(fill), None) = (fill.next(), fill.next()) else {
+			return Err(RuntimeError::Native(
+				"Fill must be a single character.".into(),
+			));
+		};
+		let padding = (width.abs() as usize).saturating_sub(text.chars().count());
+		let padding = std::iter::repeat_n(fill, padding).collect::<String>();
+		Ok(if width < 0.0 {
+			text + &padding
+		} else {
+			padding + &text
+		}
+		.into())
+	}
+}
+
+/// A native that turns one string into another, such as an encoding.
+#[derive(Clone)]
+pub struct StringFunction {
+	pub name: &'static str,
+	convert: fn(&str) -> Result<String, &'static str>,
+}
+
+impl StringFunction {
+	pub const BASE64_ENCODE: Self = Self {
+		name: "base64Encode",
+		convert: |text| Ok(encoding::base64_encode(text.as_bytes())),
+	};
+	pub const BASE64_DECODE: Self = Self {
+		name: "base64Decode",
+		convert: |text| {
+			decoded(
+				encoding::base64_decode(text),
+				"Argument is not valid base64.",
+			)
+		},
+	};
+	pub const HEX_ENCODE: Self = Self {
+		name: "hexEncode",
+		convert: |text| Ok(encoding::hex_encode(text.as_bytes())),
+	};
+	pub const HEX_DECODE: Self = Self {
+		name: "hexDecode",
+		convert: |text| decoded(encoding::hex_decode(text), "Argument is not valid hex."),
+	};
+	/// The directory part of a path, or "." if it has none.
+	pub const DIRNAME: Self = Self {
+		name: "dirname",
+		convert: |path| {
+			let path = Path::new(path);
+			Ok(match path.parent() {
+				Some(parent) if parent.as_os_str().is_empty() => ".".to_owned(),
+				Some(parent) => parent.to_string_lossy().into_owned(),
+				// A root, or nothing at all.
+				None => path.to_string_lossy().into_owned(),
+			})
+		},
+	};
+	/// The last part of a path, or "" if it ends in `..` or is a root.
+	pub const BASENAME: Self = Self {
+		name: "basename",
+		convert: |path| {
+			Ok(Path::new(path)
+				.file_name()
+				.map(|name| name.to_string_lossy().into_owned())
+				.unwrap_or_default())
+		},
+	};
+	/// The MD5 digest of the string's bytes, in hex.
+	#[cfg(feature = "hash")]
+	pub const MD5: Self = Self {
+		name: "md5",
+		convert: |text| {
+			use md5::Digest;
+			Ok(encoding::hex_encode(&md5::Md5::digest(text)))
+		},
+	};
+	/// The SHA-256 digest of the string's bytes, in hex.
+	#[cfg(feature = "hash")]
+	pub const SHA256: Self = Self {
+		name: "sha256",
+		convert: |text| {
+			use sha2::Digest;
+			Ok(encoding::hex_encode(&sha2::Sha256::digest(text)))
+		},
+	};
+}
+
+/// Decoded bytes as a string, which they must be valid UTF-8 for, or
+/// `invalid` if they could not be decoded.
+fn decoded(bytes: Option<Vec<u8>>, invalid: &'static str) -> Result<String, &'static str> {
+	let bytes = bytes.ok_or(invalid)?;
+	String::from_utf8(bytes).map_err(|_| "Decoded bytes are not valid UTF-8.")
+}
+
+impl std::fmt::Debug for StringFunction {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<native fn {}>", self.name)
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for StringFunction {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn identity(&self) -> usize {
+		self.convert as usize
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let text = string_argument(&args[0], "Argument")?;
+		match (self.convert)(text) {
+			Ok(converted) => Ok(converted.into()),
+			Err(message) => Err(RuntimeError::Native(message.into())),
+		}
+	}
+}
+
+/// Runs a program with string arguments, given after it, and waits for it
+/// to finish. Returns its exit status, which is nil if a signal ended it, and
+/// what it wrote to stdout and stderr, as a tuple. Only allowed if the
+/// interpreter allows processes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Exec;
+
+impl std::fmt::Debug for Exec {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn exec>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Exec {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn variadic(&self) -> bool {
+		true
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		if !intpr.allow_process {
+			return Err(RuntimeError::Native(
+				"Running programs is not allowed; run with --allow-process to allow it.".into(),
+			));
+		}
+		let program = string_argument(&args[0], "Program")?;
+		let arguments = args[1..]
+			.iter()
+			.map(|argument| string_argument(argument, "Each argument of the program"))
+			.collect::<Result<Vec<_>, _>>()?;
+		let output = std::process::Command::new(program)
+			.args(arguments)
+			.stdin(std::process::Stdio::null())
+			.output()
+			.map_err(|error| {
+				RuntimeError::Native(format!("Could not run '{program}': {error}.").into())
+			})?;
+		let status = match output.status.code() {
+			Some(code) => Object::from(i64::from(code)),
+			None => ().into(),
+		};
+		Ok(Object::Tuple(Rc::new([
+			status,
+			String::from_utf8_lossy(&output.stdout).into_owned().into(),
+			String::from_utf8_lossy(&output.stderr).into_owned().into(),
+		])))
+	}
+}
+
+/// Parses CSV, or TSV for a tab delimiter, into a tuple of records, each
+/// a tuple of its fields as strings.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TableParse(pub char);
+
+impl std::fmt::Debug for TableParse {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<native fn {}Parse>", table_format(self.0))
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for TableParse {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn identity(&self) -> usize {
+		self.0 as usize
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let text = string_argument(&args[0], "Text")?;
+		let records =
+			csv::parse(text, self.0).map_err(|message| RuntimeError::Native(message.into()))?;
+		Ok(Object::Tuple(
+			records
+				.into_iter()
+				.map(|record| Object::Tuple(record.into_iter().map(Object::from).collect()))
+				.collect(),
+		))
+	}
+}
+
+/// Writes a tuple of records, each a tuple of values, as CSV, or TSV for
+/// a tab delimiter. Values are written as `print` shows them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TableStringify(pub char);
+
+impl std::fmt::Debug for TableStringify {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "<native fn {}Stringify>", table_format(self.0))
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for TableStringify {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn identity(&self) -> usize {
+		self.0 as usize
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let not_records = || {
+			RuntimeError::Native(
+				format!(
+					"Argument of {}Stringify must be a tuple of tuples.",
+					table_format(self.0)
+				)
+				.into(),
+			)
+		};
+		let Object::Tuple(records) = &args[0] else {
+			return Err(not_records());
+		};
+		let records = records
+			.iter()
+			.map(|record| match record {
+				Object::Tuple(fields) => Ok(fields.iter().map(Object::to_string).collect()),
+				_ => Err(not_records()),
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(csv::stringify(&records, self.0).into())
+	}
+}
+
+/// What the natives for tables with `delimiter` are named after.
+fn table_format(delimiter: char) -> &'static str {
+	if delimiter == '\t' {
+		"tsv"
+	} else {
+		"csv"
+	}
+}
+
+/// Joins two paths with the separator of the OS. If the second path is
+/// absolute, it is the result.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PathJoin;
+
+impl std::fmt::Debug for PathJoin {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn pathJoin>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for PathJoin {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let base = string_argument(&args[0], "Path")?;
+		let path = string_argument(&args[1], "Path")?;
+		Ok(Path::new(base)
+			.join(path)
+			.to_string_lossy()
+			.into_owned()
+			.into())
+	}
+}
+
+/// Whether anything is at a path. Broken symbolic links count as nothing.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Exists;
+
+impl std::fmt::Debug for Exists {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn exists>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Exists {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let path = string_argument(&args[0], "Path")?;
+		Ok(Path::new(path).exists().into())
+	}
+}
+
+/// Whether a path is a directory, following symbolic links.
+#[derive(Clone, PartialEq, Eq)]
+pub struct IsDir;
+
+impl std::fmt::Debug for IsDir {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn isDir>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for IsDir {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let path = string_argument(&args[0], "Path")?;
+		Ok(Path::new(path).is_dir().into())
+	}
+}
+
+/// The names of the entries of a directory, sorted, as a tuple.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ListDir;
+
+impl std::fmt::Debug for ListDir {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn listDir>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for ListDir {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let path = string_argument(&args[0], "Path")?;
+		let mut names = std::fs::read_dir(path)
+			.and_then(|entries| {
+				entries
+					.map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+					.collect::<std::io::Result<Vec<_>>>()
+			})
+			.map_err(|error| io_error(path, error))?;
+		names.sort();
+		Ok(Object::Tuple(names.into_iter().map(Object::from).collect()))
+	}
+}
+
+/// Adds two integers of any size, each given as a string of decimal
+/// digits or as a whole number, and returns the sum as a string.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BigAdd;
+
+impl std::fmt::Debug for BigAdd {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn bigAdd>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for BigAdd {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let a = big_int_argument(&args[0], "bigAdd")?;
+		let b = big_int_argument(&args[1], "bigAdd")?;
+		Ok(a.add(&b).to_string().into())
+	}
+}
+
+/// Multiplies two integers of any size, as `bigAdd` adds them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BigMul;
+
+impl std::fmt::Debug for BigMul {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn bigMul>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for BigMul {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let a = big_int_argument(&args[0], "bigMul")?;
+		let b = big_int_argument(&args[1], "bigMul")?;
+		Ok(a.mul(&b).to_string().into())
+	}
+}
+
+fn big_int_argument<'ast>(
+	argument: &Object<'ast>,
+	native: &str,
+) -> Result<BigInt, RuntimeError<'ast>> {
+	let big_int = match argument {
+		Object::Literal(Literal::Integer(n)) => Some(BigInt::from(*n)),
+		Object::Literal(Literal::String(digits)) => BigInt::parse(digits),
+		_ => None,
+	};
+	big_int.ok_or_else(|| {
+		RuntimeError::Native(
+			format!("Arguments of {native} must be whole numbers or strings of digits.").into(),
+		)
+	})
+}
+
+/// The function passed to `native`, which must take at least one argument
+/// for there to be anything to bind.
+fn function_argument<'ast>(
+	argument: Object<'ast>,
+	native: &str,
+) -> Result<Box<dyn LoxCallable<'ast> + 'ast>, RuntimeError<'ast>> {
+	match argument {
+		Object::Callable(callee) if callee.arity() > 0 => Ok(callee),
+		_ => Err(RuntimeError::Native(
+			format!("Argument of {native} must be a function that takes arguments.").into(),
+		)),
+	}
+}
+
+fn number_argument<'ast>(argument: &Object<'ast>, what: &str) -> Result<f64, RuntimeError<'ast>> {
+	argument
+		.as_number()
+		.ok_or_else(|| RuntimeError::Native(format!("{what} must be a number.").into()))
+}
+
+/// A whole number in `range`, such as a count of digits.
+fn count_argument<'ast>(
+	argument: &Object<'ast>,
+	what: &str,
+	range: std::ops::RangeInclusive<usize>,
+) -> Result<usize, RuntimeError<'ast>> {
+	match argument.as_number() {
+		Some(n)
+			if n.fract() == 0.0 && (*range.start() as f64..=*range.end() as f64).contains(&n) =>
+		{
+			Ok(n as usize)
+		}
+		_ => Err(RuntimeError::Native(
+			format!(
+				"{what} must be a whole number from {} to {}.",
+				range.start(),
+				range.end()
+			)
+			.into(),
+		)),
+	}
+}
+
+fn string_argument<'a, 'ast>(
+	argument: &'a Object<'ast>,
+	what: &str,
+) -> Result<&'a str, RuntimeError<'ast>> {
+	match argument {
+		Object::Literal(Literal::String(string)) => Ok(string),
+		_ => Err(RuntimeError::Native(
+			format!("{what} must be a string.").into(),
+		)),
+	}
+}
+
+fn io_error<'ast>(path: &str, error: std::io::Error) -> RuntimeError<'ast> {
+	RuntimeError::Native(format!("Could not access '{path}': {error}.").into())
+}
+
+/// Returns how many environments are alive and how many have been created
+/// so far, as a tuple, so scripts can check what they keep alive.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MemoryUsage;
+
+impl std::fmt::Debug for MemoryUsage {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn memoryUsage>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for MemoryUsage {
+	fn arity(&self) -> usize {
+		0
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let (live, created) = intpr.environment_counts.get();
+		Ok(Object::Tuple(Rc::new([
+			(live as f64).into(),
+			(created as f64).into(),
+		])))
+	}
+}
+
+/// Prints a value with nested tuples spread over indented lines, showing
+/// tuples at most a given count of levels deep.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrettyPrint;
+
+impl std::fmt::Debug for PrettyPrint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn prettyPrint>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for PrettyPrint {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let depth = match args[1].as_number() {
+			Some(n) if n.fract() == 0.0 && (0.0..=MAX_PRETTY_DEPTH as f64).contains(&n) => {
+				n as usize
+			}
+			_ => {
+				return Err(RuntimeError::Native(
+					format!("Depth must be a whole number from 0 to {MAX_PRETTY_DEPTH}.").into(),
+				))
+			}
+		};
+		intpr.print(&args[0].pretty(depth).into(), true)?;
+		Ok(().into())
+	}
+}
+
+/// The deepest `prettyPrint` looks, which keeps its recursion shallow.
+const MAX_PRETTY_DEPTH: usize = 1024;
+
+/// Returns every global that has a value as a tuple of `(name, value)`
+/// tuples, sorted by name.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Globals;
+
+impl std::fmt::Debug for Globals {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn globals>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Globals {
+	fn arity(&self) -> usize {
+		0
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let mut globals = intpr
+			.globals
+			.iter()
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect::<Vec<_>>();
+		globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+		Ok(bindings(globals))
+	}
+}
+
+/// Returns the local variables that have a value where it is called, like
+/// `globals`. The resolver has to see the call to know their names, so
+/// `locals` only works when called by that name.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Locals;
+
+impl std::fmt::Debug for Locals {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn locals>")
+	}
+}
+
+impl<'ast> LoxCallable<'ast> for Locals {
+	fn arity(&self) -> usize {
+		0
+	}
+
+	fn call(
+		&self,
+		intpr: &mut Interpreter<'ast>,
+		_: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		match intpr.call_locals() {
+			Some(locals) => Ok(bindings(locals)),
+			None => Err(RuntimeError::Native(
+				"Can only list locals where 'locals' is called by name.".into(),
+			)),
+		}
+	}
+}
+
+/// `(name, value)` pairs as a tuple of tuples.
+fn bindings<'ast>(bindings: Vec<(Rc<str>, Object<'ast>)>) -> Object<'ast> {
+	Object::Tuple(
+		bindings
+			.into_iter()
+			.map(|(name, value)| Object::Tuple(Rc::new([Literal::String(name).into(), value])))
+			.collect(),
+	)
+}
+
+#[cfg(feature = "http")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpGet;
+
+#[cfg(feature = "http")]
+impl std::fmt::Debug for HttpGet {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn httpGet>")
+	}
+}
+
+#[cfg(feature = "http")]
+impl<'ast> LoxCallable<'ast> for HttpGet {
+	fn arity(&self) -> usize {
+		1
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let url = string_argument(&args[0], "URL")?;
+		response_body(ureq::get(url).call())
+	}
+}
+
+#[cfg(feature = "http")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct HttpPost;
+
+#[cfg(feature = "http")]
+impl std::fmt::Debug for HttpPost {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("<native fn httpPost>")
+	}
+}
+
+#[cfg(feature = "http")]
+impl<'ast> LoxCallable<'ast> for HttpPost {
+	fn arity(&self) -> usize {
+		2
+	}
+
+	fn call(
+		&self,
+		_: &mut Interpreter<'ast>,
+		args: Vec<Object<'ast>>,
+	) -> Result<Object<'ast>, RuntimeError<'ast>> {
+		let url = string_argument(&args[0], "URL")?;
+		let body = string_argument(&args[1], "Body")?;
+		response_body(ureq::post(url).send_string(body))
+	}
+}
+
+/// The body of a successful response as a string. Failed requests,
+/// including those answered with an error status, are runtime errors.
+#[cfg(feature = "http")]
+fn response_body<'ast>(
+	response: Result<ureq::Response, ureq::Error>,
+) -> Result<Object<'ast>, RuntimeError<'ast>> {
+	let failed = |error: &dyn std::fmt::Display| {
+		RuntimeError::Native(format!("HTTP request failed: {error}").into())
+	};
+	let response = response.map_err(|error| failed(&error))?;
+	let body = response.into_string().map_err(|error| failed(&error))?;
+	Ok(body.into())
+}
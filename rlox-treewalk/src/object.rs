@@ -1,37 +1,109 @@
+use std::rc::Rc;
+
 use crate::{literal::Literal, lox_callable::LoxCallable};
 
 #[derive(Debug, Clone)]
-pub enum Object {
+pub enum Object<'ast> {
 	Literal(Literal),
-	Callable(Box<dyn LoxCallable>),
+	Callable(Box<dyn LoxCallable<'ast> + 'ast>),
+	/// An immutable, fixed-size group of values, e.g. what `return a, b;`
+	/// produces.
+	Tuple(Rc<[Object<'ast>]>),
 }
 
-impl Object {
-	pub fn from_callable<T: 'static + LoxCallable>(callable: T) -> Self {
+impl<'ast> Object<'ast> {
+	pub fn from_callable<T: 'ast + LoxCallable<'ast>>(callable: T) -> Self {
 		Object::Callable(Box::new(callable))
 	}
+
+	/// Every name `type_name` can return.
+	pub const TYPE_NAMES: [&'static str; 6] =
+		["number", "string", "boolean", "nil", "function", "tuple"];
+
+	/// The name `is` tests this value's type against.
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Object::Literal(Literal::Number(_) | Literal::Integer(_)) => "number",
+			Object::Literal(Literal::String(_)) => "string",
+			Object::Literal(Literal::Boolean(_)) => "boolean",
+			Object::Literal(Literal::Nil) => "nil",
+			Object::Callable(_) => "function",
+			Object::Tuple(_) => "tuple",
+		}
+	}
+
+	/// This value as `prettyPrint` shows it: a tuple holding other tuples
+	/// has one element per line, indented under it, and tuples nested more
+	/// than `depth` deep are cut short as `(...)`.
+	pub fn pretty(&self, depth: usize) -> String {
+		let mut out = String::new();
+		self.write_pretty(&mut out, depth, 0);
+		out
+	}
+
+	fn write_pretty(&self, out: &mut String, depth: usize, indent: usize) {
+		let Object::Tuple(elements) = self else {
+			return out.push_str(&self.to_string());
+		};
+		if depth == 0 {
+			return out.push_str("(...)");
+		}
+		if !elements
+			.iter()
+			.any(|element| matches!(element, Object::Tuple(_)))
+		{
+			return out.push_str(&self.to_string());
+		}
+		out.push_str("(\n");
+		for (i, element) in elements.iter().enumerate() {
+			out.push_str(&" ".repeat(indent + 2));
+			element.write_pretty(out, depth - 1, indent + 2);
+			out.push_str(if i + 1 < elements.len() { ",\n" } else { "\n" });
+		}
+		out.push_str(&" ".repeat(indent));
+		out.push(')');
+	}
+
+	/// The value of a number, however it is kept.
+	pub fn as_number(&self) -> Option<f64> {
+		match self {
+			Object::Literal(literal) => literal.as_number(),
+			_ => None,
+		}
+	}
 }
 
-impl std::fmt::Display for Object {
+impl std::fmt::Display for Object<'_> {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
 			Object::Literal(lit) => lit.fmt(f),
 			Object::Callable(callable) => callable.fmt(f),
+			Object::Tuple(elements) => {
+				f.write_str("(")?;
+				for (i, element) in elements.iter().enumerate() {
+					if i > 0 {
+						f.write_str(", ")?;
+					}
+					element.fmt(f)?;
+				}
+				f.write_str(")")
+			}
 		}
 	}
 }
 
-impl<T: Into<Literal>> From<T> for Object {
+impl<T: Into<Literal>> From<T> for Object<'_> {
 	fn from(lit: T) -> Self {
 		Self::Literal(lit.into())
 	}
 }
 
-impl PartialEq for Object {
+impl PartialEq for Object<'_> {
 	fn eq(&self, other: &Self) -> bool {
 		match (self, other) {
 			(Self::Literal(l0), Self::Literal(r0)) => l0 == r0,
 			(Self::Callable(l0), Self::Callable(r0)) => l0 == r0,
+			(Self::Tuple(l0), Self::Tuple(r0)) => l0 == r0,
 			_ => false,
 		}
 	}
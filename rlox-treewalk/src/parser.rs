@@ -1,59 +1,123 @@
-use std::rc::Rc;
+use std::borrow::Cow;
+
+use bumpalo::{collections::Vec as BumpVec, Bump};
+use rlox_common::number::integer_literal;
 
 use crate::{
-	expr::Expr,
+	expr::{Expr, ExprId},
 	literal::Literal,
+	scanner::{string_contents, unescape},
 	stmt::{Stmt, StmtFunction},
 	token::Token,
 	token_type::TokenTy,
 };
 
-#[derive(Default)]
-pub struct Parser {
-	tokens: Vec<Token>,
+/// How deeply expressions and statements may nest, counting each operator
+/// of a chain such as `a + b + c` as one level. Every later pass walks the
+/// tree recursively, so deeper trees could overflow the stack.
+const MAX_NESTING: usize = 1024;
+
+pub struct Parser<'ast> {
+	arena: &'ast Bump,
+	tokens: Vec<Token<'ast>>,
 	current: usize,
-	errors: Vec<ParseError>,
+	errors: Vec<ParseError<'ast>>,
+	/// Whether an error has been reported since the current declaration
+	/// started, so that those following from it are not.
+	panicking: bool,
+	/// How many blocks the parser is in, so recovering from an error stops
+	/// at the `}` that ends the innermost one.
+	depth: usize,
+	/// How many expressions and statements the parser is inside.
+	nesting: usize,
+	/// Whether `print` starts a statement. When off, `print` is an ordinary
+	/// name that refers to the native function of the same name.
+	print_statement: bool,
 }
 
-impl Parser {
-	pub fn new(tokens: Vec<Token>) -> Self {
+impl<'ast> Parser<'ast> {
+	/// Creates a parser that allocates every node it builds in `arena`.
+	pub fn new(arena: &'ast Bump, tokens: Vec<Token<'ast>>) -> Self {
 		Self {
+			arena,
 			tokens,
-			..Default::default()
+			current: 0,
+			errors: Vec::new(),
+			panicking: false,
+			depth: 0,
+			nesting: 0,
+			print_statement: true,
 		}
 	}
 
-	pub fn parse(mut self) -> Result<Vec<Stmt>> {
-		let mut statements = Vec::new();
+	pub fn with_print_statement(mut self, enabled: bool) -> Self {
+		self.print_statement = enabled;
+		self
+	}
+
+	/// Parses the whole script, even past errors. Each declaration that
+	/// fails to parse leaves a `Stmt::Error` in its place, so the rest of
+	/// the script can still be resolved.
+	pub fn parse(mut self) -> (&'ast [Stmt<'ast>], Option<ParseError<'ast>>) {
+		let mut statements = BumpVec::new_in(self.arena);
 		while !self.is_at_end() {
-			match self.declaration() {
-				Ok(stmt) => {
-					statements.push(stmt);
-				}
-				Err(err) => {
-					self.errors.push(err);
-					self.synchonize();
-				}
-			}
+			self.recovering_declaration(&mut statements);
 		}
+		// The scanner has already reported whatever it could not scan, and
+		// anything the parser then trips over there would only repeat it.
+		self.errors.retain(
+			|err| !matches!(err, ParseError::Custom(token, _) if token.ty == TokenTy::Error),
+		);
+		let statements = statements.into_bump_slice();
 		if self.errors.is_empty() {
-			Ok(statements)
+			(statements, None)
 		} else {
-			Err(ParseError::Multiple(self.errors))
+			(statements, Some(ParseError::Multiple(self.errors)))
 		}
 	}
 
-	fn declaration(&mut self) -> Result<Stmt> {
-		if self.matches([TokenTy::Var]) {
-			self.var_declaration()
+	/// Parses one declaration into `statements` like `declaration`, or a
+	/// `Stmt::Error` if it fails, skipping to where the next one starts.
+	fn recovering_declaration(&mut self, statements: &mut BumpVec<'ast, Stmt<'ast>>) {
+		self.panicking = false;
+		let nesting = self.nesting;
+		if let Err(err) = self.declaration(statements) {
+			self.nesting = nesting;
+			let ParseError::Custom(token, _) = &err else {
+				unreachable!("only the whole parse has multiple errors")
+			};
+			statements.push(Stmt::Error(token.clone()));
+			self.error(err);
+			self.synchonize();
+		}
+	}
+
+	/// Reports `err`, unless it follows from one already reported.
+	fn error(&mut self, err: ParseError<'ast>) {
+		if !self.panicking {
+			self.errors.push(err);
+		}
+		self.panicking = true;
+	}
+
+	/// Parses one declaration into `statements`. A `var` declaring several
+	/// variables becomes one statement for each.
+	fn declaration(&mut self, statements: &mut BumpVec<'ast, Stmt<'ast>>) -> Result<'ast, ()> {
+		let statement = if self.matches([TokenTy::Var]) {
+			return self.var_declaration(statements);
+		} else if self.matches([TokenTy::Const]) {
+			self.const_declaration()
 		} else if self.matches([TokenTy::Fun]) {
 			self.function("function")
 		} else {
 			self.statement()
-		}
+		};
+		statements.push(statement?);
+		Ok(())
 	}
 
-	fn function(&mut self, kind: &'static str) -> Result<Stmt> {
+	fn function(&mut self, kind: &'static str) -> Result<'ast, Stmt<'ast>> {
+		let generator = self.matches([TokenTy::Star]);
 		let name = self
 			.consume(TokenTy::Identifier, format!("Expect {kind} name.").into())?
 			.clone();
@@ -61,7 +125,7 @@ impl Parser {
 			TokenTy::LeftParen,
 			format!("Expect '(' after {kind} name.").into(),
 		)?;
-		let mut params = Vec::new();
+		let mut params = BumpVec::new_in(self.arena);
 
 		if !self.check(TokenTy::RightParen) {
 			loop {
@@ -89,70 +153,166 @@ impl Parser {
 		)?;
 		let body = self.block()?;
 
-		Ok(Stmt::Function(Rc::new(StmtFunction { name, params, body })))
+		Ok(Stmt::Function(self.arena.alloc(StmtFunction {
+			name,
+			generator,
+			params: params.into_bump_slice(),
+			body,
+		})))
+	}
+
+	fn var_declaration(&mut self, statements: &mut BumpVec<'ast, Stmt<'ast>>) -> Result<'ast, ()> {
+		if self.matches([TokenTy::LeftParen]) {
+			statements.push(self.var_tuple_declaration()?);
+			return Ok(());
+		}
+
+		loop {
+			let name = self
+				.consume(TokenTy::Identifier, "Expect variable name.".into())?
+				.clone();
+
+			let initializer = self
+				.matches([TokenTy::Equal])
+				.then(|| self.expression())
+				.transpose();
+			let initializer = initializer?;
+
+			statements.push(Stmt::Var {
+				name,
+				initializer,
+				constant: false,
+			});
+
+			if !self.matches([TokenTy::Comma]) {
+				break;
+			}
+		}
+
+		self.end_statement("Expect ';' after variable declaration.")?;
+		Ok(())
 	}
 
-	fn var_declaration(&mut self) -> Result<Stmt> {
+	fn const_declaration(&mut self) -> Result<'ast, Stmt<'ast>> {
 		let name = self
-			.consume(TokenTy::Identifier, "Expect variable name.".into())?
+			.consume(TokenTy::Identifier, "Expect constant name.".into())?
 			.clone();
+		self.consume(TokenTy::Equal, "Expect '=' after constant name.".into())?;
+		let initializer = self.expression()?;
+		self.end_statement("Expect ';' after constant declaration.")?;
+
+		Ok(Stmt::Var {
+			name,
+			initializer: Some(initializer),
+			constant: true,
+		})
+	}
 
-		let initializer = self
-			.matches([TokenTy::Equal])
-			.then(|| self.expression())
-			.transpose();
-		let initializer = initializer?;
-
+	fn var_tuple_declaration(&mut self) -> Result<'ast, Stmt<'ast>> {
+		let mut names = BumpVec::new_in(self.arena);
+		loop {
+			names.push(
+				self.consume(TokenTy::Identifier, "Expect variable name.".into())?
+					.clone(),
+			);
+			if !self.matches([TokenTy::Comma]) {
+				break;
+			}
+		}
 		self.consume(
-			TokenTy::Semicolon,
-			"Expect ';' after variable declaration.".into(),
+			TokenTy::RightParen,
+			"Expect ')' after variable names.".into(),
 		)?;
+		let equals = self
+			.consume(
+				TokenTy::Equal,
+				"Expect '=' after destructuring declaration.".into(),
+			)?
+			.clone();
+		let initializer = self.expression()?;
+
+		self.end_statement("Expect ';' after variable declaration.")?;
+
+		Ok(Stmt::VarTuple {
+			names: names.into_bump_slice(),
+			equals,
+			initializer,
+		})
+	}
 
-		Ok(Stmt::Var { name, initializer })
-	}
-
-	fn statement(&mut self) -> Result<Stmt> {
-		if self.matches([TokenTy::If]) {
-			self.if_statement()
-		} else if self.matches([TokenTy::For]) {
-			self.for_statement()
-		} else if self.matches([TokenTy::While]) {
-			self.while_statement()
-		} else if self.matches([TokenTy::Print]) {
-			self.print_statement()
-		} else if self.matches([TokenTy::LeftBrace]) {
-			Ok(Stmt::Block(self.block()?))
-		} else if self.matches([TokenTy::Return]) {
-			self.return_statement()
+	fn statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		self.nested(|parser| {
+			if parser.matches([TokenTy::If]) {
+				parser.if_statement()
+			} else if parser.matches([TokenTy::For]) {
+				parser.for_statement()
+			} else if parser.matches([TokenTy::While]) {
+				parser.while_statement()
+			} else if parser.print_statement && parser.matches([TokenTy::Print]) {
+				parser.print_statement()
+			} else if parser.matches([TokenTy::LeftBrace]) {
+				Ok(Stmt::Block(parser.block()?))
+			} else if parser.matches([TokenTy::Return]) {
+				parser.return_statement()
+			} else if parser.matches([TokenTy::Yield]) {
+				parser.yield_statement()
+			} else if parser.matches([TokenTy::Break]) {
+				let keyword = parser.previous().clone();
+				parser.end_statement("Expect ';' after 'break'.")?;
+				Ok(Stmt::Break { keyword })
+			} else {
+				parser.expression_statement()
+			}
+		})
+	}
+
+	fn return_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		let keyword = self.previous().clone();
+		let value = if !self.check(TokenTy::Semicolon) {
+			let value = self.expression()?;
+			self.tuple(value)?
 		} else {
-			self.expression_statement()
-		}
+			Expr::Literal(().into())
+		};
+		self.end_statement("Expect ';' after return value.")?;
+		Ok(Stmt::Return { keyword, value })
 	}
 
-	fn return_statement(&mut self) -> Result<Stmt> {
+	fn yield_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
 		let keyword = self.previous().clone();
 		let value = if !self.check(TokenTy::Semicolon) {
 			self.expression()?
 		} else {
 			Expr::Literal(().into())
 		};
-		self.consume(TokenTy::Semicolon, "Expect ';' after return value.".into())?;
-		Ok(Stmt::Return { keyword, value })
+		self.end_statement("Expect ';' after yield value.")?;
+		Ok(Stmt::Yield { keyword, value })
 	}
 
-	fn block(&mut self) -> Result<Vec<Stmt>> {
-		let mut statements = Vec::new();
+	fn block(&mut self) -> Result<'ast, &'ast [Stmt<'ast>]> {
+		let mut statements = BumpVec::new_in(self.arena);
 
-		while !self.check(TokenTy::RightBrace) && !self.is_at_end() {
-			statements.push(self.declaration()?);
+		self.nested(|parser| {
+			parser.depth += 1;
+			while !parser.check(TokenTy::RightBrace) && !parser.is_at_end() {
+				parser.recovering_declaration(&mut statements);
+			}
+			parser.depth -= 1;
+			Ok(())
+		})?;
+
+		// Only the end of the script stops a block early, so keep what it
+		// has: that is how it looks while it is still being typed.
+		if !self.matches([TokenTy::RightBrace]) {
+			let token = self.peek().clone();
+			self.error(ParseError::Custom(token, "Expect '}' after block.".into()));
 		}
 
-		self.consume(TokenTy::RightBrace, "Expect '}' after block.".into())?;
-
-		Ok(statements)
+		Ok(statements.into_bump_slice())
 	}
 
-	fn if_statement(&mut self) -> Result<Stmt> {
+	fn if_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		let keyword = self.previous().clone();
 		self.consume(TokenTy::LeftParen, "Expect '(' after 'if'.".into())?;
 		let condition = self.expression()?;
 		self.consume(TokenTy::RightParen, "Expect ')' after if condition.".into())?;
@@ -164,21 +324,26 @@ impl Parser {
 			.transpose()?;
 
 		Ok(Stmt::If {
+			keyword,
 			condition,
-			then_branch: Box::new(then_branch),
-			else_branch: else_branch.map(Box::new),
+			then_branch: self.arena.alloc(then_branch),
+			else_branch: else_branch.map(|stmt| &*self.arena.alloc(stmt)),
 		})
 	}
 
-	fn for_statement(&mut self) -> Result<Stmt> {
+	fn for_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		let keyword = self.previous().clone();
 		self.consume(TokenTy::LeftParen, "Expect '(' after 'if'.".into())?;
-		let initializer = if self.matches([TokenTy::Semicolon]) {
-			None
-		} else if self.matches([TokenTy::Var]) {
-			Some(self.var_declaration()?)
-		} else {
-			Some(self.expression_statement()?)
-		};
+		if self.is_for_in() {
+			return self.for_in_statement();
+		}
+
+		let mut initializer = BumpVec::new_in(self.arena);
+		if self.matches([TokenTy::Var]) {
+			self.var_declaration(&mut initializer)?;
+		} else if !self.matches([TokenTy::Semicolon]) {
+			initializer.push(self.expression_statement()?);
+		}
 
 		let condition = if !self.check(TokenTy::Semicolon) {
 			self.expression()?
@@ -198,22 +363,55 @@ impl Parser {
 		let mut body = self.statement()?;
 
 		if let Some(increment) = increment {
-			body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
+			body = Stmt::Block(self.arena.alloc([body, Stmt::Expression(increment)]));
 		}
 
 		body = Stmt::While {
+			keyword,
 			condition,
-			body: Box::new(body),
+			body: self.arena.alloc(body),
 		};
 
-		if let Some(initializer) = initializer {
-			body = Stmt::Block(vec![initializer, body]);
+		if !initializer.is_empty() {
+			initializer.push(body);
+			body = Stmt::Block(initializer.into_bump_slice());
 		}
 
 		Ok(body)
 	}
 
-	fn while_statement(&mut self) -> Result<Stmt> {
+	/// Whether the clauses start with `var name in`. `in` is only a keyword
+	/// here, so it remains usable as a variable name elsewhere.
+	fn is_for_in(&self) -> bool {
+		matches!(
+			&self.tokens[self.current..],
+			[var, name, keyword, ..]
+				if var.ty == TokenTy::Var
+					&& name.ty == TokenTy::Identifier
+					&& keyword.ty == TokenTy::Identifier
+					&& keyword.lexeme == "in"
+		)
+	}
+
+	fn for_in_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		self.advance(); // var
+		let name = self.advance().clone();
+		let keyword = self.advance().clone();
+		let iterable = self.expression()?;
+		self.consume(TokenTy::RightParen, "Expect ')' after for clauses.".into())?;
+
+		let body = self.statement()?;
+
+		Ok(Stmt::ForIn {
+			name,
+			keyword,
+			iterable,
+			body: self.arena.alloc(body),
+		})
+	}
+
+	fn while_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		let keyword = self.previous().clone();
 		self.consume(TokenTy::LeftParen, "Expect '(' after 'if'.".into())?;
 		let condition = self.expression()?;
 		self.consume(TokenTy::RightParen, "Expect ')' after if condition.".into())?;
@@ -221,41 +419,57 @@ impl Parser {
 		let body = self.statement()?;
 
 		Ok(Stmt::While {
+			keyword,
 			condition,
-			body: Box::new(body),
+			body: self.arena.alloc(body),
 		})
 	}
 
-	fn print_statement(&mut self) -> Result<Stmt> {
+	fn print_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
+		let keyword = self.previous().clone();
 		let value = self.expression()?;
-		self.consume(TokenTy::Semicolon, "Expect ';' after value.".into())?;
-		Ok(Stmt::Print(value))
+		self.end_statement("Expect ';' after value.")?;
+		Ok(Stmt::Print { keyword, value })
 	}
 
-	fn expression_statement(&mut self) -> Result<Stmt> {
+	fn expression_statement(&mut self) -> Result<'ast, Stmt<'ast>> {
 		let expr = self.expression()?;
-		self.consume(TokenTy::Semicolon, "Expect ';' after expression".into())?;
+		self.end_statement("Expect ';' after expression")?;
 		Ok(Stmt::Expression(expr))
 	}
 
-	fn expression(&mut self) -> Result<Expr> {
-		self.assignment()
+	fn expression(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.nested(Self::assignment)
 	}
 
-	fn assignment(&mut self) -> Result<Expr> {
+	fn assignment(&mut self) -> Result<'ast, Expr<'ast>> {
 		let expr = self.or()?;
 
 		if self.matches([TokenTy::Equal]) {
 			let equals = self.previous().clone();
-			let value = self.assignment()?;
+			let value = self.nested(Self::assignment)?;
 
-			if let Expr::Variable(name) = expr {
+			if let Expr::Variable { name, .. } = expr {
 				return Ok(Expr::Assign {
+					id: ExprId::next(),
 					name,
-					value: Box::new(value),
+					value: self.arena.alloc(value),
 				});
 			}
 
+			if let Expr::Tuple(targets) = expr {
+				if targets
+					.iter()
+					.all(|target| matches!(target, Expr::Variable { .. }))
+				{
+					return Ok(Expr::AssignTuple {
+						targets,
+						equals,
+						value: self.arena.alloc(value),
+					});
+				}
+			}
+
 			self.errors.push(ParseError::Custom(
 				equals,
 				"Invalid assignment target.".into(),
@@ -265,136 +479,221 @@ impl Parser {
 		Ok(expr)
 	}
 
-	fn or(&mut self) -> Result<Expr> {
+	fn or(&mut self) -> Result<'ast, Expr<'ast>> {
+		let nesting = self.nesting;
 		let mut expr = self.and()?;
 
 		while self.matches([TokenTy::Or]) {
+			self.nest()?;
 			let operator = self.previous().clone();
 			let right = self.and()?;
 			expr = Expr::Logical {
-				left: Box::new(expr),
+				left: self.arena.alloc(expr),
 				operator,
-				right: Box::new(right),
+				right: self.arena.alloc(right),
 			};
 		}
 
+		self.nesting = nesting;
 		Ok(expr)
 	}
 
-	fn and(&mut self) -> Result<Expr> {
+	fn and(&mut self) -> Result<'ast, Expr<'ast>> {
+		let nesting = self.nesting;
 		let mut expr = self.equality()?;
 
 		while self.matches([TokenTy::And]) {
+			self.nest()?;
 			let operator = self.previous().clone();
 			let right = self.equality()?;
 			expr = Expr::Logical {
-				left: Box::new(expr),
+				left: self.arena.alloc(expr),
 				operator,
-				right: Box::new(right),
+				right: self.arena.alloc(right),
 			};
 		}
 
+		self.nesting = nesting;
 		Ok(expr)
 	}
 
-	fn equality(&mut self) -> Result<Expr> {
+	fn equality(&mut self) -> Result<'ast, Expr<'ast>> {
+		let nesting = self.nesting;
 		let mut expr = self.comparison()?;
 
 		while self.matches([TokenTy::BangEqual, TokenTy::EqualEqual]) {
+			self.nest()?;
 			let operator = self.previous().clone();
 			let right = self.comparison()?;
 			expr = Expr::Binary {
-				left: Box::new(expr),
+				left: self.arena.alloc(expr),
 				operator,
-				right: Box::new(right),
+				right: self.arena.alloc(right),
 			};
 		}
 
+		self.nesting = nesting;
 		Ok(expr)
 	}
 
-	fn comparison(&mut self) -> Result<Expr> {
-		let mut expr = self.term()?;
-
-		while self.matches([
+	fn comparison(&mut self) -> Result<'ast, Expr<'ast>> {
+		const ORDERING: [TokenTy; 4] = [
 			TokenTy::Greater,
 			TokenTy::GreaterEqual,
 			TokenTy::Less,
 			TokenTy::LessEqual,
-		]) {
-			let operator = self.previous().clone();
-			let right = self.term()?;
-			expr = Expr::Binary {
-				left: Box::new(expr),
-				operator,
-				right: Box::new(right),
-			};
+		];
+		let nesting = self.nesting;
+		let mut expr = self.bit_or()?;
+
+		loop {
+			if self.matches([TokenTy::Is]) {
+				self.nest()?;
+				let operator = self.previous().clone();
+				let right = self.bit_or()?;
+				expr = Expr::Binary {
+					left: self.arena.alloc(expr),
+					operator,
+					right: self.arena.alloc(right),
+				};
+			} else if self.matches(ORDERING) {
+				self.nest()?;
+				let mut operators = BumpVec::new_in(self.arena);
+				let mut operands = BumpVec::new_in(self.arena);
+				operands.push(expr);
+				loop {
+					operators.push(self.previous().clone());
+					operands.push(self.bit_or()?);
+					if !self.matches(ORDERING) {
+						break;
+					}
+				}
+				expr = if operators.len() == 1 {
+					let right = operands.pop().unwrap();
+					Expr::Binary {
+						left: self.arena.alloc(operands.pop().unwrap()),
+						operator: operators.pop().unwrap(),
+						right: self.arena.alloc(right),
+					}
+				} else {
+					Expr::Comparison {
+						operands: operands.into_bump_slice(),
+						operators: operators.into_bump_slice(),
+					}
+				};
+			} else {
+				self.nesting = nesting;
+				return Ok(expr);
+			}
 		}
+	}
 
-		Ok(expr)
+	// The bitwise operators bind tighter than comparisons, unlike in C,
+	// so that `a & mask == 0` means what it looks like.
+	fn bit_or(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.binary([TokenTy::Pipe], Self::bit_xor)
 	}
 
-	fn term(&mut self) -> Result<Expr> {
-		let mut expr = self.factor()?;
+	fn bit_xor(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.binary([TokenTy::Caret], Self::bit_and)
+	}
 
-		while self.matches([TokenTy::Minus, TokenTy::Plus]) {
-			let operator = self.previous().clone();
-			let right = self.factor()?;
-			expr = Expr::Binary {
-				left: Box::new(expr),
-				operator,
-				right: Box::new(right),
-			};
-		}
+	fn bit_and(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.binary([TokenTy::Ampersand], Self::shift)
+	}
 
-		Ok(expr)
+	fn shift(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.binary([TokenTy::LessLess, TokenTy::GreaterGreater], Self::term)
 	}
 
-	fn factor(&mut self) -> Result<Expr> {
-		let mut expr = self.unary()?;
+	fn term(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.binary([TokenTy::Minus, TokenTy::Plus], Self::factor)
+	}
 
-		while self.matches([TokenTy::Slash, TokenTy::Star]) {
+	fn factor(&mut self) -> Result<'ast, Expr<'ast>> {
+		self.binary(
+			[TokenTy::Slash, TokenTy::Star, TokenTy::Percent],
+			Self::unary,
+		)
+	}
+
+	/// Parses `operand`s joined by any of `operators`, which associate to
+	/// the left.
+	fn binary<const N: usize>(
+		&mut self,
+		operators: [TokenTy; N],
+		operand: fn(&mut Self) -> Result<'ast, Expr<'ast>>,
+	) -> Result<'ast, Expr<'ast>> {
+		let nesting = self.nesting;
+		let mut expr = operand(self)?;
+
+		while self.matches(operators) {
+			self.nest()?;
 			let operator = self.previous().clone();
-			let right = self.unary()?;
+			let right = operand(self)?;
 			expr = Expr::Binary {
-				left: Box::new(expr),
+				left: self.arena.alloc(expr),
 				operator,
-				right: Box::new(right),
+				right: self.arena.alloc(right),
 			};
 		}
 
+		self.nesting = nesting;
 		Ok(expr)
 	}
 
-	fn unary(&mut self) -> Result<Expr> {
-		if self.matches([TokenTy::Bang, TokenTy::Minus]) {
+	fn unary(&mut self) -> Result<'ast, Expr<'ast>> {
+		if self.matches([TokenTy::Bang, TokenTy::Minus, TokenTy::Tilde]) {
 			let operator = self.previous().clone();
-			let right = self.unary()?;
+			let right = self.nested(Self::unary)?;
 			Ok(Expr::Unary {
 				operator,
-				right: Box::new(right),
+				right: self.arena.alloc(right),
 			})
 		} else {
 			self.call()
 		}
 	}
 
-	fn call(&mut self) -> Result<Expr> {
+	fn call(&mut self) -> Result<'ast, Expr<'ast>> {
+		let nesting = self.nesting;
 		let mut expr = self.primary()?;
 
 		loop {
 			if self.matches([TokenTy::LeftParen]) {
+				self.nest()?;
 				expr = self.finish_call(expr)?;
+			} else if self.matches([TokenTy::Dot]) {
+				self.nest()?;
+				let name = self
+					.consume(TokenTy::Identifier, "Expect method name after '.'.".into())?
+					.clone();
+				expr = Expr::Get {
+					object: self.arena.alloc(expr),
+					name,
+				};
+			} else if self.matches([TokenTy::LeftBracket]) {
+				self.nest()?;
+				let index = self.expression()?;
+				let bracket = self
+					.consume(TokenTy::RightBracket, "Expect ']' after index.".into())?
+					.clone();
+				expr = Expr::Index {
+					object: self.arena.alloc(expr),
+					bracket,
+					index: self.arena.alloc(index),
+				};
 			} else {
 				break;
 			}
 		}
 
+		self.nesting = nesting;
 		Ok(expr)
 	}
 
-	fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
-		let mut arguments = Vec::new();
+	fn finish_call(&mut self, callee: Expr<'ast>) -> Result<'ast, Expr<'ast>> {
+		let mut arguments = BumpVec::new_in(self.arena);
 
 		if !self.check(TokenTy::RightParen) {
 			loop {
@@ -404,7 +703,15 @@ impl Parser {
 						"Can't have more than 255 arguments".into(),
 					));
 				}
-				arguments.push(self.expression()?);
+				let argument = if self.matches([TokenTy::DotDotDot]) {
+					Expr::Spread {
+						ellipsis: self.previous().clone(),
+						value: self.arena.alloc(self.expression()?),
+					}
+				} else {
+					self.expression()?
+				};
+				arguments.push(argument);
 				if !self.matches([TokenTy::Comma]) {
 					break;
 				}
@@ -416,36 +723,111 @@ impl Parser {
 			.clone();
 
 		Ok(Expr::Call {
-			callee: Box::new(callee),
+			callee: self.arena.alloc(callee),
 			paren,
-			arguments,
+			arguments: arguments.into_bump_slice(),
 		})
 	}
 
-	fn primary(&mut self) -> Result<Expr> {
+	fn primary(&mut self) -> Result<'ast, Expr<'ast>> {
 		if self.matches([TokenTy::False]) {
 			Ok(Expr::Literal(Literal::Boolean(false)))
 		} else if self.matches([TokenTy::True]) {
 			Ok(Expr::Literal(Literal::Boolean(true)))
 		} else if self.matches([TokenTy::Nil]) {
 			Ok(Expr::Literal(Literal::Nil))
-		} else if self.matches([TokenTy::Number, TokenTy::String]) {
-			Ok(Expr::Literal(self.previous().clone().literal.unwrap()))
-		} else if self.matches([TokenTy::Identifier]) {
-			Ok(Expr::Variable(self.previous().clone()))
+		} else if self.matches([TokenTy::Number]) {
+			let token = self.previous().clone();
+			match integer_literal(token.lexeme) {
+				Some(value) => Ok(Expr::Literal(Literal::Integer(value))),
+				None => match token.lexeme.parse() {
+					Ok(value) => Ok(Expr::Literal(Literal::Number(value))),
+					Err(_) => {
+						let error = format!("Expect a value for '{}'.", token.lexeme);
+						self.error(ParseError::Custom(token.clone(), error.into()));
+						Ok(Expr::Error(token))
+					}
+				},
+			}
+		} else if self.matches([TokenTy::String]) {
+			// The scanner has checked the escapes of every string token.
+			let token = self.previous().clone();
+			match unescape(string_contents(token.lexeme)) {
+				Ok(Cow::Borrowed(value)) => Ok(Expr::String(value)),
+				Ok(Cow::Owned(value)) => Ok(Expr::String(self.arena.alloc_str(&value))),
+				Err(message) => {
+					self.error(ParseError::Custom(token.clone(), message.into()));
+					Ok(Expr::Error(token))
+				}
+			}
+		} else if self.matches([TokenTy::Identifier])
+			|| (!self.print_statement && self.matches([TokenTy::Print]))
+		{
+			Ok(Expr::Variable {
+				id: ExprId::next(),
+				name: self.previous().clone(),
+			})
 		} else if self.matches([TokenTy::LeftParen]) {
 			let expr = self.expression()?;
+			let expr = self.tuple(expr)?;
 			self.consume(TokenTy::RightParen, "Expect ')' after expression.".into())?;
-			Ok(Expr::Grouping(Box::new(expr)))
+			Ok(match expr {
+				Expr::Tuple(_) => expr,
+				_ => Expr::Grouping(self.arena.alloc(expr)),
+			})
 		} else {
-			Err(ParseError::Custom(
-				self.peek().clone(),
+			// Go on as if there were one, for the rest of the statement.
+			let token = self.peek().clone();
+			self.error(ParseError::Custom(
+				token.clone(),
 				"Expect expression.".into(),
-			))
+			));
+			Ok(Expr::Error(token))
+		}
+	}
+
+	/// Continues a comma-separated list that started with `first`. Returns
+	/// `first` unchanged if no comma follows it.
+	fn tuple(&mut self, first: Expr<'ast>) -> Result<'ast, Expr<'ast>> {
+		if !self.check(TokenTy::Comma) {
+			return Ok(first);
+		}
+
+		let mut elements = BumpVec::new_in(self.arena);
+		elements.push(first);
+		while self.matches([TokenTy::Comma]) {
+			elements.push(self.expression()?);
 		}
+		Ok(Expr::Tuple(elements.into_bump_slice()))
+	}
+
+	/// Parses with `parse` one level deeper, unless that is too deep.
+	fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<'ast, T>) -> Result<'ast, T> {
+		self.nest()?;
+		let result = parse(self);
+		self.nesting -= 1;
+		result
+	}
+
+	/// Goes one level deeper, unless that is too deep. Whoever calls this
+	/// restores `nesting` when done; after an error, the declaration that
+	/// recovers from it does.
+	fn nest(&mut self) -> Result<'ast, ()> {
+		if self.nesting == MAX_NESTING {
+			return Err(ParseError::Custom(
+				self.peek().clone(),
+				"Too much nesting.".into(),
+			));
+		}
+		self.nesting += 1;
+		Ok(())
 	}
 
 	fn synchonize(&mut self) {
+		let in_block = self.depth > 0;
+		if in_block && self.check(TokenTy::RightBrace) {
+			return;
+		}
 		self.advance();
 
 		while !self.is_at_end() {
@@ -454,14 +836,19 @@ impl Parser {
 			}
 
 			match self.peek().ty {
+				TokenTy::RightBrace if in_block => {
+					return;
+				}
 				TokenTy::Class
+				| TokenTy::Const
 				| TokenTy::Fun
 				| TokenTy::Var
 				| TokenTy::For
 				| TokenTy::If
 				| TokenTy::While
 				| TokenTy::Print
-				| TokenTy::Return => {
+				| TokenTy::Return
+				| TokenTy::Break => {
 					return;
 				}
 				_ => {
@@ -471,7 +858,27 @@ impl Parser {
 		}
 	}
 
-	fn consume(&mut self, ty: TokenTy, message: std::borrow::Cow<'static, str>) -> Result<&Token> {
+	/// Consumes the `;` that ends a statement. If the statement is cut
+	/// short by the end of its block or of the script instead, as while it
+	/// is being typed, the error is reported but the statement kept.
+	fn end_statement(&mut self, message: &'static str) -> Result<'ast, ()> {
+		if self.matches([TokenTy::Semicolon]) {
+			return Ok(());
+		}
+		let err = ParseError::Custom(self.peek().clone(), message.into());
+		if self.is_at_end() || (self.depth > 0 && self.check(TokenTy::RightBrace)) {
+			self.error(err);
+			Ok(())
+		} else {
+			Err(err)
+		}
+	}
+
+	fn consume(
+		&mut self,
+		ty: TokenTy,
+		message: std::borrow::Cow<'static, str>,
+	) -> Result<'ast, &Token<'ast>> {
 		if self.check(ty) {
 			Ok(self.advance())
 		} else {
@@ -492,7 +899,7 @@ impl Parser {
 		!self.is_at_end() && self.peek().ty == ty
 	}
 
-	fn advance(&mut self) -> &Token {
+	fn advance(&mut self) -> &Token<'ast> {
 		if !self.is_at_end() {
 			self.current += 1;
 		}
@@ -503,19 +910,19 @@ impl Parser {
 		self.peek().ty == TokenTy::Eof
 	}
 
-	fn peek(&self) -> &Token {
+	fn peek(&self) -> &Token<'ast> {
 		&self.tokens[self.current]
 	}
 
-	fn previous(&self) -> &Token {
+	fn previous(&self) -> &Token<'ast> {
 		&self.tokens[self.current - 1]
 	}
 }
 
 #[derive(Debug)]
-pub enum ParseError {
-	Custom(Token, std::borrow::Cow<'static, str>),
-	Multiple(Vec<ParseError>),
+pub enum ParseError<'a> {
+	Custom(Token<'a>, std::borrow::Cow<'static, str>),
+	Multiple(Vec<ParseError<'a>>),
 }
 
-type Result<T> = std::result::Result<T, ParseError>;
+type Result<'ast, T> = std::result::Result<T, ParseError<'ast>>;
@@ -0,0 +1,118 @@
+//! Renaming a variable or function everywhere it is referred to, for
+//! `rslox rename`.
+
+use std::{collections::HashSet, ops::Range, rc::Rc};
+
+use rlox_common::keyword::Keyword;
+
+use crate::{resolver::Reference, token::Token, token_type::TokenTy};
+
+/// Renames `old`, the name at byte `offset` of `source`, to `new`: its
+/// declaration and every name in `references` that refers to it. `tokens`
+/// are the tokens of `source` and `globals` the names the script can use
+/// without declaring them. Returns the renamed source.
+///
+/// To be sure nothing else comes to refer to the renamed declaration,
+/// or it to something else, `new` must not already be used for anything
+/// in the script, nor be a global.
+pub fn rename(
+	source: &str,
+	tokens: &[Token],
+	references: &[Reference],
+	globals: &HashSet<Rc<str>>,
+	offset: usize,
+	old: &str,
+	new: &str,
+) -> Result<String, String> {
+	let mut chars = new.chars();
+	let valid = chars
+		.next()
+		.is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+		&& chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+	if !valid || Keyword::from_word(new).is_some() {
+		return Err(format!("'{new}' is not a valid name."));
+	}
+	let taken = tokens
+		.iter()
+		.any(|token| token.ty == TokenTy::Identifier && token.lexeme == new);
+	if taken || globals.contains(new) {
+		return Err(format!("'{new}' is already a name in the script."));
+	}
+
+	let Some(target) = references
+		.iter()
+		.find(|reference| reference.name.touches(offset) && &source[reference.name.range()] == old)
+	else {
+		return Err(format!("No declared name '{old}' there."));
+	};
+	let mut renamed = references
+		.iter()
+		.filter(|reference| reference.declaration == target.declaration)
+		.map(|reference| reference.name.range())
+		.collect::<Vec<_>>();
+	renamed.sort_by_key(|range| range.start);
+	renamed.dedup();
+
+	let mut result = String::with_capacity(source.len());
+	let mut copied = 0;
+	for range in renamed {
+		result.push_str(&source[copied..range.start]);
+		result.push_str(new);
+		copied = range.end;
+	}
+	result.push_str(&source[copied..]);
+	Ok(result)
+}
+
+/// The unified diff from `before` to `after`, which have the same number
+/// of lines, as they do after a rename, under the name `path`.
+pub fn unified_diff(path: &str, before: &str, after: &str) -> String {
+	const CONTEXT: usize = 3;
+
+	let before = before.lines().collect::<Vec<_>>();
+	let after = after.lines().collect::<Vec<_>>();
+	let changed = (0..before.len())
+		.filter(|&line| before[line] != after[line])
+		.collect::<Vec<_>>();
+
+	let mut diff = String::new();
+	if changed.is_empty() {
+		return diff;
+	}
+	diff.push_str(&format!("--- {path}\n+++ {path}\n"));
+
+	// Hunks of changed lines close enough for their context to touch.
+	let mut hunks: Vec<Range<usize>> = Vec::new();
+	for &line in &changed {
+		let start = line.saturating_sub(CONTEXT);
+		let end = (line + CONTEXT + 1).min(before.len());
+		match hunks.last_mut() {
+			Some(hunk) if start <= hunk.end => hunk.end = end,
+			_ => hunks.push(start..end),
+		}
+	}
+
+	for hunk in hunks {
+		let header = format!("{},{}", hunk.start + 1, hunk.len());
+		diff.push_str(&format!("@@ -{header} +{header} @@\n"));
+		let mut line = hunk.start;
+		while line < hunk.end {
+			if before[line] == after[line] {
+				diff.push_str(&format!(" {}\n", before[line]));
+				line += 1;
+				continue;
+			}
+			let run = line..(line..hunk.end)
+				.find(|&line| before[line] == after[line])
+				.unwrap_or(hunk.end);
+			for line in run.clone() {
+				diff.push_str(&format!("-{}\n", before[line]));
+			}
+			for line in run.clone() {
+				diff.push_str(&format!("+{}\n", after[line]));
+			}
+			line = run.end;
+		}
+	}
+	diff
+}
@@ -1,53 +1,227 @@
-use std::collections::HashMap;
+use std::{
+	collections::{HashMap, HashSet},
+	rc::Rc,
+};
 
 use crate::{
-	expr::Expr,
-	interpreter::Interpreter,
+	call_graph::CallGraph,
+	completion::{Completion, CompletionKind},
+	environment::Slot,
+	expr::{Expr, ExprId},
+	interpreter::{Interpreter, VisibleLocals},
+	literal::Literal,
 	stmt::{Stmt, StmtFunction},
-	token::Token,
+	token::{Span, Token},
 };
 
-pub struct Resolver<'intpt> {
-	interpreter: &'intpt mut Interpreter,
-	scopes: Vec<HashMap<String, bool>>,
-	errors: Vec<ResolveError>,
+pub struct Resolver<'intpt, 'ast> {
+	interpreter: &'intpt mut Interpreter<'ast>,
+	scopes: Vec<HashMap<&'ast str, Variable>>,
+	/// How many slots each of `scopes` has, which is more than the
+	/// variables it has if `_` was declared in it more than once.
+	slots: Vec<usize>,
+	errors: Vec<ResolveError<'ast>>,
+	warnings: Vec<ResolveWarning<'ast>>,
 	function_ty: FunctionType,
+	/// How many loops the statement being resolved is in, within the
+	/// innermost function.
+	loop_depth: usize,
+	/// The offset `completions` looks for the names visible at.
+	cursor: Option<usize>,
+	/// The locals visible at the variable or missing expression nearest to
+	/// the cursor so far, and where that is.
+	visible_at_cursor: Option<(usize, Vec<Completion>)>,
+	/// Every declaration and every use of a local resolved so far.
+	references: Vec<Reference>,
+	/// The uses of names that are not locals, to be matched with
+	/// `global_declarations` once the whole script has been seen.
+	global_uses: Vec<(&'ast str, Span)>,
+	/// The first top-level declaration of each name.
+	global_declarations: HashMap<&'ast str, Span>,
+	/// Where the name of the function being resolved was declared, or
+	/// `None` at the top level.
+	current_function: Option<Span>,
+	/// Every function declared, by name and where.
+	functions: Vec<(Rc<str>, Span)>,
+	/// Every call of a name, from the function declared at the first span
+	/// or from the top level, to the name at the second.
+	calls: Vec<(Option<Span>, Span)>,
+}
+
+/// A name in the source, and the declaration it refers to. A declaration
+/// refers to itself.
+#[derive(Debug, Clone, Copy)]
+pub struct Reference {
+	pub name: Span,
+	pub declaration: Span,
 }
 
-pub enum ResolveError {
-	Custom(Token, std::borrow::Cow<'static, str>),
-	Multiple(Vec<ResolveError>),
+pub enum ResolveError<'a> {
+	Custom(Token<'a>, std::borrow::Cow<'static, str>),
+	Multiple(Vec<ResolveError<'a>>),
+}
+
+/// Something suspicious that does not stop the program from running.
+pub struct ResolveWarning<'a>(pub Token<'a>, pub std::borrow::Cow<'static, str>);
+
+struct Variable {
+	/// Where the name was declared.
+	declaration: Span,
+	defined: bool,
+	index: usize,
+	constant: bool,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum FunctionType {
 	None,
 	Function,
+	Generator,
 }
 
-pub type Result<T> = std::result::Result<T, ResolveError>;
+pub type Result<'ast, T> = std::result::Result<T, ResolveError<'ast>>;
 
-impl<'intpt> Resolver<'intpt> {
-	pub fn new(interpreter: &'intpt mut Interpreter) -> Self {
+impl<'intpt, 'ast> Resolver<'intpt, 'ast> {
+	pub fn new(interpreter: &'intpt mut Interpreter<'ast>) -> Self {
 		Self {
 			interpreter,
 			scopes: Default::default(),
+			slots: Vec::new(),
 			errors: Default::default(),
+			warnings: Default::default(),
 			function_ty: FunctionType::None,
+			loop_depth: 0,
+			cursor: None,
+			visible_at_cursor: None,
+			references: Vec::new(),
+			global_uses: Vec::new(),
+			global_declarations: HashMap::new(),
+			current_function: None,
+			functions: Vec::new(),
+			calls: Vec::new(),
+		}
+	}
+
+	/// Resolves `statements` only to find the local variables visible at
+	/// `cursor`, a byte offset into their source: at the name being typed
+	/// there, or at the missing expression after it. Errors are ignored.
+	pub fn completions(mut self, statements: &[Stmt<'ast>], cursor: usize) -> Vec<Completion> {
+		self.cursor = Some(cursor);
+		self.analyze(statements);
+		self.visible_at_cursor
+			.map(|(_, visible)| visible)
+			.unwrap_or_default()
+	}
+
+	/// Resolves `statements` only to find what each name in them refers
+	/// to, in source order. Uses of globals the script does not declare,
+	/// like natives, have no declaration to refer to and are left out.
+	/// Errors are ignored.
+	pub fn references(mut self, statements: &[Stmt<'ast>]) -> Vec<Reference> {
+		self.analyze(statements);
+		self.finish_references()
+	}
+
+	/// Resolves `statements` only to find which functions call which. A
+	/// call counts when the callee is a name that refers to a function
+	/// declaration. Errors are ignored.
+	pub fn call_graph(mut self, statements: &[Stmt<'ast>]) -> CallGraph {
+		self.analyze(statements);
+		let functions = std::mem::take(&mut self.functions);
+		let calls = std::mem::take(&mut self.calls);
+		let declarations = self
+			.finish_references()
+			.into_iter()
+			.map(|reference| (reference.name, reference.declaration))
+			.collect::<HashMap<_, _>>();
+		let calls = calls
+			.into_iter()
+			.filter_map(|(caller, callee)| {
+				let declaration = *declarations.get(&callee)?;
+				let function = functions.iter().any(|&(_, span)| span == declaration);
+				function.then_some((caller, declaration))
+			})
+			.collect();
+		CallGraph { functions, calls }
+	}
+
+	/// Matches the uses of globals with their declarations, now that all
+	/// of them have been seen, and takes every reference, in source order.
+	fn finish_references(&mut self) -> Vec<Reference> {
+		let mut references = std::mem::take(&mut self.references);
+		for (name, span) in std::mem::take(&mut self.global_uses) {
+			if let Some(&declaration) = self.global_declarations.get(&name) {
+				references.push(Reference {
+					name: span,
+					declaration,
+				});
+			}
 		}
+		references.sort_by_key(|reference| reference.name.offset);
+		references
 	}
 
-	pub fn resolve(mut self, statements: &[Stmt]) -> Result<()> {
+	/// Resolves `statements` for what that finds out about them alone. As
+	/// they are not run, the interpreter is left with the constants it had.
+	fn analyze(&mut self, statements: &[Stmt<'ast>]) {
+		let constants = self.interpreter.global_constants.clone();
+		self.resolve_block(statements);
+		self.interpreter.global_constants = constants;
+	}
+
+	/// Resolves `statements`, returning any warnings if there were no errors.
+	pub fn resolve(mut self, statements: &[Stmt<'ast>]) -> Result<'ast, Vec<ResolveWarning<'ast>>> {
+		// Hoisted functions replace any constants of the same name before
+		// the code above their declarations runs, so it may assign them.
+		if self.interpreter.hoist_functions {
+			for statement in statements {
+				if let Stmt::Function(statement) = statement {
+					self.interpreter
+						.global_constants
+						.remove(statement.name.lexeme);
+				}
+			}
+			self.check_hoisted_names(statements);
+		}
 		self.resolve_block(statements);
 
 		if self.errors.is_empty() {
-			Ok(())
+			Ok(self.warnings)
 		} else {
 			Err(ResolveError::Multiple(self.errors))
 		}
 	}
 
-	fn resolve_block(&mut self, statements: &[Stmt]) {
+	/// Reports every top-level function that shares its name with another
+	/// top-level declaration. Hoisting would define the function before
+	/// that declaration runs instead of where it is, changing which of the
+	/// two the name ends up with.
+	fn check_hoisted_names(&mut self, statements: &[Stmt<'ast>]) {
+		let mut declarations = HashMap::new();
+		for statement in statements {
+			let names = match statement {
+				Stmt::Var { name, .. } => std::slice::from_ref(name),
+				Stmt::VarTuple { names, .. } => names,
+				Stmt::Function(function) => std::slice::from_ref(&function.name),
+				_ => continue,
+			};
+			for name in names {
+				*declarations.entry(name.lexeme).or_insert(0) += 1;
+			}
+		}
+		for statement in statements {
+			if let Stmt::Function(function) = statement {
+				if declarations[function.name.lexeme] > 1 {
+					self.errors.push(ResolveError::Custom(
+						function.name.clone(),
+						"A hoisted function can't share its name with another declaration.".into(),
+					));
+				}
+			}
+		}
+	}
+
+	fn resolve_block(&mut self, statements: &[Stmt<'ast>]) {
 		for statement in statements {
 			self.resolve_statement(statement);
 		}
@@ -55,47 +229,113 @@ impl<'intpt> Resolver<'intpt> {
 
 	fn begin_scope(&mut self) {
 		self.scopes.push(Default::default());
+		self.slots.push(0);
 	}
 
 	fn end_scope(&mut self) {
 		self.scopes.pop();
+		self.slots.pop();
 	}
 
-	fn resolve_statement(&mut self, statement: &Stmt) {
+	fn resolve_statement(&mut self, statement: &Stmt<'ast>) {
 		match statement {
 			Stmt::Block(statements) => {
 				self.begin_scope();
 				self.resolve_block(statements);
 				self.end_scope();
 			}
-			Stmt::Var { name, initializer } => {
+			Stmt::Var {
+				name,
+				initializer,
+				constant,
+			} => {
 				self.declare(name);
 				if let Some(initializer) = initializer {
 					self.resolve_expression(initializer);
 				}
 				self.define(name);
+				match self.scopes.last_mut() {
+					Some(scope) => {
+						let variable = scope.get_mut(name.lexeme).expect("just declared");
+						variable.constant = *constant;
+					}
+					None if *constant => {
+						self.interpreter.global_constants.insert(name.lexeme.into());
+					}
+					None => {
+						self.interpreter.global_constants.remove(name.lexeme);
+					}
+				}
+			}
+			Stmt::VarTuple {
+				names, initializer, ..
+			} => {
+				for name in *names {
+					self.declare(name);
+				}
+				self.resolve_expression(initializer);
+				for name in *names {
+					self.define(name);
+				}
+				if self.scopes.is_empty() {
+					for name in *names {
+						self.interpreter.global_constants.remove(name.lexeme);
+					}
+				}
+			}
+			Stmt::ForIn {
+				name,
+				iterable,
+				body,
+				..
+			} => {
+				self.resolve_expression(iterable);
+				self.begin_scope();
+				self.declare(name);
+				self.define(name);
+				self.resolve_loop_body(body);
+				self.end_scope();
 			}
 			Stmt::Function(statement) => {
+				self.functions
+					.push((statement.name.lexeme.into(), statement.name.span()));
 				self.declare(&statement.name);
 				self.define(&statement.name);
-				self.resolve_function(statement, FunctionType::Function);
+				if self.scopes.is_empty() {
+					self.interpreter
+						.global_constants
+						.remove(statement.name.lexeme);
+				}
+				let function_ty = if statement.generator {
+					FunctionType::Generator
+				} else {
+					FunctionType::Function
+				};
+				self.resolve_function(statement, function_ty);
 			}
 			Stmt::Expression(expression) => {
 				self.resolve_expression(expression);
 			}
 			Stmt::If {
+				keyword,
 				condition,
 				then_branch,
 				else_branch,
 			} => {
+				if let Some(value) = constant_condition(condition) {
+					self.warnings.push(ResolveWarning(
+						keyword.clone(),
+						format!("Condition is always {value}.").into(),
+					));
+				}
 				self.resolve_expression(condition);
 				self.resolve_statement(then_branch);
 				if let Some(else_branch) = else_branch {
 					self.resolve_statement(else_branch);
 				}
 			}
-			Stmt::Print(expression) => {
-				self.resolve_expression(expression);
+			Stmt::Print { value, .. } => {
+				self.resolve_expression(value);
 			}
 			Stmt::Return { value, keyword } => {
 				if self.function_ty == FunctionType::None {
@@ -106,28 +346,87 @@ impl<'intpt> Resolver<'intpt> {
 				}
 				self.resolve_expression(value);
 			}
-			Stmt::While { condition, body } => {
+			Stmt::Yield { keyword, value } => {
+				if self.function_ty != FunctionType::Generator {
+					self.errors.push(ResolveError::Custom(
+						keyword.clone(),
+						"Can't yield outside of a generator.".into(),
+					))
+				}
+				self.resolve_expression(value);
+			}
+			Stmt::Break { keyword } => {
+				if self.loop_depth == 0 {
+					self.errors.push(ResolveError::Custom(
+						keyword.clone(),
+						"Can't break outside of a loop.".into(),
+					))
+				}
+			}
+			Stmt::Error(_) => {}
+			Stmt::While {
+				keyword,
+				condition,
+				body,
+			} => {
+				// `while (true)` is how an endless loop is spelled, and what
+				// `for (;;)` becomes, so only the loop that never runs is
+				// worth a warning.
+				if constant_condition(condition) == Some(false) {
+					self.warnings.push(ResolveWarning(
+						keyword.clone(),
+						"Condition is always false.".into(),
+					));
+				}
 				self.resolve_expression(condition);
-				self.resolve_statement(body);
+				self.resolve_loop_body(body);
 			}
 		}
 	}
 
-	fn resolve_function(&mut self, function: &StmtFunction, function_ty: FunctionType) {
+	fn resolve_loop_body(&mut self, body: &Stmt<'ast>) {
+		self.loop_depth += 1;
+		self.resolve_statement(body);
+		self.loop_depth -= 1;
+	}
+
+	fn resolve_function(&mut self, function: &StmtFunction<'ast>, function_ty: FunctionType) {
 		let enclosing_function = self.function_ty;
 		self.function_ty = function_ty;
+		let enclosing_loops = std::mem::take(&mut self.loop_depth);
+		let enclosing_declaration = self.current_function.replace(function.name.span());
 		self.begin_scope();
-		for param in &function.params {
+		for param in function.params {
 			self.declare(param);
 			self.define(param);
 		}
+		self.resolve_block(function.body);
 		self.end_scope();
+		self.current_function = enclosing_declaration;
 		self.function_ty = enclosing_function;
+		self.loop_depth = enclosing_loops;
 	}
 
-	fn declare(&mut self, name: &Token) -> Option<()> {
-		let scope = self.scopes.last_mut()?;
-		if scope.insert(name.lexeme.to_owned(), false).is_some() {
+	fn declare(&mut self, name: &Token<'ast>) -> Option<()> {
+		self.references.push(Reference {
+			name: name.span(),
+			declaration: name.span(),
+		});
+		let (Some(scope), Some(slots)) = (self.scopes.last_mut(), self.slots.last_mut()) else {
+			self.global_declarations
+				.entry(name.lexeme)
+				.or_insert(name.span());
+			return None;
+		};
+		let variable = Variable {
+			declaration: name.span(),
+			defined: false,
+			index: *slots,
+			constant: false,
+		};
+		*slots += 1;
+		// Each `_` discards a value of its own, so there can be several.
+		if scope.insert(name.lexeme, variable).is_some() && !is_discard(name) {
 			self.errors.push(ResolveError::Custom(
 				name.clone(),
 				"Already a variable with this name in this scope.".into(),
@@ -136,30 +435,52 @@ impl<'intpt> Resolver<'intpt> {
 		Some(())
 	}
 
-	fn define(&mut self, name: &Token) -> Option<()> {
+	fn define(&mut self, name: &Token<'ast>) -> Option<()> {
 		let scope = self.scopes.last_mut()?;
-		let variable = scope.get_mut(&name.lexeme).expect("undeclared variable");
-		*variable = true;
+		let variable = scope.get_mut(name.lexeme).expect("undeclared variable");
+		variable.defined = true;
 		Some(())
 	}
 
-	fn resolve_expression(&mut self, expression: &Expr) {
+	fn resolve_expression(&mut self, expression: &Expr<'ast>) {
 		match expression {
-			Expr::Variable(name) => {
+			Expr::Variable { id, name } => {
+				self.visit_for_completion(name);
+				if is_discard(name) {
+					self.errors.push(ResolveError::Custom(
+						name.clone(),
+						"Can't read '_', which only discards values.".into(),
+					));
+				}
 				let scope = self.scopes.last();
 				if let Some(scope) = scope {
-					if let Some(false) = scope.get(&name.lexeme) {
+					if let Some(Variable { defined: false, .. }) = scope.get(name.lexeme) {
 						self.errors.push(ResolveError::Custom(
 							name.clone(),
 							"Can't read local variable in its own initializer.".into(),
 						));
 					}
 				}
-				self.resolve_local(expression, name);
+				self.resolve_local(*id, name);
+			}
+			Expr::Assign { id, name, value } => {
+				self.resolve_expression(value);
+				self.check_assignable(name);
+				self.resolve_local(*id, name);
+			}
+			Expr::Tuple(elements) => {
+				for element in *elements {
+					self.resolve_expression(element);
+				}
 			}
-			Expr::Assign { name, value } => {
+			Expr::AssignTuple { targets, value, .. } => {
 				self.resolve_expression(value);
-				self.resolve_local(expression, name);
+				for target in *targets {
+					if let Expr::Variable { id, name } = target {
+						self.check_assignable(name);
+						self.resolve_local(*id, name);
+					}
+				}
 			}
 			Expr::Binary { left, right, .. } => {
 				self.resolve_expression(left);
@@ -168,29 +489,154 @@ impl<'intpt> Resolver<'intpt> {
 			Expr::Call {
 				callee, arguments, ..
 			} => {
+				if let Expr::Variable { id, name } = callee {
+					self.calls.push((self.current_function, name.span()));
+					if name.lexeme == "locals" && !self.is_local(name) {
+						let visible = self.visible_locals();
+						self.interpreter.resolve_visible_locals(*id, visible);
+					}
+				}
 				self.resolve_expression(callee);
-				for argument in arguments {
+				for argument in *arguments {
 					self.resolve_expression(argument);
 				}
 			}
+			Expr::Get { object, .. } => self.resolve_expression(object),
 			Expr::Grouping(expression) => self.resolve_expression(expression),
-			Expr::Literal(_) => {}
+			Expr::Index { object, index, .. } => {
+				self.resolve_expression(object);
+				self.resolve_expression(index);
+			}
+			Expr::Spread { value, .. } => self.resolve_expression(value),
+			Expr::Literal(_) | Expr::String(_) => {}
+			Expr::Error(token) => self.visit_for_completion(token),
 			Expr::Logical { left, right, .. } => {
 				self.resolve_expression(left);
 				self.resolve_expression(right);
 			}
+			Expr::Comparison { operands, .. } => {
+				for operand in *operands {
+					self.resolve_expression(operand);
+				}
+			}
 			Expr::Unary { right, .. } => {
 				self.resolve_expression(right);
 			}
 		}
 	}
 
-	fn resolve_local(&mut self, expression: &Expr, name: &Token) {
-		for (i, scope) in self.scopes.iter().rev().enumerate() {
-			if scope.contains_key(&name.lexeme) {
-				self.interpreter.resolve(expression, i);
+	/// Reports an assignment to `name` if it refers to a `const`.
+	fn check_assignable(&mut self, name: &Token<'ast>) {
+		let constant = match self
+			.scopes
+			.iter()
+			.rev()
+			.find_map(|scope| scope.get(&*name.lexeme))
+		{
+			// The variable has no slot to assign to until it is defined.
+			Some(Variable { defined: false, .. }) => {
+				self.errors.push(ResolveError::Custom(
+					name.clone(),
+					"Can't assign local variable in its own initializer.".into(),
+				));
+				return;
+			}
+			Some(variable) => variable.constant,
+			None => self.interpreter.global_constants.contains(name.lexeme),
+		};
+		if constant {
+			self.errors.push(ResolveError::Custom(
+				name.clone(),
+				"Can't assign to a constant.".into(),
+			));
+		}
+	}
+
+	fn is_local(&self, name: &Token<'ast>) -> bool {
+		self.scopes
+			.iter()
+			.any(|scope| scope.contains_key(&*name.lexeme))
+	}
+
+	/// Every local variable that can be read here, by name, as `locals()`
+	/// lists them.
+	fn visible_locals(&self) -> VisibleLocals {
+		let mut shadowed = HashSet::new();
+		let mut visible: Vec<(Rc<str>, Slot)> = Vec::new();
+		for (depth, scope) in self.scopes.iter().rev().enumerate() {
+			for (name, variable) in scope {
+				if variable.defined && *name != "_" && shadowed.insert(*name) {
+					let index = variable.index;
+					visible.push(((*name).into(), Slot { depth, index }));
+				}
+			}
+		}
+		visible.sort_by(|(a, _), (b, _)| a.cmp(b));
+		visible.into()
+	}
+
+	/// Notes the locals visible at `token` if it is nearer to the cursor
+	/// than anything before: the first token that does not end before it.
+	fn visit_for_completion(&mut self, token: &Token<'ast>) {
+		let Some(cursor) = self.cursor else {
+			return;
+		};
+		let nearer = match &self.visible_at_cursor {
+			Some((offset, _)) => token.offset < *offset,
+			None => true,
+		};
+		if !nearer || token.offset + token.lexeme.len() < cursor {
+			return;
+		}
+
+		let mut shadowed = HashSet::new();
+		let mut visible = Vec::new();
+		for scope in self.scopes.iter().rev() {
+			for (name, variable) in scope {
+				if variable.defined && *name != "_" && shadowed.insert(*name) {
+					let kind = if variable.constant {
+						CompletionKind::Constant
+					} else {
+						CompletionKind::Local
+					};
+					visible.push(Completion {
+						name: (*name).into(),
+						kind,
+					});
+				}
+			}
+		}
+		self.visible_at_cursor = Some((token.offset, visible));
+	}
+
+	fn resolve_local(&mut self, id: ExprId, name: &Token<'ast>) {
+		for (depth, scope) in self.scopes.iter().rev().enumerate() {
+			if let Some(variable) = scope.get(name.lexeme) {
+				let index = variable.index;
+				self.interpreter.resolve(id, Slot { depth, index });
+				self.references.push(Reference {
+					name: name.span(),
+					declaration: variable.declaration,
+				});
 				return;
 			}
 		}
+		self.global_uses.push((name.lexeme, name.span()));
 	}
 }
+
+/// The value of a condition that is a `true` or `false` literal, possibly
+/// parenthesized.
+fn constant_condition(condition: &Expr) -> Option<bool> {
+	match condition {
+		Expr::Literal(Literal::Boolean(value)) => Some(*value),
+		Expr::Grouping(inner) => constant_condition(inner),
+		_ => None,
+	}
+}
+
+/// Whether `name` is the bare `_`, which takes a value only to drop it:
+/// it may be declared any number of times, but never read.
+fn is_discard(name: &Token) -> bool {
+	name.lexeme == "_"
+}
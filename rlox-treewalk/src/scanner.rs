@@ -1,53 +1,59 @@
-use crate::{literal::Literal, token::Token, token_type::TokenTy};
-
-static KEYWORDS: phf::Map<&'static str, TokenTy> = phf::phf_map! {
-	"and" =>    TokenTy::And,
-	"class" =>  TokenTy::Class,
-	"else" =>   TokenTy::Else,
-	"false" =>  TokenTy::False,
-	"for" =>    TokenTy::For,
-	"fun" =>    TokenTy::Fun,
-	"if" =>     TokenTy::If,
-	"nil" =>    TokenTy::Nil,
-	"or" =>     TokenTy::Or,
-	"print" =>  TokenTy::Print,
-	"return" => TokenTy::Return,
-	"super" =>  TokenTy::Super,
-	"this" =>   TokenTy::This,
-	"true" =>   TokenTy::True,
-	"var" =>    TokenTy::Var,
-	"while" =>  TokenTy::While,
+use std::{borrow::Cow, collections::HashSet, ops::Range};
+
+use bumpalo::Bump;
+use rlox_common::{conditional::Conditions, keyword::Keyword};
+
+use crate::{
+	token::{Span, Token},
+	token_type::TokenTy,
 };
 
 #[derive(Default)]
-pub struct Scanner {
-	source: String,
-	tokens: Vec<Token>,
+pub struct Scanner<'a> {
+	source: &'a str,
+	tokens: Vec<Token<'a>>,
 	start: usize,
 	current: usize,
 	line: usize,
+	conditions: Conditions,
 	pub errors: Vec<ScanError>,
 }
 
-impl Scanner {
-	pub fn new(source: String) -> Self {
+impl<'a> Scanner<'a> {
+	/// Scans `source`. Every token's lexeme is a slice of it, so to keep
+	/// them as long as a tree parsed from them, copy it into the arena
+	/// first.
+	pub fn new(source: &'a str) -> Self {
 		Self {
 			source,
 			..Default::default()
 		}
 	}
 
-	pub fn scan_tokens(mut self) -> Result<Vec<Token>> {
+	/// Sets the symbols `//#if` directives test for.
+	pub fn with_defined(mut self, defined: HashSet<String>) -> Self {
+		self.conditions = Conditions::new(defined);
+		self
+	}
+
+	/// Scans the whole source, even past errors. Each error leaves a
+	/// `TokenTy::Error` token in the stream where it happened, so the tokens
+	/// can still be parsed for further errors.
+	pub fn scan_tokens(mut self) -> (Vec<Token<'a>>, Option<ScanError>) {
 		while !self.is_at_end() {
 			self.start = self.current;
 			self.scan_token();
 		}
+		if let Err(message) = self.conditions.finish() {
+			self.start = self.current;
+			self.error(message);
+		}
 		self.tokens
-			.push(Token::new(TokenTy::Eof, String::new(), None, self.line));
+			.push(Token::new(TokenTy::Eof, "", self.line, self.current));
 		if self.errors.is_empty() {
-			Ok(self.tokens)
+			(self.tokens, None)
 		} else {
-			Err(ScanError::Multiple(self.errors))
+			(self.tokens, Some(ScanError::Multiple(self.errors)))
 		}
 	}
 
@@ -58,12 +64,26 @@ impl Scanner {
 			')' => self.add_token(TokenTy::RightParen),
 			'{' => self.add_token(TokenTy::LeftBrace),
 			'}' => self.add_token(TokenTy::RightBrace),
+			'[' => self.add_token(TokenTy::LeftBracket),
+			']' => self.add_token(TokenTy::RightBracket),
 			',' => self.add_token(TokenTy::Comma),
-			'.' => self.add_token(TokenTy::Dot),
+			'.' => {
+				if self.peek() == '.' && self.peek_next() == '.' {
+					self.current += 2;
+					self.add_token(TokenTy::DotDotDot);
+				} else {
+					self.add_token(TokenTy::Dot);
+				}
+			}
 			'-' => self.add_token(TokenTy::Minus),
 			'+' => self.add_token(TokenTy::Plus),
 			';' => self.add_token(TokenTy::Semicolon),
 			'*' => self.add_token(TokenTy::Star),
+			'%' => self.add_token(TokenTy::Percent),
+			'&' => self.add_token(TokenTy::Ampersand),
+			'|' => self.add_token(TokenTy::Pipe),
+			'^' => self.add_token(TokenTy::Caret),
+			'~' => self.add_token(TokenTy::Tilde),
 			'!' => {
 				let ty = if self.matches('=') {
 					TokenTy::BangEqual
@@ -83,6 +103,8 @@ impl Scanner {
 			'<' => {
 				let ty = if self.matches('=') {
 					TokenTy::LessEqual
+				} else if self.matches('<') {
+					TokenTy::LessLess
 				} else {
 					TokenTy::Less
 				};
@@ -91,6 +113,8 @@ impl Scanner {
 			'>' => {
 				let ty = if self.matches('=') {
 					TokenTy::GreaterEqual
+				} else if self.matches('>') {
+					TokenTy::GreaterGreater
 				} else {
 					TokenTy::Greater
 				};
@@ -101,6 +125,7 @@ impl Scanner {
 					while self.peek() != '\n' && !self.is_at_end() {
 						self.advance();
 					}
+					self.directive();
 				} else {
 					self.add_token(TokenTy::Slash);
 				}
@@ -116,22 +141,23 @@ impl Scanner {
 			ch if ch.is_ascii_digit() => {
 				self.number();
 			}
-			ch if ch.is_ascii_alphabetic() => {
-				while self.peek().is_ascii_alphanumeric() {
+			ch if ch.is_ascii_alphabetic() || ch == '_' => {
+				while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
 					self.advance();
 				}
 
-				let text = &self.source.as_bytes()[self.start..self.current];
-				let text = String::from_utf8_lossy(text);
-				if let Some(&ty) = KEYWORDS.get(&text) {
-					self.add_token(ty);
-				} else {
-					self.add_token(TokenTy::Identifier);
+				let ty =
+					Keyword::from_word(self.lexeme()).map_or(TokenTy::Identifier, TokenTy::from);
+				self.add_token(ty);
+			}
+			_ => {
+				// The scanner steps through bytes, so take in the rest of a
+				// character longer than one.
+				while !self.source.is_char_boundary(self.current) {
+					self.current += 1;
 				}
+				self.error("Unexpected character.");
 			}
-			_ => self
-				.errors
-				.push(ScanError::Custom(self.line, "Unexpected character.".into())),
 		}
 	}
 
@@ -148,10 +174,7 @@ impl Scanner {
 			}
 		}
 
-		let value = &self.source.as_bytes()[self.start..self.current];
-		let value = String::from_utf8_lossy(value);
-		let value: f64 = value.parse().unwrap();
-		self.add_literal(TokenTy::Number, Literal::Number(value));
+		self.add_token(TokenTy::Number);
 	}
 
 	fn peek_next(&self) -> char {
@@ -164,6 +187,13 @@ impl Scanner {
 
 	fn string(&mut self) {
 		while self.peek() != '"' && !self.is_at_end() {
+			// An escaped quote does not end the string.
+			if self.peek() == '\\' {
+				self.advance();
+				if self.is_at_end() {
+					break;
+				}
+			}
 			if self.peek() == '\n' {
 				self.line += 1;
 			}
@@ -171,17 +201,18 @@ impl Scanner {
 		}
 
 		if self.is_at_end() {
-			self.errors
-				.push(ScanError::Custom(self.line, "Unterminated string.".into()));
+			return self.error("Unterminated string.");
 		}
 
 		// closing "
 		self.advance();
 
-		// trim
-		let value = &self.source.as_bytes()[self.start + 1..self.current - 1];
-		let value = String::from_utf8_lossy(value).into_owned();
-		self.add_literal(TokenTy::String, Literal::String(value.into()));
+		// The parser unescapes the string again for its value, so here it
+		// is only checked.
+		match unescape(string_contents(self.lexeme())) {
+			Ok(_) => self.add_token(TokenTy::String),
+			Err(message) => self.error(message),
+		}
 	}
 
 	fn peek(&self) -> char {
@@ -207,20 +238,47 @@ impl Scanner {
 		ch
 	}
 
+	/// Applies the comment just scanned if it is a directive, then skips
+	/// any lines the directives leave out.
+	fn directive(&mut self) {
+		let line_start = self.source[..self.start]
+			.rfind('\n')
+			.map_or(0, |newline| newline + 1);
+		let mut line = line_start..self.current;
+		loop {
+			if let Err(message) = self.conditions.apply(&self.source[line.clone()]) {
+				self.start = line.start;
+				self.error(message);
+			}
+			if self.conditions.is_active() || self.is_at_end() {
+				return;
+			}
+			// Step over the newline, onto the next line.
+			self.current += 1;
+			self.line += 1;
+			let rest = &self.source[self.current..];
+			self.start = self.current;
+			self.current += rest.find('\n').unwrap_or(rest.len());
+			line = self.start..self.current;
+		}
+	}
+
 	#[inline]
 	fn add_token(&mut self, ty: TokenTy) {
-		self.add_token_or_literal(ty, None)
+		let lexeme = self.lexeme();
+		self.tokens
+			.push(Token::new(ty, lexeme, self.line, self.start))
 	}
 
-	#[inline]
-	fn add_literal(&mut self, ty: TokenTy, literal: Literal) {
-		self.add_token_or_literal(ty, Some(literal))
+	/// The text of the token being scanned.
+	fn lexeme(&self) -> &'a str {
+		&self.source[self.start..self.current]
 	}
 
-	fn add_token_or_literal(&mut self, ty: TokenTy, literal: Option<Literal>) {
-		let text = &self.source.as_bytes()[self.start..self.current];
-		let text = String::from_utf8_lossy(text).into_owned();
-		self.tokens.push(Token::new(ty, text, literal, self.line))
+	fn error(&mut self, message: &'static str) {
+		self.errors
+			.push(ScanError::Custom(self.line, message.into()));
+		self.add_token(TokenTy::Error);
 	}
 
 	#[inline]
@@ -229,9 +287,322 @@ impl Scanner {
 	}
 }
 
-type Result<T> = std::result::Result<T, ScanError>;
+/// The text of a string literal with its escapes replaced: `\n`, `\t`,
+/// `\"`, `\\` and `\u{...}`, which takes up to six hex digits naming a
+/// Unicode scalar value.
+pub fn unescape(raw: &str) -> Result<Cow<'_, str>, &'static str> {
+	if !raw.contains('\\') {
+		return Ok(Cow::Borrowed(raw));
+	}
+	let mut value = String::with_capacity(raw.len());
+	let mut chars = raw.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			value.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => value.push('\n'),
+			Some('t') => value.push('\t'),
+			Some('"') => value.push('"'),
+			Some('\\') => value.push('\\'),
+			Some('u') => {
+				let rest = chars.as_str();
+				let digits = rest
+					.strip_prefix('{')
+					.and_then(|rest| rest.split_once('}'))
+					.map(|(digits, _)| digits)
+					.filter(|digits| {
+						(1..=6).contains(&digits.len())
+							&& digits.bytes().all(|b| b.is_ascii_hexdigit())
+					})
+					.ok_or("Expect 1 to 6 hex digits in braces after '\\u'.")?;
+				let c = u32::from_str_radix(digits, 16)
+					.ok()
+					.and_then(char::from_u32)
+					.ok_or("Invalid Unicode code point in '\\u' escape.")?;
+				value.push(c);
+				chars = rest[digits.len() + 2..].chars();
+			}
+			_ => return Err("Invalid escape sequence."),
+		}
+	}
+	Ok(Cow::Owned(value))
+}
+
+/// A string literal whose value is `value`, with the escapes `unescape`
+/// takes for the characters that cannot be written as they are.
+pub fn escape(value: &str) -> String {
+	let mut literal = String::with_capacity(value.len() + 2);
+	literal.push('"');
+	for c in value.chars() {
+		match c {
+			'\n' => literal.push_str("\\n"),
+			'\t' => literal.push_str("\\t"),
+			'"' => literal.push_str("\\\""),
+			'\\' => literal.push_str("\\\\"),
+			c if c.is_control() => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+			c => literal.push(c),
+		}
+	}
+	literal.push('"');
+	literal
+}
+
+/// The text between the quotes of a string token.
+pub fn string_contents(lexeme: &str) -> &str {
+	&lexeme[1..lexeme.len() - 1]
+}
 
 pub enum ScanError {
-	Custom(usize, std::borrow::Cow<'static, str>),
+	Custom(usize, Cow<'static, str>),
 	Multiple(Vec<ScanError>),
 }
+
+/// How far past the end of a token the scanner may have looked to decide
+/// where the token ends, as with the `.` and digit after `1` in `1.5`.
+const LOOKAHEAD: usize = 2;
+
+/// Scans a buffer that changes by small edits, such as a file open in an
+/// editor. An edit is rescanned from the last token it could have
+/// changed, and only until the new tokens line up with the old ones after
+/// it again; the tokens in between are reused.
+///
+/// The tokens are always those `Scanner` would give for the whole buffer.
+/// While the buffer has `//#` directives in it, or just had, every edit
+/// rescans it all, as what a line scans to then depends on every
+/// directive above it.
+pub struct IncrementalScanner {
+	source: String,
+	defined: HashSet<String>,
+	/// The tokens of the buffer, `Eof` last.
+	tokens: Vec<Scanned>,
+}
+
+/// A token of the buffer, kept apart from the text it was scanned from,
+/// which later edits change.
+struct Scanned {
+	ty: TokenTy,
+	line: usize,
+	/// Where the token's lexeme is in the buffer.
+	span: Span,
+	/// Where the token is in the buffer.
+	range: Range<usize>,
+	/// What was wrong, for a `TokenTy::Error` token.
+	error: Option<Cow<'static, str>>,
+}
+
+impl IncrementalScanner {
+	/// Scans `source`, with `defined` the symbols `//#if` directives test
+	/// for.
+	pub fn new(source: String, defined: HashSet<String>) -> Self {
+		let mut scanner = Self {
+			source,
+			defined,
+			tokens: Vec::new(),
+		};
+		scanner.scan_from(0, 0, Vec::new(), 0);
+		scanner
+	}
+
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	/// Replaces the bytes of the buffer in `range` with `text`, and
+	/// rescans what that changed.
+	pub fn edit(&mut self, range: Range<usize>, text: &str) {
+		let had_directives = self.source.contains("//#");
+		self.source.replace_range(range.clone(), text);
+		if had_directives || self.source.contains("//#") {
+			self.tokens.clear();
+			return self.scan_from(0, 0, Vec::new(), 0);
+		}
+
+		let kept = self
+			.tokens
+			.partition_point(|scanned| scanned.range.end + LOOKAHEAD <= range.start);
+		let (from, line) = match kept.checked_sub(1).map(|last| &self.tokens[last]) {
+			Some(last) => (last.range.end, last.line),
+			None => (0, 0),
+		};
+		let moved = |offset: usize| offset - range.len() + text.len();
+		let after = self
+			.tokens
+			.split_off(kept)
+			.into_iter()
+			.filter(|scanned| scanned.range.start >= range.end)
+			.map(|mut scanned| {
+				scanned.range = moved(scanned.range.start)..moved(scanned.range.end);
+				scanned.span.offset = moved(scanned.span.offset);
+				scanned
+			})
+			.collect();
+		self.scan_from(from, line, after, range.start + text.len());
+	}
+
+	/// Scans from offset `from`, on `line`, to the end of the buffer, or
+	/// until a token starts at or past `unchanged` where one of `after`,
+	/// the old tokens from there on, did.
+	fn scan_from(&mut self, from: usize, line: usize, after: Vec<Scanned>, unchanged: usize) {
+		let scanner = &mut Scanner {
+			source: &self.source,
+			current: from,
+			line,
+			conditions: Conditions::new(self.defined.clone()),
+			..Default::default()
+		};
+		let mut after = after.into_iter().peekable();
+		while !scanner.is_at_end() {
+			scanner.start = scanner.current;
+			scanner.scan_token();
+			for scanned in Self::take_scanned(scanner) {
+				while after
+					.next_if(|old| old.range.start < scanned.range.start)
+					.is_some()
+				{}
+				let lined_up = scanned.range.start >= unchanged
+					&& matches!(after.peek(), Some(old) if old.range.start == scanned.range.start);
+				if lined_up {
+					// The rest of the buffer is the same text scanned from
+					// the same state as before, so it gives the same
+					// tokens, only maybe on other lines.
+					let (new_line, old_line) = (scanned.line, after.peek().unwrap().line);
+					self.tokens.extend(after.map(|mut old| {
+						old.line = old.line + new_line - old_line;
+						old
+					}));
+					return;
+				}
+				self.tokens.push(scanned);
+			}
+		}
+		if let Err(message) = scanner.conditions.finish() {
+			scanner.start = scanner.current;
+			scanner.error(message);
+		}
+		scanner.start = scanner.current;
+		scanner
+			.tokens
+			.push(Token::new(TokenTy::Eof, "", scanner.line, scanner.current));
+		self.tokens.extend(Self::take_scanned(scanner));
+	}
+
+	/// Takes the tokens `scanner` has added since last time, with the
+	/// errors for them.
+	fn take_scanned(scanner: &mut Scanner) -> Vec<Scanned> {
+		let mut errors = scanner.errors.drain(..);
+		let range = scanner.start..scanner.current;
+		scanner
+			.tokens
+			.drain(..)
+			.map(|token| Scanned {
+				// Each error added an error token, in the same order.
+				error: match token.ty {
+					TokenTy::Error => match errors.next() {
+						Some(ScanError::Custom(_, message)) => Some(message),
+						_ => None,
+					},
+					_ => None,
+				},
+				ty: token.ty,
+				line: token.line,
+				span: token.span(),
+				range: range.clone(),
+			})
+			.collect()
+	}
+
+	/// The type of every token of the buffer, in order.
+	pub fn types(&self) -> impl Iterator<Item = TokenTy> + '_ {
+		self.tokens.iter().map(|scanned| scanned.ty)
+	}
+
+	/// The tokens of the buffer and its errors, as `Scanner::scan_tokens`
+	/// gives them. Their lexemes borrow from a copy of the buffer made in
+	/// `arena`.
+	pub fn tokens<'a>(&self, arena: &'a Bump) -> (Vec<Token<'a>>, Option<ScanError>) {
+		let source = arena.alloc_str(&self.source);
+		let tokens = self
+			.tokens
+			.iter()
+			.map(|scanned| {
+				let lexeme = &source[scanned.span.range()];
+				Token::new(scanned.ty, lexeme, scanned.line, scanned.span.offset)
+			})
+			.collect();
+		let errors = self
+			.tokens
+			.iter()
+			.filter_map(|scanned| {
+				let message = scanned.error.clone()?;
+				Some(ScanError::Custom(scanned.line, message))
+			})
+			.collect::<Vec<_>>();
+		if errors.is_empty() {
+			(tokens, None)
+		} else {
+			(tokens, Some(ScanError::Multiple(errors)))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The type and lexeme of every token in `source`, without the end.
+	fn scan(source: &str) -> Vec<(TokenTy, String)> {
+		let (tokens, error) = Scanner::new(source).scan_tokens();
+		assert!(error.is_none(), "{source:?} did not scan");
+		tokens
+			.into_iter()
+			.filter(|token| token.ty != TokenTy::Eof)
+			.map(|token| (token.ty, token.lexeme.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn identifiers_with_underscores() {
+		for name in ["_x", "my_var", "a_1", "_"] {
+			assert_eq!(scan(name), [(TokenTy::Identifier, name.to_string())]);
+		}
+	}
+
+	#[test]
+	fn keywords_next_to_underscores() {
+		assert_eq!(
+			scan("var var_ _var"),
+			[
+				(TokenTy::Var, "var".to_string()),
+				(TokenTy::Identifier, "var_".to_string()),
+				(TokenTy::Identifier, "_var".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn comments_that_only_look_like_directives() {
+		assert_eq!(
+			scan("//### Section\n//#iffy\nx"),
+			[(TokenTy::Identifier, "x".to_string())]
+		);
+	}
+
+	#[test]
+	fn escape_undoes_unescape() {
+		for value in [
+			"",
+			"plain",
+			"say \"hi\"",
+			"a\\b",
+			"two\nlines\ttab",
+			"bell\u{7}",
+			"héllo",
+		] {
+			let literal = escape(value);
+			assert_eq!(scan(&literal), [(TokenTy::String, literal.clone())]);
+			assert_eq!(unescape(string_contents(&literal)).unwrap(), value);
+		}
+	}
+}
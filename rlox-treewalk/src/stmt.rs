@@ -1,33 +1,68 @@
-use std::rc::Rc;
-
 use crate::{expr::Expr, token::Token};
 
-pub struct StmtFunction {
-	pub name: Token,
-	pub params: Vec<Token>,
-	pub body: Vec<Stmt>,
+pub struct StmtFunction<'ast> {
+	pub name: Token<'ast>,
+	/// Declared with `fun*`: calling it returns a generator instead of
+	/// running the body.
+	pub generator: bool,
+	pub params: &'ast [Token<'ast>],
+	pub body: &'ast [Stmt<'ast>],
 }
 
-pub enum Stmt {
-	Expression(Expr),
-	Print(Expr),
+pub enum Stmt<'ast> {
+	Expression(Expr<'ast>),
+	Print {
+		keyword: Token<'ast>,
+		value: Expr<'ast>,
+	},
 	Var {
-		name: Token,
-		initializer: Option<Expr>,
+		name: Token<'ast>,
+		initializer: Option<Expr<'ast>>,
+		/// Declared with `const`, so it always has an initializer and can
+		/// never be assigned.
+		constant: bool,
+	},
+	/// `var (a, b) = value;`
+	VarTuple {
+		names: &'ast [Token<'ast>],
+		equals: Token<'ast>,
+		initializer: Expr<'ast>,
 	},
 	If {
-		condition: Expr,
-		then_branch: Box<Stmt>,
-		else_branch: Option<Box<Stmt>>,
+		keyword: Token<'ast>,
+		condition: Expr<'ast>,
+		then_branch: &'ast Stmt<'ast>,
+		else_branch: Option<&'ast Stmt<'ast>>,
 	},
 	While {
-		condition: Expr,
-		body: Box<Stmt>,
+		keyword: Token<'ast>,
+		condition: Expr<'ast>,
+		body: &'ast Stmt<'ast>,
 	},
-	Function(Rc<StmtFunction>),
+	/// `for (var name in iterable) body`
+	ForIn {
+		name: Token<'ast>,
+		keyword: Token<'ast>,
+		iterable: Expr<'ast>,
+		body: &'ast Stmt<'ast>,
+	},
+	Function(&'ast StmtFunction<'ast>),
 	Return {
-		keyword: Token,
-		value: Expr,
+		keyword: Token<'ast>,
+		value: Expr<'ast>,
+	},
+	Block(&'ast [Stmt<'ast>]),
+	/// Only valid inside a loop, which the resolver makes sure of.
+	Break {
+		keyword: Token<'ast>,
+	},
+	/// Only valid directly inside a generator's body.
+	Yield {
+		keyword: Token<'ast>,
+		value: Expr<'ast>,
 	},
-	Block(Vec<Stmt>),
+	/// A declaration that failed to parse, with the error at `token`, in
+	/// place of everything skipped to recover from it. Like `Expr::Error`,
+	/// never run.
+	Error(Token<'ast>),
 }
@@ -1,29 +1,56 @@
-use crate::{literal::Literal, token_type::TokenTy};
+use crate::token_type::TokenTy;
 
 #[derive(Debug, Clone)]
-pub struct Token {
+pub struct Token<'a> {
 	pub ty: TokenTy,
-	pub lexeme: String,
-	pub literal: Option<Literal>,
+	/// The token's text, borrowed from the source it was scanned from.
+	pub lexeme: &'a str,
 	pub line: usize,
+	/// Where the token starts in the source, in bytes.
+	pub offset: usize,
 }
 
-impl Token {
-	pub fn new(ty: TokenTy, lexeme: String, literal: Option<Literal>, line: usize) -> Self {
+impl<'a> Token<'a> {
+	pub fn new(ty: TokenTy, lexeme: &'a str, line: usize, offset: usize) -> Self {
 		Token {
 			ty,
 			lexeme,
-			literal,
 			line,
+			offset,
 		}
 	}
 }
 
-impl std::fmt::Display for Token {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		match &self.literal {
-			Some(literal) => f.write_fmt(format_args!("{:?} {} {}", self.ty, self.lexeme, literal)),
-			None => f.write_fmt(format_args!("{:?} {}", self.ty, self.lexeme)),
+impl Token<'_> {
+	pub fn span(&self) -> Span {
+		Span {
+			offset: self.offset,
+			length: self.lexeme.len(),
 		}
 	}
 }
+
+/// Where a token is in the source, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+	pub offset: usize,
+	pub length: usize,
+}
+
+impl Span {
+	pub fn range(&self) -> std::ops::Range<usize> {
+		self.offset..self.offset + self.length
+	}
+
+	/// Whether `offset` is in the span or just after it, where a cursor
+	/// that has just typed it would be.
+	pub fn touches(&self, offset: usize) -> bool {
+		(self.offset..=self.offset + self.length).contains(&offset)
+	}
+}
+
+impl std::fmt::Display for Token<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_fmt(format_args!("{:?} {}", self.ty, self.lexeme))
+	}
+}
@@ -1,3 +1,5 @@
+use rlox_common::keyword::Keyword;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenTy {
 	// single character
@@ -5,13 +7,21 @@ pub enum TokenTy {
 	RightParen,
 	LeftBrace,
 	RightBrace,
+	LeftBracket,
+	RightBracket,
 	Comma,
 	Dot,
+	DotDotDot,
 	Minus,
 	Plus,
 	Semicolon,
 	Slash,
 	Star,
+	Percent,
+	Ampersand,
+	Pipe,
+	Caret,
+	Tilde,
 
 	// one or two character
 	Bang,
@@ -22,6 +32,8 @@ pub enum TokenTy {
 	GreaterEqual,
 	Less,
 	LessEqual,
+	LessLess,
+	GreaterGreater,
 
 	// literals
 	Identifier,
@@ -30,12 +42,15 @@ pub enum TokenTy {
 
 	// keywords
 	And,
+	Break,
 	Class,
+	Const,
 	Else,
 	False,
 	Fun,
 	For,
 	If,
+	Is,
 	Nil,
 	Or,
 	Print,
@@ -45,6 +60,37 @@ pub enum TokenTy {
 	True,
 	Var,
 	While,
+	Yield,
 
+	/// Stands in for text the scanner could not make sense of, after
+	/// reporting it, so that parsing can carry on.
+	Error,
 	Eof,
 }
+
+impl From<Keyword> for TokenTy {
+	fn from(keyword: Keyword) -> Self {
+		match keyword {
+			Keyword::And => TokenTy::And,
+			Keyword::Break => TokenTy::Break,
+			Keyword::Class => TokenTy::Class,
+			Keyword::Const => TokenTy::Const,
+			Keyword::Else => TokenTy::Else,
+			Keyword::False => TokenTy::False,
+			Keyword::For => TokenTy::For,
+			Keyword::Fun => TokenTy::Fun,
+			Keyword::If => TokenTy::If,
+			Keyword::Is => TokenTy::Is,
+			Keyword::Nil => TokenTy::Nil,
+			Keyword::Or => TokenTy::Or,
+			Keyword::Print => TokenTy::Print,
+			Keyword::Return => TokenTy::Return,
+			Keyword::Super => TokenTy::Super,
+			Keyword::This => TokenTy::This,
+			Keyword::True => TokenTy::True,
+			Keyword::Var => TokenTy::Var,
+			Keyword::While => TokenTy::While,
+			Keyword::Yield => TokenTy::Yield,
+		}
+	}
+}
@@ -0,0 +1,214 @@
+//! The log `--trace-exec` writes: every statement executed, with its line
+//! and kind, and every call entered and left, with its arguments and
+//! result, indented by how deep in calls it happened:
+//!
+//! ```text
+//! [line 4] print
+//!   -> <fn fib>(2) [line 4]
+//!   [line 1] if
+//!   [line 2] return
+//!   <- <fn fib> returned 1
+//! ```
+//!
+//! Lines are counted as in runtime errors. So that a loop running for long
+//! does not fill the disk, each statement and call site is only logged its
+//! first `SITE_LIMIT` times, and values are cut short past `VALUE_LIMIT`
+//! characters.
+
+use std::{
+	collections::HashMap,
+	fmt::{Debug, Display},
+	fs::File,
+	io::{BufWriter, Write},
+	path::Path,
+};
+
+use crate::{expr::Expr, interpreter, object::Object, stmt::Stmt, token::Token};
+
+/// How many times a statement or call site is logged.
+const SITE_LIMIT: usize = 1000;
+/// How many characters of a value are logged.
+const VALUE_LIMIT: usize = 80;
+/// How many levels of calls are shown by indenting; deeper calls are
+/// indented as much as these.
+const INDENT_LIMIT: usize = 40;
+
+pub struct Tracer {
+	/// `None` once writing the log failed.
+	output: Option<BufWriter<File>>,
+	/// How many calls are running.
+	depth: usize,
+	/// How many times each statement or call site was reached, by address.
+	reached: HashMap<usize, usize>,
+	/// The line of the last statement logged, for statements that have no
+	/// token to tell their own.
+	line: usize,
+}
+
+impl Tracer {
+	/// Starts a log at `path`, replacing whatever was there.
+	pub fn create(path: &Path) -> std::io::Result<Self> {
+		Ok(Self {
+			output: Some(BufWriter::new(File::create(path)?)),
+			depth: 0,
+			reached: HashMap::new(),
+			line: 0,
+		})
+	}
+
+	/// Logs that `statement` is about to run.
+	pub fn statement(&mut self, statement: &Stmt) {
+		if let Some(line) = statement_line(statement) {
+			self.line = line;
+		}
+		let kind = match statement {
+			Stmt::Expression(_) => "expression",
+			Stmt::Print { .. } => "print",
+			Stmt::Var { constant: true, .. } => "const",
+			Stmt::Var { .. } | Stmt::VarTuple { .. } => "var",
+			Stmt::If { .. } => "if",
+			Stmt::While { .. } => "while",
+			Stmt::ForIn { .. } => "for",
+			Stmt::Function(_) => "fun",
+			Stmt::Return { .. } => "return",
+			Stmt::Block(_) => "block",
+			Stmt::Break { .. } => "break",
+			Stmt::Yield { .. } => "yield",
+			Stmt::Error(_) => "error",
+		};
+		let entry = format!("[line {}] {kind}", self.line);
+		if self.reach(statement as *const Stmt as usize, &entry) {
+			self.write(&entry);
+		}
+	}
+
+	/// Logs that `function` is called with `arguments` at `paren`. Returns
+	/// whether it was, for `exit` to log the call's end as well.
+	pub fn enter(&mut self, paren: &Token, function: &dyn Debug, arguments: &[Object]) -> bool {
+		let arguments = arguments.iter().map(truncate).collect::<Vec<_>>();
+		let entry = format!(
+			"-> {function:?}({}) [line {}]",
+			arguments.join(", "),
+			paren.line
+		);
+		let logged = self.reach(paren as *const Token as usize, &entry);
+		if logged {
+			self.write(&entry);
+		}
+		self.depth += 1;
+		logged
+	}
+
+	/// Logs that the call to `function` ended with `result`, if its start
+	/// was logged.
+	pub fn exit(
+		&mut self,
+		function: &dyn Debug,
+		result: &interpreter::Result<Object>,
+		logged: bool,
+	) {
+		self.depth -= 1;
+		if logged {
+			let result = match result {
+				Ok(value) => format!("returned {}", truncate(value)),
+				Err(_) => "failed".to_string(),
+			};
+			self.write(&format!("<- {function:?} {result}"));
+		}
+		if self.depth == 0 {
+			self.flush();
+		}
+	}
+
+	/// Writes out whatever is logged so far.
+	pub fn flush(&mut self) {
+		if let Some(output) = &mut self.output {
+			if let Err(err) = output.flush() {
+				self.fail(err);
+			}
+		}
+	}
+
+	/// Counts that the site at `address` was reached again, noting in the
+	/// log when it stops being logged. Returns whether to log it this time.
+	fn reach(&mut self, address: usize, entry: &str) -> bool {
+		let reached = self.reached.entry(address).or_default();
+		*reached += 1;
+		let reached = *reached;
+		if reached == SITE_LIMIT + 1 {
+			self.write(&format!(
+				"{entry}: reached {SITE_LIMIT} times, no longer logged"
+			));
+		}
+		reached <= SITE_LIMIT
+	}
+
+	fn write(&mut self, entry: &str) {
+		let Some(output) = &mut self.output else {
+			return;
+		};
+		let indent = 2 * self.depth.min(INDENT_LIMIT);
+		if let Err(err) = writeln!(output, "{:indent$}{entry}", "") {
+			self.fail(err);
+		}
+	}
+
+	fn fail(&mut self, err: std::io::Error) {
+		eprintln!("Could not write the execution trace, stopped tracing: {err}");
+		self.output = None;
+	}
+}
+
+/// `value` as printed, cut short if it is long.
+fn truncate(value: &impl Display) -> String {
+	let value = value.to_string();
+	match value.char_indices().nth(VALUE_LIMIT) {
+		Some((end, _)) => format!("{}...", &value[..end]),
+		None => value,
+	}
+}
+
+fn statement_line(statement: &Stmt) -> Option<usize> {
+	match statement {
+		Stmt::Expression(expr) => expression_line(expr),
+		Stmt::Var { name, .. } | Stmt::ForIn { name, .. } => Some(name.line),
+		Stmt::VarTuple { equals, .. } => Some(equals.line),
+		Stmt::If { keyword, .. }
+		| Stmt::While { keyword, .. }
+		| Stmt::Print { keyword, .. }
+		| Stmt::Return { keyword, .. }
+		| Stmt::Break { keyword }
+		| Stmt::Yield { keyword, .. } => Some(keyword.line),
+		Stmt::Function(function) => Some(function.name.line),
+		Stmt::Block(statements) => statements.first().and_then(statement_line),
+		Stmt::Error(token) => Some(token.line),
+	}
+}
+
+/// The line of the first token of `expr` that has one; literals and empty
+/// tuples do not.
+fn expression_line(expr: &Expr) -> Option<usize> {
+	match expr {
+		Expr::Binary { left, operator, .. } | Expr::Logical { left, operator, .. } => {
+			expression_line(left).or(Some(operator.line))
+		}
+		Expr::Call { callee, paren, .. } => expression_line(callee).or(Some(paren.line)),
+		Expr::Get { object, name } => expression_line(object).or(Some(name.line)),
+		Expr::Grouping(expr) => expression_line(expr),
+		Expr::Index {
+			object, bracket, ..
+		} => expression_line(object).or(Some(bracket.line)),
+		Expr::Spread { ellipsis, .. } => Some(ellipsis.line),
+		Expr::Literal(_) | Expr::String(_) => None,
+		Expr::Comparison {
+			operands,
+			operators,
+		} => (operands.first().and_then(expression_line))
+			.or_else(|| operators.first().map(|operator| operator.line)),
+		Expr::Unary { operator, .. } => Some(operator.line),
+		Expr::Variable { name, .. } | Expr::Assign { name, .. } => Some(name.line),
+		Expr::Tuple(elements) => elements.iter().find_map(expression_line),
+		Expr::AssignTuple { equals, .. } => Some(equals.line),
+		Expr::Error(token) => Some(token.line),
+	}
+}